@@ -0,0 +1,310 @@
+use anyhow::{bail, Result};
+
+use crate::geometry::{Point, Rect, Subpixels};
+
+/*
+ * Helpers for reading and writing the compact binary formats used by replays and save states.
+ *
+ * All fixed-size values are little-endian. Unsigned integers can also be written as
+ * LEB128 varints, which keeps small values like frame deltas down to a byte or two.
+ * Signed integers are zigzag-encoded first, so small negative numbers stay small too.
+ */
+pub struct BinaryWriter {
+    bytes: Vec<u8>,
+}
+
+impl BinaryWriter {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> BinaryWriter {
+        BinaryWriter { bytes: Vec::new() }
+    }
+
+    // Everything written so far.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn write_u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    pub fn write_u16(&mut self, n: u16) {
+        self.write_bytes(&n.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, n: u64) {
+        self.write_bytes(&n.to_le_bytes());
+    }
+
+    pub fn write_varint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.write_u8(byte);
+                return;
+            }
+            self.write_u8(byte | 0x80);
+        }
+    }
+
+    pub fn write_i32(&mut self, n: i32) {
+        self.write_varint(((n << 1) ^ (n >> 31)) as u32 as u64);
+    }
+
+    pub fn write_bool(&mut self, b: bool) {
+        self.write_u8(b as u8);
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        self.write_varint(s.len() as u64);
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn write_subpixels(&mut self, n: Subpixels) {
+        self.write_i32(n / Subpixels::new(1));
+    }
+
+    pub fn write_point(&mut self, point: Point<Subpixels>) {
+        self.write_subpixels(point.x);
+        self.write_subpixels(point.y);
+    }
+
+    // An optional index into a list, written as 0 for None, or one more than the index.
+    pub fn write_index(&mut self, index: Option<usize>) {
+        self.write_varint(index.map(|i| i as u64 + 1).unwrap_or(0));
+    }
+
+    pub fn write_rect(&mut self, rect: Rect<Subpixels>) {
+        self.write_subpixels(rect.x);
+        self.write_subpixels(rect.y);
+        self.write_subpixels(rect.w);
+        self.write_subpixels(rect.h);
+    }
+}
+
+pub struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> BinaryReader<'a> {
+        BinaryReader { bytes, position: 0 }
+    }
+
+    // Everything that hasn't been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.position < n {
+            bail!(
+                "unexpected end of data: wanted {} bytes at offset {}",
+                n,
+                self.position
+            );
+        }
+        let bytes = &self.bytes[self.position..self.position + n];
+        self.position += n;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift >= 64 {
+                bail!("varint is too long at offset {}", self.position);
+            }
+            result |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32> {
+        let n = self.read_varint()?;
+        if n > u32::MAX as u64 {
+            bail!("integer is out of range at offset {}", self.position);
+        }
+        let n = n as u32;
+        Ok(((n >> 1) as i32) ^ -((n & 1) as i32))
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            n => bail!("invalid bool {} at offset {}", n, self.position),
+        }
+    }
+
+    pub fn read_string(&mut self) -> Result<String> {
+        let len = self.read_varint()? as usize;
+        let bytes = self.read_bytes(len)?;
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+
+    pub fn read_subpixels(&mut self) -> Result<Subpixels> {
+        Ok(Subpixels::new(self.read_i32()?))
+    }
+
+    pub fn read_point(&mut self) -> Result<Point<Subpixels>> {
+        let x = self.read_subpixels()?;
+        let y = self.read_subpixels()?;
+        Ok(Point::new(x, y))
+    }
+
+    // Reads an index written with write_index, which has to be in a list of len items.
+    pub fn read_index(&mut self, len: usize) -> Result<Option<usize>> {
+        let index = self.read_varint()? as usize;
+        if index > len {
+            bail!("index {} is out of range for {} items", index - 1, len);
+        }
+        Ok(index.checked_sub(1))
+    }
+
+    pub fn read_rect(&mut self) -> Result<Rect<Subpixels>> {
+        let x = self.read_subpixels()?;
+        let y = self.read_subpixels()?;
+        let w = self.read_subpixels()?;
+        let h = self.read_subpixels()?;
+        Ok(Rect { x, y, w, h })
+    }
+}
+
+/*
+ * A 64-bit FNV-1a hasher.
+ *
+ * Unlike the standard library's hashers, its output is specified and stable, so it's safe
+ * to store the results in files and compare them across builds. It doesn't implement
+ * std::hash::Hasher on purpose, since Hash writes lengths and enum tags as usize, which
+ * differs between platforms. Every value here is written with a fixed width, little-endian.
+ */
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u8(&mut self, n: u8) {
+        self.write(&[n]);
+    }
+
+    pub fn write_bool(&mut self, b: bool) {
+        self.write_u8(b as u8);
+    }
+
+    pub fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, n: i32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_u32(s.len() as u32);
+        self.write(s.as_bytes());
+    }
+
+    pub fn write_subpixels(&mut self, n: Subpixels) {
+        self.write_i32(n / Subpixels::new(1));
+    }
+
+    pub fn write_point(&mut self, point: Point<Subpixels>) {
+        self.write_subpixels(point.x);
+        self.write_subpixels(point.y);
+    }
+
+    pub fn write_rect(&mut self, rect: Rect<Subpixels>) {
+        self.write_subpixels(rect.x);
+        self.write_subpixels(rect.y);
+        self.write_subpixels(rect.w);
+        self.write_subpixels(rect.h);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_fixed_width() {
+        // The usual test vector for 64-bit FNV-1a.
+        let mut hasher = Fnv1a::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        // Numbers are always written as 4 little-endian bytes, on any platform.
+        let mut number = Fnv1a::new();
+        number.write_i32(-2);
+        let mut bytes = Fnv1a::new();
+        bytes.write(&[0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(number.finish(), bytes.finish());
+    }
+
+    #[test]
+    fn signed_round_trip() {
+        let values = [0, 1, -1, 63, -64, 64, 1000, -1000, i32::MAX, i32::MIN];
+        let mut writer = BinaryWriter::new();
+        for n in values {
+            writer.write_i32(n);
+        }
+        let bytes = writer.into_bytes();
+        // Small values of either sign fit in a single byte.
+        assert_eq!(bytes[..5], [0, 2, 1, 126, 127]);
+
+        let mut reader = BinaryReader::new(&bytes);
+        for n in values {
+            assert_eq!(reader.read_i32().unwrap(), n);
+        }
+        assert!(reader.remaining().is_empty());
+    }
+}
@@ -0,0 +1,458 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::filemanager::FileManager;
+use crate::inputmanager::JoystickButton;
+use crate::keyboard::KeyboardKey;
+
+// How far a stick has to be pushed for an action, unless its bindings say otherwise.
+const DEFAULT_THRESHOLD: f32 = 0.5;
+
+/*
+ * The default bindings, in the same format as a bindings file.
+ *
+ * Each line is an action, followed by everything that triggers it. Keys are named like
+ * KeyboardKey, buttons like JoystickButton, and stick directions are for the left stick.
+ * A line can end with threshold=N, to set how far the stick has to be pushed. Saving,
+ * loading, and rewinding are left unbound, so they're only there for players who ask.
+ */
+const DEFAULT_BINDINGS: &str = "
+ok          key:Enter button:South
+cancel      key:Escape button:West
+left        key:Left key:A button:DPadLeft stick:Left
+right       key:Right key:D button:DPadRight stick:Right
+up          key:Up key:W button:DPadUp stick:Up
+down        key:Down key:S button:DPadDown stick:Down
+jump        key:Space key:W key:Up button:South
+";
+
+// Something the player can do, which can be bound to any number of inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Ok = 0,
+    Cancel,
+    Left,
+    Right,
+    Up,
+    Down,
+    Jump,
+    SaveState,
+    LoadState,
+    Rewind,
+}
+
+impl Action {
+    pub const ALL: [Action; 10] = [
+        Action::Ok,
+        Action::Cancel,
+        Action::Left,
+        Action::Right,
+        Action::Up,
+        Action::Down,
+        Action::Jump,
+        Action::SaveState,
+        Action::LoadState,
+        Action::Rewind,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Ok => "ok",
+            Action::Cancel => "cancel",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Jump => "jump",
+            Action::SaveState => "save_state",
+            Action::LoadState => "load_state",
+            Action::Rewind => "rewind",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Action::ALL
+            .iter()
+            .find(|action| action.name() == name)
+            .copied()
+    }
+
+    // Actions used in menus can share inputs with actions only used in levels, and vice versa.
+    fn in_menus(&self) -> bool {
+        matches!(
+            self,
+            Action::Ok | Action::Cancel | Action::Left | Action::Right | Action::Up | Action::Down
+        )
+    }
+
+    // Up only climbs ladders, where sharing an input with jump is expected.
+    fn in_levels(&self) -> bool {
+        !matches!(self, Action::Ok | Action::Up)
+    }
+
+    fn can_share_inputs(&self, other: Action) -> bool {
+        let both_in_menus = self.in_menus() && other.in_menus();
+        let both_in_levels = self.in_levels() && other.in_levels();
+        !both_in_menus && !both_in_levels
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StickDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl StickDirection {
+    const ALL: [StickDirection; 4] = [
+        StickDirection::Up,
+        StickDirection::Down,
+        StickDirection::Left,
+        StickDirection::Right,
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        StickDirection::ALL
+            .iter()
+            .find(|direction| format!("{:?}", direction).eq_ignore_ascii_case(name))
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyboardKey),
+    Button(JoystickButton),
+    Stick(StickDirection),
+}
+
+impl Binding {
+    fn parse(s: &str) -> Result<Binding> {
+        let (kind, name) = s
+            .split_once(':')
+            .with_context(|| format!("invalid binding: {}", s))?;
+        Ok(match kind {
+            "key" => Binding::Key(
+                KeyboardKey::from_name(name).with_context(|| format!("unknown key: {}", name))?,
+            ),
+            "button" => Binding::Button(
+                JoystickButton::from_name(name)
+                    .with_context(|| format!("unknown button: {}", name))?,
+            ),
+            "stick" => Binding::Stick(
+                StickDirection::from_name(name)
+                    .with_context(|| format!("unknown stick direction: {}", name))?,
+            ),
+            _ => bail!("unknown binding type: {}", kind),
+        })
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("key:{}", key.name()),
+            Binding::Button(button) => format!("button:{}", button.name()),
+            Binding::Stick(direction) => format!("stick:{:?}", direction),
+        }
+    }
+
+    // A short name to show to players, e.g. "SPACE" or "PAD SOUTH".
+    pub fn label(&self) -> String {
+        match self {
+            Binding::Key(key) => key.name().to_uppercase(),
+            Binding::Button(button) => format!("PAD {}", button.name().to_uppercase()),
+            Binding::Stick(direction) => format!("STICK {:?}", direction).to_uppercase(),
+        }
+    }
+
+    // Packs the binding into 14 bits for input snapshots. The top 2 bits are the type.
+    pub fn code(&self) -> u16 {
+        match self {
+            Binding::Key(key) => (1 << 12) | *key as u16,
+            Binding::Button(button) => (2 << 12) | *button as u16,
+            Binding::Stick(direction) => (3 << 12) | *direction as u16,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Binding> {
+        let index = (code & 0x0FFF) as usize;
+        match (code >> 12) & 0x3 {
+            1 => KeyboardKey::ALL.get(index).copied().map(Binding::Key),
+            2 => JoystickButton::ALL.get(index).copied().map(Binding::Button),
+            3 => StickDirection::ALL.get(index).copied().map(Binding::Stick),
+            _ => None,
+        }
+    }
+
+    fn same_type(&self, other: &Binding) -> bool {
+        matches!(
+            (self, other),
+            (Binding::Key(_), Binding::Key(_))
+                | (Binding::Button(_), Binding::Button(_))
+                | (Binding::Stick(_), Binding::Stick(_))
+        )
+    }
+}
+
+// Two actions that would both be triggered by the same input at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict {
+    pub first: Action,
+    pub second: Action,
+    pub binding: Binding,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionBindings {
+    pub inputs: Vec<Binding>,
+    // How far, from 0 to 1, a stick has to be pushed to count. Anything less is dead zone.
+    pub threshold: f32,
+}
+
+// Which inputs trigger each action.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bindings {
+    // Indexed by Action.
+    actions: Vec<ActionBindings>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let empty = Bindings {
+            actions: vec![
+                ActionBindings {
+                    inputs: Vec::new(),
+                    threshold: DEFAULT_THRESHOLD,
+                };
+                Action::ALL.len()
+            ],
+        };
+        empty
+            .parse(DEFAULT_BINDINGS)
+            .expect("default bindings should be valid")
+    }
+}
+
+impl Bindings {
+    // Loads a bindings file. Any actions it doesn't mention keep their default bindings.
+    pub fn load(path: &Path, files: &FileManager) -> Result<Bindings> {
+        let text = files.read_to_string(path)?;
+        Bindings::default()
+            .parse(&text)
+            .map_err(|e| anyhow!("unable to read bindings from {:?}: {}", path, e))
+    }
+
+    // Returns a copy of these bindings, with any actions in the text replaced.
+    pub fn parse(&self, text: &str) -> Result<Bindings> {
+        let mut bindings = self.clone();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let mut parts = line.split_whitespace();
+            let Some(action) = parts.next() else {
+                continue;
+            };
+            let action =
+                Action::from_name(action).with_context(|| format!("unknown action: {}", action))?;
+            let mut action_bindings = ActionBindings {
+                inputs: Vec::new(),
+                threshold: DEFAULT_THRESHOLD,
+            };
+            for part in parts {
+                if let Some(threshold) = part.strip_prefix("threshold=") {
+                    let threshold: f32 = threshold
+                        .parse()
+                        .with_context(|| format!("invalid threshold: {}", threshold))?;
+                    if !(0.0..1.0).contains(&threshold) {
+                        bail!(
+                            "threshold must be at least 0 and less than 1: {}",
+                            threshold
+                        );
+                    }
+                    action_bindings.threshold = threshold;
+                } else {
+                    action_bindings.inputs.push(Binding::parse(part)?);
+                }
+            }
+            bindings.actions[action as usize] = action_bindings;
+        }
+        Ok(bindings)
+    }
+
+    // Writes all of the bindings in the format that load reads.
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        for action in Action::ALL {
+            let action_bindings = self.get(action);
+            text.push_str(action.name());
+            for input in action_bindings.inputs.iter() {
+                text.push(' ');
+                text.push_str(&input.encode());
+            }
+            if action_bindings.threshold != DEFAULT_THRESHOLD {
+                text.push_str(&format!(" threshold={}", action_bindings.threshold));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn get(&self, action: Action) -> &ActionBindings {
+        &self.actions[action as usize]
+    }
+
+    /*
+     * Binds an action to a single key or button, e.g. from a controls menu.
+     *
+     * It replaces the action's other bindings of the same type, so that rebinding the
+     * keyboard doesn't lose the gamepad bindings, and rebinding buttons keeps the stick.
+     */
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let inputs = &mut self.actions[action as usize].inputs;
+        inputs.retain(|input| !input.same_type(&binding));
+        inputs.push(binding);
+    }
+
+    // Every pair of actions that share an input, and are used at the same time.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for (i, &first) in Action::ALL.iter().enumerate() {
+            for &second in Action::ALL[i + 1..].iter() {
+                if first.can_share_inputs(second) {
+                    continue;
+                }
+                for binding in self.get(first).inputs.iter() {
+                    if self.get(second).inputs.contains(binding) {
+                        conflicts.push(Conflict {
+                            first,
+                            second,
+                            binding: *binding,
+                        });
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_round_trip() {
+        let bindings = Bindings::default();
+        assert_eq!(
+            bindings.get(Action::Jump).inputs,
+            vec![
+                Binding::Key(KeyboardKey::Space),
+                Binding::Key(KeyboardKey::W),
+                Binding::Key(KeyboardKey::Up),
+                Binding::Button(JoystickButton::South),
+            ]
+        );
+        // Saving, loading, and rewinding are only there for players who bind them.
+        for action in [Action::SaveState, Action::LoadState, Action::Rewind] {
+            assert_eq!(bindings.get(action).inputs, vec![]);
+        }
+        assert!(bindings.encode().contains("\nrewind\n"));
+        let parsed = Bindings::default().parse(&bindings.encode()).unwrap();
+        assert_eq!(parsed, bindings);
+
+        let text = "save_state key:F5\nload_state key:F9\nrewind key:R button:LeftTrigger\n";
+        let bindings = Bindings::default().parse(text).unwrap();
+        assert_eq!(
+            bindings.get(Action::Rewind).inputs,
+            vec![
+                Binding::Key(KeyboardKey::R),
+                Binding::Button(JoystickButton::LeftTrigger)
+            ]
+        );
+        assert_eq!(bindings.conflicts(), vec![]);
+    }
+
+    #[test]
+    fn overrides_only_listed_actions() {
+        let text = "# AZERTY\nleft key:Q stick:left threshold=0.25\nup key:Z   # comment\n";
+        let bindings = Bindings::default().parse(text).unwrap();
+        let left = bindings.get(Action::Left);
+        assert_eq!(
+            left.inputs,
+            vec![
+                Binding::Key(KeyboardKey::Q),
+                Binding::Stick(StickDirection::Left)
+            ]
+        );
+        assert_eq!(left.threshold, 0.25);
+        assert_eq!(
+            bindings.get(Action::Up).inputs,
+            vec![Binding::Key(KeyboardKey::Z)]
+        );
+        assert_eq!(
+            bindings.get(Action::Right),
+            Bindings::default().get(Action::Right)
+        );
+        assert!(bindings
+            .encode()
+            .contains("left key:Q stick:Left threshold=0.25\n"));
+
+        assert!(Bindings::default().parse("fly key:F").is_err());
+        assert!(Bindings::default().parse("left key:Nope").is_err());
+        assert!(Bindings::default().parse("left mouse:Left").is_err());
+        assert!(Bindings::default().parse("left threshold=2").is_err());
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        let bindings = [
+            Binding::Key(KeyboardKey::Escape),
+            Binding::Key(KeyboardKey::KeypadEnter),
+            Binding::Button(JoystickButton::RightThumb),
+            Binding::Stick(StickDirection::Right),
+        ];
+        for binding in bindings {
+            assert_eq!(Binding::from_code(binding.code()), Some(binding));
+        }
+        assert_eq!(Binding::from_code(0), None);
+        assert_eq!(Binding::from_code((1 << 12) | 999), None);
+    }
+
+    #[test]
+    fn rebinding_finds_conflicts() {
+        let mut bindings = Bindings::default();
+        assert_eq!(bindings.conflicts(), vec![]);
+
+        // The keyboard bindings are replaced, but not the gamepad ones.
+        bindings.rebind(Action::Jump, Binding::Key(KeyboardKey::K));
+        assert_eq!(
+            bindings.get(Action::Jump).inputs,
+            vec![
+                Binding::Button(JoystickButton::South),
+                Binding::Key(KeyboardKey::K)
+            ]
+        );
+        assert_eq!(bindings.conflicts(), vec![]);
+
+        // Jump and up are never used at the same time, but jump and left are.
+        bindings.rebind(Action::Up, Binding::Key(KeyboardKey::K));
+        assert_eq!(bindings.conflicts(), vec![]);
+        bindings.rebind(Action::Left, Binding::Key(KeyboardKey::K));
+        assert_eq!(
+            bindings.conflicts(),
+            vec![
+                Conflict {
+                    first: Action::Left,
+                    second: Action::Up,
+                    binding: Binding::Key(KeyboardKey::K),
+                },
+                Conflict {
+                    first: Action::Left,
+                    second: Action::Jump,
+                    binding: Binding::Key(KeyboardKey::K),
+                },
+            ]
+        );
+    }
+}
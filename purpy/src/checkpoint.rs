@@ -0,0 +1,31 @@
+use anyhow::Result;
+
+use crate::{
+    geometry::{Point, Rect, Subpixels},
+    tilemap::MapObject,
+};
+
+pub struct Checkpoint {
+    position: Rect<Subpixels>,
+}
+
+impl Checkpoint {
+    pub fn new(obj: &MapObject) -> Result<Self> {
+        Ok(Self {
+            position: obj.position.into(),
+        })
+    }
+
+    pub fn is_inside(&self, player_rect: Rect<Subpixels>) -> bool {
+        player_rect.intersects(self.position)
+    }
+
+    // Where to put the 24x24 player so that they're standing in the middle of the checkpoint.
+    pub fn respawn_position(&self) -> Point<Subpixels> {
+        let size = Subpixels::from_pixels(24);
+        Point::new(
+            self.position.x + (self.position.w - size) / 2,
+            self.position.bottom() - size,
+        )
+    }
+}
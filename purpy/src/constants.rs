@@ -1,10 +1,35 @@
-use crate::geometry::Subpixels;
+use crate::geometry::{Pixels, Subpixels};
+use crate::utils::Color;
 
 // Basic window and render size.
 pub const RENDER_WIDTH: u32 = 320;
 pub const RENDER_HEIGHT: u32 = 180;
 pub const FRAME_RATE: u32 = 60;
 
+// Where the game starts, in the same form as a menu button action.
+pub const START_SCENE: &str = "menu:assets/menus/start.tmx";
+
+// How often recordings save a hash of the game state, to check for desyncs during playback.
+pub const STATE_HASH_INTERVAL: u64 = 60;
+
+// When holding a direction in a menu, how many frames until it starts repeating, and how
+// many frames between each repeat after that.
+pub const MENU_REPEAT_DELAY: u32 = 24;
+pub const MENU_REPEAT_INTERVAL: u32 = 6;
+
+// How much memory levels can use to remember their recent states for rewinding, by default.
+// Only the bytes that change each frame are kept, which is about 60 bytes with the player and
+// seven other things moving, so this covers several minutes.
+pub const REWIND_BUDGET: usize = 1024 * 1024;
+
+// How ghosts from a previous run are drawn.
+pub const GHOST_TINT: Color = Color {
+    r: 160,
+    g: 200,
+    b: 255,
+    a: 112,
+};
+
 // Rendering details.
 pub const MAX_LIGHTS: usize = 32;
 
@@ -36,6 +61,20 @@ pub const WALL_JUMP_VERTICAL_SPEED: Subpixels = Subpixels::from_pixels(3);
 pub const WALL_STICK_TIME: i32 = 3;
 pub const WALL_SLIDE_TIME: i32 = 60;
 
+// Ladders.
+pub const CLIMB_SPEED: Subpixels = Subpixels::from_pixels(1);
+
+// Swimming.
+pub const SWIM_FALL_ACCELERATION: Subpixels = Subpixels::new(2);
+pub const SWIM_MAX_GRAVITY: Subpixels = Subpixels::new(16);
+pub const SWIM_STROKE_SPEED: Subpixels = Subpixels::new(48);
+pub const WATER_COLOR: Color = Color {
+    r: 48,
+    g: 96,
+    b: 224,
+    a: 96,
+};
+
 // Player appearance.
 pub const IDLE_TIME: i32 = 240; // How long before showing idle animation.
 pub const PLAYER_FRAMES_PER_FRAME: i32 = 4; // How fast to animate the player.
@@ -64,6 +103,22 @@ pub const SPRING_BOUNCE_VELOCITY: Subpixels = JUMP_INITIAL_SPEED;
 pub const SPRING_JUMP_DURATION: i32 = 10; // How long to jump when jumping from spring.
 pub const SPRING_JUMP_VELOCITY: Subpixels = Subpixels::new(156);
 
+// Enemies.
+pub const ENEMY_SPEED: Pixels = Pixels::new(8); // In the same units as platform speeds.
+pub const ENEMY_FRAMES_PER_FRAME: i32 = 6; // How fast to animate enemies.
+pub const ENEMY_STOMPED_TIME: i32 = 30; // How long a stomped enemy stays on screen.
+
+// Turrets and what they shoot.
+pub const TURRET_INTERVAL: i32 = 90; // How many frames between shots.
+pub const BULLET_SPEED: Pixels = Pixels::new(32); // In the same units as platform speeds.
+pub const BULLET_SIZE: Subpixels = Subpixels::from_pixels(4);
+pub const BULLET_COLOR: Color = Color {
+    r: 255,
+    g: 96,
+    b: 64,
+    a: 255,
+};
+
 // Doors.
 pub const DOOR_SPEED: u32 = 3;
 pub const DOOR_CLOSING_FRAMES: u32 = 9; // The should match the door animation frames.
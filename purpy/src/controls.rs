@@ -0,0 +1,310 @@
+use std::path::Path;
+
+use anyhow::Result;
+use num_traits::Zero;
+
+use crate::bindings::{Action, Binding, Bindings};
+use crate::cursor::Cursor;
+use crate::filemanager::FileManager;
+use crate::font::Font;
+use crate::geometry::{Pixels, Point, Rect, Subpixels};
+use crate::imagemanager::ImageLoader;
+use crate::inputmanager::InputSnapshot;
+use crate::keyboard::KeyboardKey;
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::SoundManager;
+use crate::switchstate::SwitchState;
+use crate::tilemap::TileMap;
+use crate::uibutton::{ButtonOrder, ButtonOrderDirection, UiButton};
+use crate::utils::Color;
+
+// The actions are laid out in two columns, with the other buttons along the bottom.
+const ACTION_ROWS: usize = 5;
+const LABEL_COLUMNS: usize = 17;
+const STATUS_COLUMNS: usize = 39;
+
+fn action_label(action: Action) -> String {
+    action.name().replace('_', " ").to_uppercase()
+}
+
+fn truncate(s: String, columns: usize) -> String {
+    s.chars().take(columns).collect()
+}
+
+/*
+ * A menu for changing which keys and gamepad buttons trigger each action.
+ *
+ * Clicking an action waits for the next key or button to be pressed, and binds it to
+ * the action. The changes are only used and saved once the player chooses to save them,
+ * and only if no two actions that are used at the same time share an input.
+ */
+pub struct ControlsMenu {
+    tilemap: Option<TileMap>,
+    switches: SwitchState,
+    cursor: Cursor,
+    buttons: Vec<UiButton>,
+    button_order: ButtonOrder,
+    selected: usize,
+    bindings: Bindings,
+    // The action waiting for a key or button to be pressed.
+    listening: Option<Action>,
+    // After binding, ignore the buttons until everything is let go, so it isn't clicked again.
+    waiting_for_release: bool,
+    saved: Option<Bindings>,
+}
+
+impl ControlsMenu {
+    pub fn new(
+        path: Option<&Path>,
+        bindings: &Bindings,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<ControlsMenu> {
+        let tilemap = match path {
+            Some(path) => Some(TileMap::from_file(path, files, images)?),
+            None => None,
+        };
+
+        let tile_size = Pixels::new(8);
+        let button_height = Subpixels::from_pixels(24);
+        let action_width = Subpixels::from_pixels(152);
+        let mut buttons = Vec::new();
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let column = (i / ACTION_ROWS) as i32;
+            let row = (i % ACTION_ROWS) as i32;
+            let position = Rect {
+                x: Subpixels::from_pixels(4 + 160 * column),
+                y: Subpixels::from_pixels(14 + 26 * row),
+                w: action_width,
+                h: button_height,
+            };
+            let action = format!("bind:{}", action.name());
+            buttons.push(UiButton::with_label(
+                position,
+                String::new(),
+                Some(action),
+                tile_size,
+                tile_size,
+                images,
+            )?);
+        }
+        for (i, (label, action)) in [("SAVE", "save"), ("DEFAULTS", "defaults"), ("BACK", "back")]
+            .into_iter()
+            .enumerate()
+        {
+            let position = Rect {
+                x: Subpixels::from_pixels(4 + 108 * i as i32),
+                y: Subpixels::from_pixels(156),
+                w: Subpixels::from_pixels(96),
+                h: button_height,
+            };
+            buttons.push(UiButton::with_label(
+                position,
+                label.to_owned(),
+                Some(action.to_owned()),
+                tile_size,
+                tile_size,
+                images,
+            )?);
+        }
+
+        let button_order = ButtonOrder::new(&buttons);
+        let selected = button_order.first();
+        let mut menu = ControlsMenu {
+            tilemap,
+            switches: SwitchState::new(),
+            cursor: Cursor::new(images)?,
+            buttons,
+            button_order,
+            selected,
+            bindings: bindings.clone(),
+            listening: None,
+            waiting_for_release: false,
+            saved: None,
+        };
+        menu.update_labels();
+        Ok(menu)
+    }
+
+    fn update_labels(&mut self) {
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let key = self
+                .bindings
+                .get(*action)
+                .inputs
+                .iter()
+                .find(|input| matches!(input, Binding::Key(_)))
+                .map(Binding::label)
+                .unwrap_or_default();
+            let label = format!("{:<10} {}", action_label(*action), key);
+            self.buttons[i].set_label(truncate(label, LABEL_COLUMNS));
+        }
+    }
+
+    fn perform_action(&mut self, action: &str) -> Option<SceneResult> {
+        if let Some(name) = action.strip_prefix("bind:") {
+            self.listening = Action::from_name(name);
+        } else if action == "save" {
+            if self.bindings.conflicts().is_empty() {
+                self.saved = Some(self.bindings.clone());
+                return Some(SceneResult::Pop);
+            }
+        } else if action == "defaults" {
+            self.bindings = Bindings::default();
+            self.update_labels();
+        } else if action == "back" {
+            return Some(SceneResult::Pop);
+        }
+        None
+    }
+
+    fn status(&self) -> String {
+        if let Some(action) = self.listening {
+            return format!("PRESS A KEY FOR {}, OR ESC", action_label(action));
+        }
+        if let Some(conflict) = self.bindings.conflicts().first() {
+            return format!(
+                "{} AND {} BOTH USE {}",
+                action_label(conflict.first),
+                action_label(conflict.second),
+                conflict.binding.label()
+            );
+        }
+        let Some(action) = Action::ALL.get(self.selected) else {
+            return String::new();
+        };
+        let inputs: Vec<String> = self
+            .bindings
+            .get(*action)
+            .inputs
+            .iter()
+            .map(Binding::label)
+            .collect();
+        inputs.join(" ")
+    }
+}
+
+impl Scene for ControlsMenu {
+    fn update(
+        &mut self,
+        _context: &RenderContext,
+        inputs: &InputSnapshot,
+        sounds: &mut SoundManager,
+    ) -> SceneResult {
+        self.cursor.update(inputs);
+
+        if let Some(action) = self.listening {
+            // Sticks can't be bound here, since they're too easy to bump.
+            match inputs.pressed {
+                Some(Binding::Key(KeyboardKey::Escape)) => {}
+                Some(binding @ (Binding::Key(_) | Binding::Button(_))) => {
+                    self.bindings.rebind(action, binding);
+                    self.update_labels();
+                }
+                _ => return SceneResult::Continue,
+            }
+            self.listening = None;
+            self.waiting_for_release = true;
+            return SceneResult::Continue;
+        }
+        if self.waiting_for_release {
+            if inputs.ok_down || inputs.mouse_button_left_down {
+                return SceneResult::Continue;
+            }
+            self.waiting_for_release = false;
+        }
+
+        if inputs.cancel_clicked {
+            return SceneResult::Pop;
+        }
+
+        if inputs.menu_down_clicked {
+            self.selected =
+                self.button_order
+                    .next(self.selected, 1, ButtonOrderDirection::Vertical);
+        }
+        if inputs.menu_up_clicked {
+            self.selected =
+                self.button_order
+                    .next(self.selected, -1, ButtonOrderDirection::Vertical);
+        }
+        if inputs.menu_left_clicked {
+            self.selected =
+                self.button_order
+                    .next(self.selected, -1, ButtonOrderDirection::Horizontal);
+        }
+        if inputs.menu_right_clicked {
+            self.selected =
+                self.button_order
+                    .next(self.selected, 1, ButtonOrderDirection::Horizontal);
+        }
+
+        let mut clicked_action = None;
+        for (i, button) in self.buttons.iter_mut().enumerate() {
+            let selected = i == self.selected;
+            if let Some(action) = button.update(selected, inputs, sounds) {
+                clicked_action = Some(action);
+            }
+        }
+        if let Some(action) = clicked_action {
+            if let Some(result) = self.perform_action(&action) {
+                return result;
+            }
+        }
+
+        SceneResult::Continue
+    }
+
+    fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
+        let area = context.logical_area_in_subpixels();
+        context.fill_rect(
+            area,
+            RenderLayer::Hud,
+            Color {
+                r: 0x33,
+                g: 0x00,
+                b: 0x33,
+                a: 0xff,
+            },
+        );
+        if let Some(tilemap) = &self.tilemap {
+            tilemap.draw_background(
+                context,
+                RenderLayer::Hud,
+                area,
+                Point::zero(),
+                &self.switches,
+            );
+            tilemap.draw_foreground(
+                context,
+                RenderLayer::Hud,
+                area,
+                Point::zero(),
+                &self.switches,
+            );
+        }
+
+        let margin = Subpixels::from_pixels(4);
+        font.draw_string(
+            context,
+            RenderLayer::Hud,
+            Point::new(margin, margin),
+            "CONTROLS",
+        );
+        for button in self.buttons.iter() {
+            button.draw(context, RenderLayer::Hud, font);
+        }
+        font.draw_string(
+            context,
+            RenderLayer::Hud,
+            Point::new(margin, Subpixels::from_pixels(146)),
+            &truncate(self.status(), STATUS_COLUMNS),
+        );
+        self.cursor.draw(context, RenderLayer::Hud);
+    }
+
+    fn take_new_bindings(&mut self) -> Option<Bindings> {
+        self.saved.take()
+    }
+}
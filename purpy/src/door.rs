@@ -1,11 +1,13 @@
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
+use crate::binary::{BinaryReader, BinaryWriter};
 use crate::constants::{DOOR_CLOSING_FRAMES, DOOR_SPEED, DOOR_UNLOCKING_FRAMES};
 use crate::font::Font;
 use crate::geometry::{Pixels, Point, Rect, Subpixels};
 use crate::imagemanager::ImageLoader;
+use crate::key::Inventory;
 use crate::rendercontext::{RenderContext, RenderLayer};
 use crate::sprite::SpriteSheet;
 use crate::tilemap::MapObject;
@@ -18,6 +20,7 @@ enum DoorLayer {
     Frame,
 }
 
+#[derive(Clone, Copy)]
 enum DoorState {
     Locked = 1,
     Unlocking,
@@ -26,12 +29,30 @@ enum DoorState {
     Closed,
 }
 
+impl TryFrom<u8> for DoorState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => DoorState::Locked,
+            2 => DoorState::Unlocking,
+            3 => DoorState::Open,
+            4 => DoorState::Closing,
+            5 => DoorState::Closed,
+            _ => bail!("invalid door state: {}", value),
+        })
+    }
+}
+
 pub struct Door {
+    pub id: i32,
     position: Point<Subpixels>,
     sprite: SpriteSheet,
     pub destination: Option<String>,
     stars_needed: i32,
     stars_remaining: i32,
+    // The color of the key that has to be used up to open the door.
+    key_needed: Option<String>,
     pub active: bool,
     state: DoorState,
     frame: u32,
@@ -52,7 +73,8 @@ impl Door {
         let destination = obj.properties.destination.clone();
         let stars_needed = obj.properties.stars_needed;
         let stars_remaining = stars_needed;
-        let state = if stars_needed > 0 {
+        let key_needed = obj.properties.color.clone();
+        let state = if stars_needed > 0 || key_needed.is_some() {
             DoorState::Locked
         } else {
             DoorState::Open
@@ -60,11 +82,13 @@ impl Door {
         let frame = 0;
 
         Ok(Door {
+            id: obj.id,
             position,
             sprite,
             destination,
             stars_needed,
             stars_remaining,
+            key_needed,
             active,
             state,
             frame,
@@ -79,6 +103,12 @@ impl Door {
         matches!(self.state, DoorState::Closed)
     }
 
+    // Whether the door needed stars or a key to open, and got them.
+    pub fn is_unlocked(&self) -> bool {
+        (self.stars_needed > 0 || self.key_needed.is_some())
+            && !matches!(self.state, DoorState::Locked)
+    }
+
     pub fn unlock(&mut self) {
         if !matches!(self.state, DoorState::Locked) {
             return;
@@ -181,7 +211,20 @@ impl Door {
         player_rect.intersects(door_rect)
     }
 
-    pub fn update(&mut self, player_rect: Rect<Subpixels>, star_count: i32) {
+    // Doors that need a key only take it once the player is standing in front of them.
+    fn take_key(&self, inventory: &mut Inventory) -> bool {
+        match &self.key_needed {
+            None => true,
+            Some(color) => self.active && inventory.take(color),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        player_rect: Rect<Subpixels>,
+        star_count: i32,
+        inventory: &mut Inventory,
+    ) {
         self.active = self.is_inside(player_rect);
         self.stars_remaining = (self.stars_needed - star_count).max(0);
 
@@ -201,11 +244,25 @@ impl Door {
                 self.frame = (self.frame + 1).min(max_frame);
             }
             DoorState::Locked => {
-                if star_count >= self.stars_needed {
+                if star_count >= self.stars_needed && self.take_key(inventory) {
                     self.unlock();
                 }
             }
             _ => {}
         }
     }
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        writer.write_u8(self.state as u8);
+        writer.write_varint(self.frame as u64);
+        writer.write_i32(self.stars_remaining);
+        writer.write_bool(self.active);
+    }
+
+    pub fn restore_state(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        self.state = reader.read_u8()?.try_into()?;
+        self.frame = reader.read_varint()? as u32;
+        self.stars_remaining = reader.read_i32()?;
+        self.active = reader.read_bool()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,369 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use num_traits::Zero;
+
+use crate::binary::{BinaryReader, BinaryWriter, Fnv1a};
+use crate::constants::{ENEMY_FRAMES_PER_FRAME, ENEMY_SPEED, ENEMY_STOMPED_TIME};
+use crate::filemanager::FileManager;
+use crate::geometry::{Point, Rect, Subpixels};
+use crate::imagemanager::ImageLoader;
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::sprite::{AnimationStateMachine, SpriteSheet};
+use crate::switchstate::SwitchState;
+use crate::tilemap::{EnemyType, MapObject, Overflow, TileMap};
+use crate::utils::Direction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnemyState {
+    Moving = 0,
+    Stomped,
+    // Stomped enemies are kept around after they disappear, so that saved states line up.
+    Gone,
+}
+
+impl TryFrom<u8> for EnemyState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => EnemyState::Moving,
+            1 => EnemyState::Stomped,
+            2 => EnemyState::Gone,
+            _ => bail!("invalid enemy state: {}", value),
+        })
+    }
+}
+
+// What happened when the player ran into an enemy.
+pub enum EnemyContact {
+    None,
+    Stomped,
+    Hurt,
+}
+
+/*
+ * Something that moves around the level on its own, and kills the player on contact.
+ *
+ * Walkers go back and forth along the ground, turning around at walls and ledges. Fliers
+ * follow a path drawn as a polyline, which has to start where the flier is. Either kind can
+ * be stomped by landing on it from above.
+ */
+pub struct Enemy {
+    kind: EnemyType,
+    position: Rect<Subpixels>,
+    speed: Subpixels,
+    facing_right: bool,
+
+    // For fliers, the points of the path, and which one it's flying toward.
+    path: Vec<Point<Subpixels>>,
+    next_point: usize,
+    path_forward: bool,
+    overflow: Overflow,
+
+    state: EnemyState,
+    stomped_counter: i32,
+
+    sprite: SpriteSheet,
+    animation_state_machine: AnimationStateMachine,
+    frame: u32,
+    frames_to_next_frame: i32,
+}
+
+impl Enemy {
+    // Only the parts of the enemy that affect the simulation are hashed.
+    pub fn state_hash(&self, hasher: &mut Fnv1a) {
+        hasher.write_rect(self.position);
+        hasher.write_bool(self.facing_right);
+        hasher.write_u8(self.state as u8);
+    }
+
+    pub fn new(
+        obj: &MapObject,
+        map: &TileMap,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<Enemy> {
+        let kind = obj
+            .properties
+            .enemy
+            .context("enemy type required for enemies")?;
+        let sprite_path = obj
+            .properties
+            .sprite
+            .as_ref()
+            .context("sprite required for enemies")?;
+        let sprite_path = Path::new(sprite_path);
+        let sprite = images.load_spritesheet(sprite_path, obj.position.w, obj.position.h)?;
+        // e.g. "slime.png" is animated by "slime_states.txt".
+        let stem = sprite_path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .context("invalid enemy sprite path")?;
+        let states_path = sprite_path.with_file_name(format!("{}_states.txt", stem));
+        let animation_state_machine = AnimationStateMachine::from_file(&states_path, files)?;
+
+        let position: Rect<Subpixels> = obj.position.into();
+        let path = match kind {
+            EnemyType::Walker => Vec::new(),
+            EnemyType::Flier => {
+                let path_id = obj.properties.path.context("path required for fliers")?;
+                let path_obj = map
+                    .objects
+                    .iter()
+                    .find(|path_obj| path_obj.id == path_id)
+                    .ok_or_else(|| anyhow!("missing path {} for enemy {}", path_id, obj.id))?;
+                let first: Point<Subpixels> = path_obj
+                    .polyline
+                    .first()
+                    .copied()
+                    .with_context(|| format!("path {} is not a polyline", path_id))?
+                    .into();
+                path_obj
+                    .polyline
+                    .iter()
+                    .map(|point| {
+                        let point: Point<Subpixels> = (*point).into();
+                        position.top_left() + (point - first)
+                    })
+                    .collect()
+            }
+        };
+
+        // This is 16 for historical reasons, to match how platform speeds are given.
+        let speed = obj.properties.speed.unwrap_or(ENEMY_SPEED);
+        let speed = speed.as_subpixels() / 16;
+
+        Ok(Enemy {
+            kind,
+            position,
+            speed,
+            facing_right: !obj.properties.facing_left,
+            path,
+            next_point: 1,
+            path_forward: true,
+            overflow: obj.properties.overflow,
+            state: EnemyState::Moving,
+            stomped_counter: 0,
+            sprite,
+            animation_state_machine,
+            frame: 0,
+            frames_to_next_frame: ENEMY_FRAMES_PER_FRAME,
+        })
+    }
+
+    pub fn update(&mut self, map: &TileMap, switches: &SwitchState) -> Result<()> {
+        let state = match self.state {
+            EnemyState::Moving => {
+                match self.kind {
+                    EnemyType::Walker => self.walk(map, switches),
+                    EnemyType::Flier => self.fly(),
+                }
+                match self.kind {
+                    EnemyType::Walker => "WALKING",
+                    EnemyType::Flier => "FLYING",
+                }
+            }
+            EnemyState::Stomped => {
+                self.stomped_counter -= 1;
+                if self.stomped_counter <= 0 {
+                    self.state = EnemyState::Gone;
+                }
+                "STOMPED"
+            }
+            EnemyState::Gone => return Ok(()),
+        };
+
+        if self.frames_to_next_frame == 0 {
+            self.frame = self
+                .animation_state_machine
+                .next_frame(self.frame + 1, state)?
+                - 1;
+            self.frames_to_next_frame = ENEMY_FRAMES_PER_FRAME;
+        } else {
+            self.frames_to_next_frame -= 1;
+        }
+        Ok(())
+    }
+
+    fn walk(&mut self, map: &TileMap, switches: &SwitchState) {
+        let (dx, direction) = if self.facing_right {
+            (self.speed, Direction::Right)
+        } else {
+            (self.speed * -1, Direction::Left)
+        };
+        self.position.x += dx;
+
+        let wall = map.try_move_to(self.position, direction, switches, false);
+        if !wall.hard_offset.is_zero() {
+            self.position.x += wall.hard_offset;
+            self.facing_right = !self.facing_right;
+        } else if !self.has_ground_ahead(map, switches) {
+            self.position.x -= dx;
+            self.facing_right = !self.facing_right;
+        }
+    }
+
+    // Checks for anything solid just under the front edge of the walker.
+    fn has_ground_ahead(&self, map: &TileMap, switches: &SwitchState) -> bool {
+        let one = Subpixels::new(1);
+        let x = if self.facing_right {
+            self.position.right() - one
+        } else {
+            self.position.left()
+        };
+        let probe = Rect {
+            x,
+            y: self.position.bottom(),
+            w: one,
+            h: one,
+        };
+        let ground = map.try_move_to(probe, Direction::Down, switches, false);
+        !ground.hard_offset.is_zero()
+    }
+
+    fn fly(&mut self) {
+        let Some(target) = self.path.get(self.next_point).copied() else {
+            return;
+        };
+        let current = self.position.top_left();
+        let dx = (target.x - current.x).clamp(self.speed * -1, self.speed);
+        let dy = (target.y - current.y).clamp(self.speed * -1, self.speed);
+        self.position.x += dx;
+        self.position.y += dy;
+        if dx < Subpixels::zero() {
+            self.facing_right = false;
+        } else if dx > Subpixels::zero() {
+            self.facing_right = true;
+        }
+        if self.position.top_left() != target {
+            return;
+        }
+
+        // Pick the next point, according to what happens at the end of the path.
+        let last = self.path.len() - 1;
+        if self.path_forward {
+            if self.next_point < last {
+                self.next_point += 1;
+            } else {
+                match self.overflow {
+                    Overflow::Oscillate => {
+                        self.path_forward = false;
+                        self.next_point = last.saturating_sub(1);
+                    }
+                    Overflow::Wrap => self.next_point = 0,
+                    Overflow::Clamp => self.next_point = self.path.len(),
+                }
+            }
+        } else if self.next_point > 0 {
+            self.next_point -= 1;
+        } else {
+            self.path_forward = true;
+            self.next_point = 1.min(last);
+        }
+    }
+
+    /*
+     * Checks whether the player is touching the enemy. Landing on top of it while falling
+     * stomps it, and touching it any other way hurts. feet is the bottom of the player's
+     * bounds, and body is the rest.
+     */
+    pub fn touch(
+        &mut self,
+        body: Rect<Subpixels>,
+        feet: Rect<Subpixels>,
+        falling: bool,
+    ) -> EnemyContact {
+        if !matches!(self.state, EnemyState::Moving) {
+            return EnemyContact::None;
+        }
+        if falling && feet.intersects(self.position) && feet.top() <= self.position.top() {
+            self.state = EnemyState::Stomped;
+            self.stomped_counter = ENEMY_STOMPED_TIME;
+            return EnemyContact::Stomped;
+        }
+        if body.intersects(self.position) {
+            return EnemyContact::Hurt;
+        }
+        EnemyContact::None
+    }
+
+    pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, offset: Point<Subpixels>) {
+        if matches!(self.state, EnemyState::Gone) {
+            return;
+        }
+        let dest = self.position + offset;
+        self.sprite
+            .blit(context, layer, dest, self.frame, 0, !self.facing_right);
+    }
+
+    // Only the parts that change during the level are saved. The rest comes from the map.
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        writer.write_rect(self.position);
+        writer.write_bool(self.facing_right);
+        writer.write_varint(self.next_point as u64);
+        writer.write_bool(self.path_forward);
+        writer.write_u8(self.state as u8);
+        writer.write_i32(self.stomped_counter);
+        writer.write_varint(self.frame as u64);
+        writer.write_i32(self.frames_to_next_frame);
+    }
+
+    pub fn restore_state(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        self.position = reader.read_rect()?;
+        self.facing_right = reader.read_bool()?;
+        self.next_point = reader.read_varint()? as usize;
+        self.path_forward = reader.read_bool()?;
+        self.state = reader.read_u8()?.try_into()?;
+        self.stomped_counter = reader.read_i32()?;
+        self.frame = reader.read_varint()? as u32;
+        self.frames_to_next_frame = reader.read_i32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{test_files, TestImages, TEST_MAP_PATH};
+
+    // A walker on a ledge that's four tiles wide.
+    const LEDGE_ROWS: &[&str] = &["..........", "..........", "....####.."];
+
+    const WALKER_OBJECTS: &str = r#"
+<object id="1" x="32" y="8" width="8" height="8">
+ <properties>
+  <property name="enemy" value="walker"/>
+  <property name="sprite" value="assets/sprites/skelly2.png"/>
+ </properties>
+</object>
+"#;
+
+    #[test]
+    fn walkers_turn_at_ledges() {
+        let files = test_files(LEDGE_ROWS, WALKER_OBJECTS);
+        let map = TileMap::from_file(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let mut walker = Enemy::new(&map.objects[0], &map, &files, &mut TestImages).unwrap();
+        let switches = SwitchState::new();
+
+        let start = walker.position;
+        let mut turns = 0;
+        let mut left = start.left();
+        let mut right = start.right();
+        for _ in 0..200 {
+            let facing_right = walker.facing_right;
+            walker.update(&map, &switches).unwrap();
+            if walker.facing_right != facing_right {
+                turns += 1;
+            }
+            left = left.min(walker.position.left());
+            right = right.max(walker.position.right());
+            assert_eq!(walker.position.y, start.y);
+        }
+        // It walks all the way to each edge, but never past it.
+        assert!(turns >= 3);
+        assert_eq!(left, Subpixels::from_pixels(32));
+        assert_eq!(right, Subpixels::from_pixels(64));
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+
+use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+use crate::filemanager::FileManager;
+use crate::geometry::{Point, Subpixels};
+use crate::imagemanager::ImageLoader;
+use crate::inputmanager::{InputRecorder, InputSnapshot};
+use crate::menu::Menu;
+use crate::movement::{Movement, MovementState};
+use crate::physics::PhysicsConfig;
+use crate::player::Player;
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::scene::{Scene, SceneResult};
+use crate::soundmanager::SoundManager;
+use crate::switchstate::SwitchState;
+use crate::tilemap::TileMap;
+
+/*
+ * A recording of a previous run of a level, to race against whenever that level is played.
+ *
+ * The ghost is just a player, moved by the recorded inputs with the same rules as the live
+ * one, against the same map. It only steps on the frames where the recorded level was
+ * running, so time spent paused is left out. Everything else is not simulated:
+ *
+ * - There are no platforms, enemies, doors, or projectiles, and it has its own switches.
+ * - While the recorded run was rewinding, the ghost just waits where it is.
+ * - If the run left the pause menu other than by resuming, e.g. to restart, the ghost stops.
+ *
+ * So nothing the ghost does can affect the live game, but it can stray from the original
+ * run wherever one of those made a difference.
+ */
+pub struct Ghost {
+    level_path: PathBuf,
+    frames: Rc<[InputSnapshot]>,
+}
+
+/*
+ * Plays the recording through the pause menu whenever it was paused, to find the inputs for
+ * just the frames where the level was running.
+ */
+fn level_frames(
+    recording: &mut InputRecorder,
+    level_path: &Path,
+    files: &FileManager,
+    images: &mut dyn ImageLoader,
+) -> Result<Vec<InputSnapshot>> {
+    let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0, 0)?;
+    let mut sounds = SoundManager::noop_manager();
+    let mut pause: Option<Menu> = None;
+    let mut frames = Vec::new();
+    for frame in 0..recording.frame_count() {
+        let inputs = recording.playback(frame);
+        if let Some(menu) = pause.as_mut() {
+            match menu.update(&context, &inputs, &mut sounds) {
+                SceneResult::Continue => {}
+                SceneResult::Pop => pause = None,
+                _ => break,
+            }
+        } else if inputs.cancel_clicked {
+            pause = Some(Menu::new_pause_screen(
+                level_path.to_owned(),
+                files,
+                images,
+            )?);
+        } else {
+            frames.push(inputs);
+        }
+    }
+    Ok(frames)
+}
+
+impl Ghost {
+    // Loads a recording, which must have started in a level, e.g. with --level.
+    pub fn load(path: &Path, files: &FileManager, images: &mut dyn ImageLoader) -> Result<Ghost> {
+        let mut recording = InputRecorder::from_file(path, files)?;
+        let level_path = recording
+            .start_scene()
+            .strip_prefix("level:")
+            .ok_or_else(|| {
+                anyhow!(
+                    "ghost recording {:?} must start in a level, not {}",
+                    path,
+                    recording.start_scene()
+                )
+            })?;
+        let level_path = Path::new(level_path).to_owned();
+        let frames = level_frames(&mut recording, &level_path, files, images)?.into();
+        Ok(Ghost { level_path, frames })
+    }
+
+    // The level the ghost runs in.
+    pub fn level_path(&self) -> &Path {
+        &self.level_path
+    }
+
+    // Starts the ghost from the beginning of the recording, in a level that was just loaded.
+    pub fn start(
+        &self,
+        map: Rc<TileMap>,
+        physics: &PhysicsConfig,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<GhostRun> {
+        Ok(GhostRun {
+            player: Player::for_map(&map, files, images)?,
+            movement_state: MovementState::new(physics),
+            map,
+            physics: physics.clone(),
+            switches: SwitchState::new(),
+            frames: self.frames.clone(),
+            sounds: SoundManager::noop_manager(),
+            frame: 0,
+        })
+    }
+}
+
+// A ghost being replayed alongside the live game.
+pub struct GhostRun {
+    player: Player,
+    movement_state: MovementState,
+    map: Rc<TileMap>,
+    physics: PhysicsConfig,
+    switches: SwitchState,
+    frames: Rc<[InputSnapshot]>,
+    sounds: SoundManager,
+    frame: usize,
+}
+
+impl GhostRun {
+    // Moves the ghost one frame, and returns false once it's finished.
+    pub fn update(&mut self) -> bool {
+        let Some(inputs) = self.frames.get(self.frame) else {
+            return false;
+        };
+        self.frame += 1;
+        // The ghost has no earlier states to go back to, so it waits out any rewinding.
+        if !inputs.rewind_down {
+            let mut movement = Movement {
+                player: &mut self.player,
+                state: &mut self.movement_state,
+                map: &self.map,
+                physics: &self.physics,
+                platforms: &mut [],
+                switches: &mut self.switches,
+                door: None,
+            };
+            movement.update(inputs, &mut self.sounds);
+            self.player
+                .update_sprite()
+                .expect("state machine should be valid");
+        }
+        !self.player.is_dead && self.frame < self.frames.len()
+    }
+
+    pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, offset: Point<Subpixels>) {
+        self.player
+            .draw_ghost(context, layer, self.player.position + offset);
+    }
+}
@@ -4,88 +4,78 @@ use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, bail, Context, Result};
 use gilrs::Gilrs;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use num_traits::Zero;
 
+use crate::bindings::{Action, Binding, Bindings, StickDirection};
+use crate::constants::{
+    MENU_REPEAT_DELAY, MENU_REPEAT_INTERVAL, REWIND_BUDGET, START_SCENE, STATE_HASH_INTERVAL,
+};
 use crate::filemanager::FileManager;
+use crate::font::Font;
 use crate::geometry::{Pixels, Point};
+use crate::keyboard::KeyboardKey;
+use crate::rendercontext::RenderContext;
+use crate::replay::{
+    Replay, ReplayEntry, ReplayHeader, StateHash, LEGACY_FORMAT_VERSION, REPLAY_FORMAT_VERSION,
+};
 use crate::smallintmap::SmallIntMap;
+use crate::storage::{NoopStorage, Storage};
+use crate::touch::{TouchPhase, Touches};
 use crate::{RENDER_HEIGHT, RENDER_WIDTH};
 
+// Gamepad buttons, named by position, as in gilrs. The triggers ending in 2 are the lower ones.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum KeyboardKey {
-    Escape,
-    Space,
-    Enter,
-    W,
-    A,
-    S,
-    D,
-    Up,
-    Down,
-    Left,
-    Right,
-}
-
-impl KeyboardKey {
-    #[cfg(feature = "sdl2")]
-    fn from_sdl_key(key: sdl2::keyboard::Keycode) -> Option<Self> {
-        use sdl2::keyboard::Keycode;
-        Some(match key {
-            Keycode::Escape => KeyboardKey::Escape,
-            Keycode::Space => KeyboardKey::Space,
-            Keycode::Return => KeyboardKey::Enter,
-            Keycode::W => KeyboardKey::W,
-            Keycode::A => KeyboardKey::A,
-            Keycode::S => KeyboardKey::S,
-            Keycode::D => KeyboardKey::D,
-            Keycode::Up => KeyboardKey::Up,
-            Keycode::Down => KeyboardKey::Down,
-            Keycode::Left => KeyboardKey::Left,
-            Keycode::Right => KeyboardKey::Right,
-            _ => return None,
-        })
-    }
-
-    #[cfg(feature = "winit")]
-    fn from_keycode(key: winit::keyboard::KeyCode) -> Option<Self> {
-        use winit::keyboard::KeyCode;
-        Some(match key {
-            KeyCode::Escape => KeyboardKey::Escape,
-            KeyCode::Space => KeyboardKey::Space,
-            KeyCode::Enter => KeyboardKey::Enter,
-            KeyCode::KeyW => KeyboardKey::W,
-            KeyCode::KeyA => KeyboardKey::A,
-            KeyCode::KeyS => KeyboardKey::S,
-            KeyCode::KeyD => KeyboardKey::D,
-            KeyCode::ArrowUp => KeyboardKey::Up,
-            KeyCode::ArrowDown => KeyboardKey::Down,
-            KeyCode::ArrowLeft => KeyboardKey::Left,
-            KeyCode::ArrowRight => KeyboardKey::Right,
-            _ => return None,
-        })
-    }
-}
-
-impl From<KeyboardKey> for usize {
-    fn from(value: KeyboardKey) -> Self {
-        value as usize
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum JoystickButton {
-    Up = 0,
-    Down,
-    Left,
-    Right,
+pub enum JoystickButton {
+    DPadUp = 0,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
     North,
     South,
     East,
     West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
 }
 
 impl JoystickButton {
+    pub const ALL: [JoystickButton; 16] = [
+        JoystickButton::DPadUp,
+        JoystickButton::DPadDown,
+        JoystickButton::DPadLeft,
+        JoystickButton::DPadRight,
+        JoystickButton::North,
+        JoystickButton::South,
+        JoystickButton::East,
+        JoystickButton::West,
+        JoystickButton::LeftTrigger,
+        JoystickButton::LeftTrigger2,
+        JoystickButton::RightTrigger,
+        JoystickButton::RightTrigger2,
+        JoystickButton::Select,
+        JoystickButton::Start,
+        JoystickButton::LeftThumb,
+        JoystickButton::RightThumb,
+    ];
+
+    pub fn name(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        JoystickButton::ALL
+            .iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+
     fn from_button(value: gilrs::Button) -> Option<Self> {
         use gilrs::Button;
 
@@ -94,10 +84,18 @@ impl JoystickButton {
             Button::East => JoystickButton::East,
             Button::North => JoystickButton::North,
             Button::West => JoystickButton::West,
-            Button::DPadUp => JoystickButton::Up,
-            Button::DPadDown => JoystickButton::Down,
-            Button::DPadLeft => JoystickButton::Left,
-            Button::DPadRight => JoystickButton::Right,
+            Button::DPadUp => JoystickButton::DPadUp,
+            Button::DPadDown => JoystickButton::DPadDown,
+            Button::DPadLeft => JoystickButton::DPadLeft,
+            Button::DPadRight => JoystickButton::DPadRight,
+            Button::LeftTrigger => JoystickButton::LeftTrigger,
+            Button::LeftTrigger2 => JoystickButton::LeftTrigger2,
+            Button::RightTrigger => JoystickButton::RightTrigger,
+            Button::RightTrigger2 => JoystickButton::RightTrigger2,
+            Button::Select => JoystickButton::Select,
+            Button::Start => JoystickButton::Start,
+            Button::LeftThumb => JoystickButton::LeftThumb,
+            Button::RightThumb => JoystickButton::RightThumb,
             _ => return None,
         })
     }
@@ -162,6 +160,11 @@ struct InputState {
     joystick_buttons_down: SmallIntMap<JoystickButton, bool>,
     joy_axes: SmallIntMap<JoystickAxis, f32>,
     mouse_buttons_down: SmallIntMap<MouseButton, bool>,
+    // The most recent key or button to be pressed since the last snapshot.
+    last_pressed: Option<Binding>,
+    // How far the mouse wheel has scrolled up since the last snapshot, or down if negative.
+    scroll: i32,
+    touches: Touches,
 
     mouse_position: Point<Pixels>,
     adjust_mouse_position: bool,
@@ -176,6 +179,9 @@ impl InputState {
             joystick_buttons_down: SmallIntMap::new(),
             joy_axes: SmallIntMap::new(),
             mouse_buttons_down: SmallIntMap::new(),
+            last_pressed: None,
+            scroll: 0,
+            touches: Touches::new(),
             mouse_position: Point::zero(),
             adjust_mouse_position,
             window_width,
@@ -184,6 +190,10 @@ impl InputState {
     }
 
     fn set_key_down(&mut self, key: KeyboardKey) {
+        // Holding a key down can repeat the event, but it only counts as pressed once.
+        if !self.is_key_down(key) {
+            self.last_pressed = Some(Binding::Key(key));
+        }
         self.keys_down.insert(key, true);
     }
 
@@ -196,6 +206,9 @@ impl InputState {
     }
 
     fn set_joystick_button_down(&mut self, button: JoystickButton) {
+        if !self.is_joystick_button_down(button) {
+            self.last_pressed = Some(Binding::Button(button));
+        }
         self.joystick_buttons_down.insert(button, true);
     }
 
@@ -223,17 +236,47 @@ impl InputState {
         *self.mouse_buttons_down.get(button).unwrap_or(&false)
     }
 
+    fn add_scroll(&mut self, amount: i32) {
+        self.scroll += amount;
+    }
+
     fn set_window_size(&mut self, width: i32, height: i32) {
         self.window_width = width;
         self.window_height = height;
     }
 
     fn set_mouse_position(&mut self, x: i32, y: i32) {
-        self.mouse_position = if self.adjust_mouse_position {
+        self.mouse_position = self.get_render_position(x, y);
+    }
+
+    fn get_render_position(&mut self, x: i32, y: i32) -> Point<Pixels> {
+        if self.adjust_mouse_position {
             self.get_adjusted_mouse_position(x, y)
         } else {
             Point::new(Pixels::new(x), Pixels::new(y))
-        };
+        }
+    }
+
+    fn touch(&mut self, id: u64, phase: TouchPhase, x: i32, y: i32) {
+        let position = self.get_render_position(x, y);
+        match phase {
+            TouchPhase::Started => {
+                if self.touches.start(id, position) {
+                    self.mouse_position = position;
+                    self.set_mouse_button_down(MouseButton::Left);
+                }
+            }
+            TouchPhase::Moved => {
+                if self.touches.move_to(id, position) {
+                    self.mouse_position = position;
+                }
+            }
+            TouchPhase::Ended => {
+                if self.touches.end(id) {
+                    self.set_mouse_button_up(MouseButton::Left);
+                }
+            }
+        }
     }
 
     fn get_adjusted_mouse_position(&mut self, pos_x: i32, pos_y: i32) -> Point<Pixels> {
@@ -323,6 +366,50 @@ where
     }
 }
 
+// Like a trigger, but if it's held down, it turns on again every so often, e.g. for menus.
+struct RepeatInput<T: TransientBinaryInput> {
+    inner: T,
+    // How many frames it's been held down, or zero if it isn't.
+    held_frames: u32,
+    on: bool,
+}
+
+impl<T> RepeatInput<T>
+where
+    T: TransientBinaryInput,
+{
+    fn from(inner: T) -> RepeatInput<T> {
+        RepeatInput {
+            inner,
+            held_frames: 0,
+            on: false,
+        }
+    }
+}
+
+impl<T> StatefulBinaryInput for RepeatInput<T>
+where
+    T: TransientBinaryInput,
+{
+    fn update(&mut self, state: &InputState) {
+        if !self.inner.is_on(state) {
+            self.held_frames = 0;
+            self.on = false;
+            return;
+        }
+        self.on = match self.held_frames {
+            0 => true,
+            n if n < MENU_REPEAT_DELAY => false,
+            n => (n - MENU_REPEAT_DELAY).is_multiple_of(MENU_REPEAT_INTERVAL),
+        };
+        self.held_frames += 1;
+    }
+
+    fn is_on(&self) -> bool {
+        self.on
+    }
+}
+
 struct KeyInput {
     key: KeyboardKey,
 }
@@ -393,6 +480,22 @@ impl TransientBinaryInput for JoystickThresholdInput {
     }
 }
 
+struct TouchInput {
+    action: Action,
+}
+
+impl TouchInput {
+    fn new(action: Action) -> Self {
+        TouchInput { action }
+    }
+}
+
+impl TransientBinaryInput for TouchInput {
+    fn is_on(&self, state: &InputState) -> bool {
+        state.touches.is_action_down(self.action)
+    }
+}
+
 struct MouseButtonInput {
     button: MouseButton,
 }
@@ -435,14 +538,19 @@ enum BinaryInput {
     Cancel,
     PlayerLeft,
     PlayerRight,
+    PlayerUp,
     PlayerCrouch,
     PlayerJumpTrigger,
     PlayerJumpDown,
+    PlayerJumpWithoutUpTrigger,
     MenuDown,
     MenuUp,
     MenuLeft,
     MenuRight,
     MouseButtonLeft,
+    SaveState,
+    LoadState,
+    Rewind,
 }
 
 impl From<BinaryInput> for usize {
@@ -458,127 +566,161 @@ fn all_binary_inputs() -> Vec<BinaryInput> {
         BinaryInput::Cancel,
         BinaryInput::PlayerLeft,
         BinaryInput::PlayerRight,
+        BinaryInput::PlayerUp,
         BinaryInput::PlayerCrouch,
         BinaryInput::PlayerJumpTrigger,
         BinaryInput::PlayerJumpDown,
+        BinaryInput::PlayerJumpWithoutUpTrigger,
         BinaryInput::MenuDown,
         BinaryInput::MenuUp,
         BinaryInput::MenuLeft,
         BinaryInput::MenuRight,
         BinaryInput::MouseButtonLeft,
+        BinaryInput::SaveState,
+        BinaryInput::LoadState,
+        BinaryInput::Rewind,
     ]
 }
 
-fn key_input(key: KeyboardKey) -> Box<CachedBinaryInput<KeyInput>> {
-    Box::new(CachedBinaryInput::from(KeyInput::new(key)))
-}
-
-fn key_trigger(key: KeyboardKey) -> Box<TriggerInput<KeyInput>> {
-    Box::new(TriggerInput::from(KeyInput::new(key)))
+// How a binding turns into an input for an action.
+#[derive(Debug, Clone, Copy)]
+enum InputMode {
+    // On for as long as it's held down.
+    Down,
+    // Only on for the first frame it's pressed.
+    Trigger,
+    // On for the first frame, and then repeatedly while it's held down.
+    Repeat,
 }
 
-fn joystick_button_input(button: JoystickButton) -> Box<CachedBinaryInput<JoystickButtonInput>> {
-    Box::new(CachedBinaryInput::from(JoystickButtonInput::new(button)))
+fn stateful_input<T>(inner: T, mode: InputMode) -> Box<dyn StatefulBinaryInput>
+where
+    T: TransientBinaryInput + 'static,
+{
+    match mode {
+        InputMode::Down => Box::new(CachedBinaryInput::from(inner)),
+        InputMode::Trigger => Box::new(TriggerInput::from(inner)),
+        InputMode::Repeat => Box::new(RepeatInput::from(inner)),
+    }
 }
 
-fn joystick_button_trigger(button: JoystickButton) -> Box<TriggerInput<JoystickButtonInput>> {
-    Box::new(TriggerInput::from(JoystickButtonInput::new(button)))
+fn mouse_button_input(button: MouseButton) -> Box<CachedBinaryInput<MouseButtonInput>> {
+    Box::new(CachedBinaryInput::from(MouseButtonInput::new(button)))
 }
 
-fn joystick_threshold(
-    axis: JoystickAxis,
-    low: Option<f32>,
-    high: Option<f32>,
-) -> Box<CachedBinaryInput<JoystickThresholdInput>> {
-    Box::new(CachedBinaryInput::from(JoystickThresholdInput::new(
-        axis, low, high,
-    )))
+fn binding_input(
+    binding: Binding,
+    threshold: f32,
+    mode: InputMode,
+) -> Box<dyn StatefulBinaryInput> {
+    match binding {
+        Binding::Key(key) => stateful_input(KeyInput::new(key), mode),
+        Binding::Button(button) => stateful_input(JoystickButtonInput::new(button), mode),
+        Binding::Stick(direction) => {
+            let (axis, low, high) = match direction {
+                StickDirection::Up => (JoystickAxis::Vertical, Some(-threshold), None),
+                StickDirection::Down => (JoystickAxis::Vertical, None, Some(threshold)),
+                StickDirection::Left => (JoystickAxis::Horizontal, Some(-threshold), None),
+                StickDirection::Right => (JoystickAxis::Horizontal, None, Some(threshold)),
+            };
+            stateful_input(JoystickThresholdInput::new(axis, low, high), mode)
+        }
+    }
 }
 
-fn joystick_trigger(
-    axis: JoystickAxis,
-    low: Option<f32>,
-    high: Option<f32>,
-) -> Box<TriggerInput<JoystickThresholdInput>> {
-    Box::new(TriggerInput::from(JoystickThresholdInput::new(
-        axis, low, high,
-    )))
+fn create_input(input: BinaryInput, bindings: &Bindings) -> AnyOfInput {
+    // Holding a direction in a menu keeps moving, so that long lists are easy to scroll.
+    let (action, mode) = match input {
+        BinaryInput::OkTrigger => (Action::Ok, InputMode::Trigger),
+        BinaryInput::OkDown => (Action::Ok, InputMode::Down),
+        BinaryInput::Cancel => (Action::Cancel, InputMode::Trigger),
+        BinaryInput::PlayerLeft => (Action::Left, InputMode::Down),
+        BinaryInput::PlayerRight => (Action::Right, InputMode::Down),
+        BinaryInput::PlayerUp => (Action::Up, InputMode::Down),
+        BinaryInput::PlayerCrouch => (Action::Down, InputMode::Down),
+        BinaryInput::PlayerJumpTrigger => (Action::Jump, InputMode::Trigger),
+        BinaryInput::PlayerJumpDown => (Action::Jump, InputMode::Down),
+        BinaryInput::PlayerJumpWithoutUpTrigger => (Action::Jump, InputMode::Trigger),
+        BinaryInput::MenuDown => (Action::Down, InputMode::Repeat),
+        BinaryInput::MenuUp => (Action::Up, InputMode::Repeat),
+        BinaryInput::MenuLeft => (Action::Left, InputMode::Repeat),
+        BinaryInput::MenuRight => (Action::Right, InputMode::Repeat),
+        BinaryInput::MouseButtonLeft => {
+            return AnyOfInput(vec![mouse_button_input(MouseButton::Left)]);
+        }
+        BinaryInput::SaveState => (Action::SaveState, InputMode::Trigger),
+        BinaryInput::LoadState => (Action::LoadState, InputMode::Trigger),
+        BinaryInput::Rewind => (Action::Rewind, InputMode::Down),
+    };
+    // Leave out anything that's also up, so a ladder can tell climbing apart from jumping.
+    let skipped = match input {
+        BinaryInput::PlayerJumpWithoutUpTrigger => bindings.get(Action::Up).inputs.as_slice(),
+        _ => &[],
+    };
+    let bindings = bindings.get(action);
+    let mut inputs: Vec<Box<dyn StatefulBinaryInput>> = bindings
+        .inputs
+        .iter()
+        .filter(|binding| !skipped.contains(binding))
+        .map(|binding| binding_input(*binding, bindings.threshold, mode))
+        .collect();
+    // The on-screen touch buttons can't be rebound.
+    inputs.push(stateful_input(TouchInput::new(action), mode));
+    AnyOfInput(inputs)
 }
 
-fn mouse_button_input(button: MouseButton) -> Box<CachedBinaryInput<MouseButtonInput>> {
-    Box::new(CachedBinaryInput::from(MouseButtonInput::new(button)))
+// Whether anything besides the stick is holding the action, e.g. a key, the d-pad, or a touch.
+fn is_held_without_stick(action: Action, state: &InputState, bindings: &Bindings) -> bool {
+    let held = bindings
+        .get(action)
+        .inputs
+        .iter()
+        .any(|binding| match binding {
+            Binding::Key(key) => state.is_key_down(*key),
+            Binding::Button(button) => state.is_joystick_button_down(*button),
+            Binding::Stick(_) => false,
+        });
+    held || TouchInput::new(action).is_on(state)
 }
 
-fn create_input(input: BinaryInput) -> AnyOfInput {
-    AnyOfInput(match input {
-        BinaryInput::OkTrigger => vec![
-            key_trigger(KeyboardKey::Enter),
-            joystick_button_trigger(JoystickButton::South),
-        ],
-        BinaryInput::OkDown => vec![
-            key_input(KeyboardKey::Enter),
-            joystick_button_input(JoystickButton::South),
-        ],
-        BinaryInput::Cancel => vec![
-            key_trigger(KeyboardKey::Escape),
-            joystick_button_trigger(JoystickButton::West),
-        ],
-        BinaryInput::PlayerLeft => vec![
-            key_input(KeyboardKey::Left),
-            key_input(KeyboardKey::A),
-            joystick_button_input(JoystickButton::Left),
-            joystick_threshold(JoystickAxis::Horizontal, Some(-0.5), None),
-        ],
-        BinaryInput::PlayerRight => vec![
-            key_input(KeyboardKey::Right),
-            key_input(KeyboardKey::D),
-            joystick_button_input(JoystickButton::Right),
-            joystick_threshold(JoystickAxis::Horizontal, None, Some(0.5)),
-        ],
-        BinaryInput::PlayerCrouch => vec![
-            key_input(KeyboardKey::Down),
-            key_input(KeyboardKey::S),
-            joystick_button_input(JoystickButton::Down),
-            joystick_threshold(JoystickAxis::Vertical, None, Some(0.5)),
-        ],
-        BinaryInput::PlayerJumpTrigger => vec![
-            key_trigger(KeyboardKey::Space),
-            key_trigger(KeyboardKey::W),
-            key_trigger(KeyboardKey::Up),
-            joystick_button_trigger(JoystickButton::South),
-        ],
-        BinaryInput::PlayerJumpDown => vec![
-            key_input(KeyboardKey::Space),
-            key_input(KeyboardKey::W),
-            key_input(KeyboardKey::Up),
-            joystick_button_input(JoystickButton::South),
-        ],
-        BinaryInput::MenuDown => vec![
-            key_trigger(KeyboardKey::Down),
-            key_trigger(KeyboardKey::S),
-            joystick_button_trigger(JoystickButton::Down),
-            joystick_trigger(JoystickAxis::Vertical, None, Some(0.5)),
-        ],
-        BinaryInput::MenuUp => vec![
-            key_trigger(KeyboardKey::W),
-            key_trigger(KeyboardKey::Up),
-            joystick_button_trigger(JoystickButton::Up),
-            joystick_trigger(JoystickAxis::Vertical, Some(-0.5), None),
-        ],
-        BinaryInput::MenuLeft => vec![
-            key_trigger(KeyboardKey::Left),
-            key_trigger(KeyboardKey::A),
-            joystick_button_trigger(JoystickButton::Left),
-            joystick_trigger(JoystickAxis::Horizontal, Some(-0.5), None),
-        ],
-        BinaryInput::MenuRight => vec![
-            key_trigger(KeyboardKey::D),
-            key_trigger(KeyboardKey::Right),
-            joystick_button_trigger(JoystickButton::Right),
-            joystick_trigger(JoystickAxis::Horizontal, None, Some(0.5)),
-        ],
-        BinaryInput::MouseButtonLeft => vec![mouse_button_input(MouseButton::Left)],
-    })
+/*
+ * How fast the stick says to walk, from -127 to 127, or 0 to walk at the usual speed.
+ *
+ * Walking starts from zero at the threshold for left or right, where those actions turn on,
+ * and speeds up the further the stick is pushed. The stick is only used this way if it's
+ * bound to walking in that direction, and not while anything else is holding left or right,
+ * so that keys and the d-pad always walk at full speed.
+ */
+fn stick_walk_x(state: &InputState, bindings: &Bindings) -> i8 {
+    if is_held_without_stick(Action::Left, state, bindings)
+        || is_held_without_stick(Action::Right, state, bindings)
+    {
+        return 0;
+    }
+    let Some(axis) = state.joy_axes.get(JoystickAxis::Horizontal).copied() else {
+        return 0;
+    };
+    let (action, direction) = if axis < 0.0 {
+        (Action::Left, StickDirection::Left)
+    } else {
+        (Action::Right, StickDirection::Right)
+    };
+    let action_bindings = bindings.get(action);
+    if !action_bindings.inputs.contains(&Binding::Stick(direction)) {
+        return 0;
+    }
+    let threshold = action_bindings.threshold;
+    if axis.abs() <= threshold {
+        return 0;
+    }
+    // Anything past the threshold walks at least a little, since 0 would mean full speed.
+    let amount = ((axis.abs() - threshold) / (1.0 - threshold)).clamp(0.0, 1.0);
+    let amount = ((amount * i8::MAX as f32).round() as i8).max(1);
+    if axis < 0.0 {
+        -amount
+    } else {
+        amount
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -588,9 +730,12 @@ pub struct InputSnapshot {
     pub cancel_clicked: bool,
     pub player_left_down: bool,
     pub player_right_down: bool,
+    pub player_up_down: bool,
     pub player_crouch_down: bool,
     pub player_jump_clicked: bool,
     pub player_jump_down: bool,
+    // Like player_jump_clicked, but only for inputs that aren't also bound to up.
+    pub player_jump_without_up_clicked: bool,
     pub menu_down_clicked: bool,
     pub menu_up_clicked: bool,
     pub menu_left_clicked: bool,
@@ -598,9 +743,34 @@ pub struct InputSnapshot {
 
     pub mouse_button_left_down: bool,
 
+    pub save_state_clicked: bool,
+    pub load_state_clicked: bool,
+    pub rewind_down: bool,
+
+    /*
+     * How far the stick is pushed to walk left (negative) or right (positive), from -127
+     * to 127, starting from zero at the edge of the dead zone. This is zero if the stick
+     * isn't being used, in which case the player walks at full speed.
+     */
+    pub player_walk_x: i8,
+
+    // The key or button that was just pressed, if any, e.g. for rebinding controls.
+    pub pressed: Option<Binding>,
+
+    // Whether the mouse wheel was scrolled since the last snapshot.
+    pub scroll_up_clicked: bool,
+    pub scroll_down_clicked: bool,
+
     pub mouse_position: Point<Pixels>,
 }
 
+// Nothing pressed or held, with the mouse in the corner.
+impl Default for InputSnapshot {
+    fn default() -> Self {
+        InputSnapshot::decode(0)
+    }
+}
+
 #[inline]
 fn bool_to_bin(b: bool, n: u8) -> u64 {
     if b {
@@ -615,8 +785,36 @@ fn bin_to_bool(encoded: u64, n: u8) -> bool {
     (encoded & (1 << n)) != 0
 }
 
+/*
+ * The version of the bit layout used by InputSnapshot::encode.
+ *
+ * New fields can use bits that were always zero without changing this. But if the
+ * meaning of any existing bits changes, bump this and teach migrate() how to convert
+ * snapshots from the older layout, so that old replays keep working.
+ */
+pub(crate) const INPUT_SNAPSHOT_LAYOUT: u16 = 2;
+
+// Layout 1 had just the original buttons, and 16 bits for each mouse coordinate.
+const MOUSE_ONLY_INPUT_SNAPSHOT_LAYOUT: u16 = 1;
+
 impl InputSnapshot {
-    fn encode(&self) -> u64 {
+    // Converts a snapshot encoded with an older layout into the current layout.
+    fn migrate(layout: u16, encoded: u64) -> Result<u64> {
+        match layout {
+            MOUSE_ONLY_INPUT_SNAPSHOT_LAYOUT => {
+                let mouse_x = (encoded >> 32) & 0xFFFF;
+                let mouse_y = (encoded >> 48) & 0xFFFF;
+                let mut result = encoded & 0xFFFFFFFF;
+                result |= (mouse_x & 0x07FF) << 32;
+                result |= (mouse_y & 0x07FF) << 43;
+                Ok(result)
+            }
+            INPUT_SNAPSHOT_LAYOUT => Ok(encoded),
+            _ => bail!("unsupported input snapshot layout: {}", layout),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> u64 {
         let mut result = 0;
         result |= bool_to_bin(self.ok_clicked, 0);
         result |= bool_to_bin(self.ok_down, 1);
@@ -631,18 +829,29 @@ impl InputSnapshot {
         result |= bool_to_bin(self.menu_left_clicked, 10);
         result |= bool_to_bin(self.menu_right_clicked, 11);
         result |= bool_to_bin(self.mouse_button_left_down, 12);
+        result |= bool_to_bin(self.save_state_clicked, 13);
+        result |= bool_to_bin(self.load_state_clicked, 14);
+        result |= bool_to_bin(self.rewind_down, 15);
+        if let Some(pressed) = self.pressed {
+            result |= (pressed.code() as u64) << 16;
+        }
+        result |= bool_to_bin(self.scroll_up_clicked, 30);
+        result |= bool_to_bin(self.scroll_down_clicked, 31);
 
         let one_pixel = Pixels::new(1);
         let mouse_x = self.mouse_position.x / one_pixel;
         let mouse_y = self.mouse_position.y / one_pixel;
-        result |= ((mouse_x & 0x0000FFFF) as u64) << 32;
-        result |= ((mouse_y & 0x0000FFFF) as u64) << 48;
+        result |= ((mouse_x & 0x000007FF) as u64) << 32;
+        result |= ((mouse_y & 0x000007FF) as u64) << 43;
+        result |= bool_to_bin(self.player_up_down, 54);
+        result |= bool_to_bin(self.player_jump_without_up_clicked, 55);
+        result |= (self.player_walk_x as u8 as u64) << 56;
         result
     }
 
     fn decode(n: u64) -> InputSnapshot {
-        let mouse_x = Pixels::new(((n >> 32) & 0x0000FFFF) as i32);
-        let mouse_y = Pixels::new(((n >> 48) & 0x0000FFFF) as i32);
+        let mouse_x = Pixels::new(((n >> 32) & 0x000007FF) as i32);
+        let mouse_y = Pixels::new(((n >> 43) & 0x000007FF) as i32);
 
         InputSnapshot {
             ok_clicked: bin_to_bool(n, 0),
@@ -650,47 +859,83 @@ impl InputSnapshot {
             cancel_clicked: bin_to_bool(n, 2),
             player_left_down: bin_to_bool(n, 3),
             player_right_down: bin_to_bool(n, 4),
+            player_up_down: bin_to_bool(n, 54),
             player_crouch_down: bin_to_bool(n, 5),
             player_jump_clicked: bin_to_bool(n, 6),
             player_jump_down: bin_to_bool(n, 7),
+            player_jump_without_up_clicked: bin_to_bool(n, 55),
             menu_down_clicked: bin_to_bool(n, 8),
             menu_up_clicked: bin_to_bool(n, 9),
             menu_left_clicked: bin_to_bool(n, 10),
             menu_right_clicked: bin_to_bool(n, 11),
             mouse_button_left_down: bin_to_bool(n, 12),
+            save_state_clicked: bin_to_bool(n, 13),
+            load_state_clicked: bin_to_bool(n, 14),
+            rewind_down: bin_to_bool(n, 15),
+            player_walk_x: (n >> 56) as u8 as i8,
+            pressed: Binding::from_code(((n >> 16) & 0x3FFF) as u16),
+            scroll_up_clicked: bin_to_bool(n, 30),
+            scroll_down_clicked: bin_to_bool(n, 31),
             mouse_position: Point::new(mouse_x, mouse_y),
         }
     }
 }
 
-struct RecorderEntry {
-    frame: u64,
-    snapshot: u64,
-}
-
+#[derive(Clone)]
 pub struct InputRecorder {
+    start_scene: String,
+    seed: u64,
+    frame_count: u64,
+    // Text recordings only stored the frames where the inputs changed, not how long they ran.
+    length_known: bool,
+    rewind_budget: usize,
     previous: u64,
-    queue: VecDeque<RecorderEntry>,
+    queue: VecDeque<ReplayEntry>,
+    state_hashes: VecDeque<StateHash>,
+    first_desync: Option<u64>,
 }
 
 impl InputRecorder {
     fn new() -> InputRecorder {
         InputRecorder {
+            start_scene: START_SCENE.to_owned(),
+            seed: rand::random(),
+            frame_count: 0,
+            length_known: true,
+            rewind_budget: REWIND_BUDGET,
             previous: 0,
             queue: VecDeque::new(),
+            state_hashes: VecDeque::new(),
+            first_desync: None,
         }
     }
 
     fn record(&mut self, frame: u64, snapshot: &InputSnapshot) {
+        self.frame_count = frame + 1;
         let snapshot = snapshot.encode();
         if self.previous == snapshot {
             return;
         }
         self.previous = snapshot;
-        self.queue.push_back(RecorderEntry { frame, snapshot });
+        self.queue.push_back(ReplayEntry { frame, snapshot });
+    }
+
+    // Loads a recording for playing back the inputs outside of an InputManager.
+    pub fn from_file(path: &Path, files: &FileManager) -> Result<InputRecorder> {
+        let mut recorder = InputRecorder::new();
+        recorder.load(path, files)?;
+        Ok(recorder)
+    }
+
+    pub fn start_scene(&self) -> &str {
+        &self.start_scene
     }
 
-    fn playback(&mut self, frame: u64) -> InputSnapshot {
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn playback(&mut self, frame: u64) -> InputSnapshot {
         if let Some(next) = self.queue.front() {
             if next.frame == frame {
                 self.previous = next.snapshot;
@@ -700,38 +945,95 @@ impl InputRecorder {
         InputSnapshot::decode(self.previous)
     }
 
-    fn save(&self, path: &Path) -> Result<()> {
-        let mut lines = Vec::new();
-        for entry in self.queue.iter() {
-            lines.push(format!("{},{}", entry.frame, entry.snapshot));
+    fn record_state(&mut self, frame: u64, state_hash: Option<u64>) {
+        if !frame.is_multiple_of(STATE_HASH_INTERVAL) {
+            return;
+        }
+        if let Some(hash) = state_hash {
+            self.state_hashes.push_back(StateHash { frame, hash });
         }
-        let text = lines.join("\n");
-        fs::write(path, text)?;
-        Ok(())
     }
 
-    fn load(&mut self, path: &Path, files: &FileManager) -> Result<()> {
-        self.previous = 0;
-        self.queue.clear();
-
-        let text = files
-            .read_to_string(path)
-            .map_err(|e| anyhow!("unable to load input snapshot record at {:?}: {}", path, e))?;
-
-        for line in text.lines() {
-            let line = line.trim();
-            if line.is_empty() {
+    fn verify_state(&mut self, frame: u64, state_hash: Option<u64>) {
+        while let Some(expected) = self.state_hashes.front() {
+            if expected.frame > frame {
+                return;
+            }
+            let expected = self.state_hashes.pop_front().expect("front exists");
+            if expected.frame < frame {
+                continue;
+            }
+            if state_hash == Some(expected.hash) {
                 continue;
             }
+            if self.first_desync.is_none() {
+                let actual = match state_hash {
+                    Some(hash) => format!("{:016x}", hash),
+                    None => "no state".to_owned(),
+                };
+                error!(
+                    "playback desynced at frame {}: expected state hash {:016x}, but got {}",
+                    frame, expected.hash, actual
+                );
+                self.first_desync = Some(frame);
+            }
+        }
+    }
 
-            let comma = line.find(',').context("missing comma")?;
-            let (frame, snapshot) = line.split_at(comma);
-            let snapshot = &snapshot[1..];
+    fn save(&self, path: &Path) -> Result<()> {
+        let replay = Replay {
+            header: ReplayHeader {
+                format_version: REPLAY_FORMAT_VERSION,
+                game_version: env!("CARGO_PKG_VERSION").to_owned(),
+                input_layout: INPUT_SNAPSHOT_LAYOUT,
+                start_scene: self.start_scene.clone(),
+                seed: self.seed,
+                frame_count: self.frame_count,
+                rewind_budget: self.rewind_budget as u64,
+            },
+            entries: self.queue.iter().cloned().collect(),
+            state_hashes: self.state_hashes.iter().cloned().collect(),
+        };
+        fs::write(path, replay.encode())?;
+        Ok(())
+    }
 
-            let frame = frame.parse()?;
-            let snapshot = snapshot.parse()?;
+    fn load(&mut self, path: &Path, files: &FileManager) -> Result<()> {
+        let bytes = files
+            .read(path)
+            .map_err(|e| anyhow!("unable to load input snapshot record at {:?}: {}", path, e))?;
+        let replay = Replay::decode(&bytes, START_SCENE)
+            .map_err(|e| anyhow!("unable to read replay {:?}: {}", path, e))?;
+
+        let header = replay.header;
+        info!("loaded replay {:?}: {:?}", path, header);
+        if header.game_version != env!("CARGO_PKG_VERSION") {
+            warn!(
+                "replay {:?} was recorded with game version {}, but this is {}",
+                path,
+                header.game_version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
 
-            self.queue.push_back(RecorderEntry { frame, snapshot });
+        self.start_scene = header.start_scene;
+        self.seed = header.seed;
+        self.frame_count = header.frame_count;
+        self.length_known = header.format_version != LEGACY_FORMAT_VERSION;
+        self.rewind_budget = header
+            .rewind_budget
+            .try_into()
+            .context("replay rewind budget is too large")?;
+        self.previous = 0;
+        self.queue.clear();
+        self.state_hashes = replay.state_hashes.into();
+        self.first_desync = None;
+        for entry in replay.entries {
+            let snapshot = InputSnapshot::migrate(header.input_layout, entry.snapshot)?;
+            self.queue.push_back(ReplayEntry {
+                frame: entry.frame,
+                snapshot,
+            });
         }
         Ok(())
     }
@@ -744,12 +1046,23 @@ pub enum RecordOption {
     Playback(PathBuf),
 }
 
+fn create_binary_hooks(bindings: &Bindings) -> SmallIntMap<BinaryInput, AnyOfInput> {
+    let mut binary_hooks = SmallIntMap::new();
+    for hook in all_binary_inputs() {
+        binary_hooks.insert(hook.clone(), create_input(hook, bindings));
+    }
+    binary_hooks
+}
+
 pub struct InputManager {
     state: InputState,
     previous_snapshot: Option<InputSnapshot>,
     binary_hooks: SmallIntMap<BinaryInput, AnyOfInput>,
     all_binary_hooks: Vec<BinaryInput>,
-    gilrs: Gilrs,
+    bindings: Bindings,
+    // Where bindings changed in the controls menu are saved.
+    saved_bindings: Box<dyn Storage>,
+    gilrs: Option<Gilrs>,
     current_gamepad: Option<gilrs::GamepadId>,
     record_option: RecordOption,
     recorder: InputRecorder,
@@ -769,16 +1082,19 @@ impl InputManager {
             recorder.load(Path::new(path), files)?;
         }
 
-        let mut binary_hooks = SmallIntMap::new();
+        let bindings = Bindings::default();
+        let binary_hooks = create_binary_hooks(&bindings);
         let all_binary_hooks = all_binary_inputs();
-        for hook in all_binary_hooks.iter() {
-            binary_hooks.insert(hook.clone(), create_input(hook.clone()));
-        }
 
-        debug!("Initializing gamepads");
-        let gilrs = Gilrs::new().map_err(|e| anyhow!("unable to load game library: {}", e))?;
+        // Playback never reads real devices, so it can run without any gamepad support.
+        let gilrs = if let RecordOption::Playback(_) = &record_option {
+            None
+        } else {
+            debug!("Initializing gamepads");
+            Some(Gilrs::new().map_err(|e| anyhow!("unable to load game library: {}", e))?)
+        };
         let mut current_gamepad = None;
-        for (id, gamepad) in gilrs.gamepads() {
+        for (id, gamepad) in gilrs.iter().flat_map(Gilrs::gamepads) {
             info!(
                 "Gamepad found: {} {} {:?}",
                 id,
@@ -795,6 +1111,8 @@ impl InputManager {
             previous_snapshot: None,
             binary_hooks,
             all_binary_hooks,
+            bindings,
+            saved_bindings: Box::new(NoopStorage {}),
             gilrs,
             current_gamepad,
             record_option,
@@ -802,15 +1120,57 @@ impl InputManager {
         })
     }
 
+    // Replaces the default bindings with the ones from a bindings file.
+    pub fn load_bindings(&mut self, path: &Path, files: &FileManager) -> Result<()> {
+        let bindings = Bindings::load(path, files)?;
+        self.set_bindings(bindings);
+        Ok(())
+    }
+
+    /*
+     * Applies any bindings saved from the controls menu, and saves future changes there.
+     * If they can't be read, the current bindings are kept, so the game can still be played.
+     */
+    pub fn load_saved_bindings(&mut self, storage: Box<dyn Storage>) {
+        match storage.load() {
+            Ok(Some(text)) => match self.bindings.parse(&text) {
+                Ok(bindings) => self.set_bindings(bindings),
+                Err(e) => warn!("unable to read saved bindings, keeping these: {}", e),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("unable to load saved bindings, keeping these: {}", e),
+        }
+        self.saved_bindings = storage;
+    }
+
+    // Uses new bindings, e.g. from the controls menu, and saves them.
+    pub fn save_bindings(&mut self, bindings: Bindings) {
+        if let Err(e) = self.saved_bindings.save(&bindings.encode()) {
+            warn!("unable to save bindings: {}", e);
+        }
+        self.set_bindings(bindings);
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.binary_hooks = create_binary_hooks(&bindings);
+        self.bindings = bindings;
+    }
+
     pub fn update(&mut self, frame: u64) -> InputSnapshot {
         if let RecordOption::Playback(_) = self.record_option {
             return self.recorder.playback(frame);
         }
 
-        while let Some(event) = self.gilrs.next_event() {
+        while let Some(event) = self.gilrs.as_mut().and_then(Gilrs::next_event) {
             self.handle_gilrs_event(event);
         }
-        self.gilrs.inc();
+        if let Some(gilrs) = self.gilrs.as_mut() {
+            gilrs.inc();
+        }
 
         for input in self.all_binary_hooks.iter() {
             self.binary_hooks
@@ -825,16 +1185,26 @@ impl InputManager {
             cancel_clicked: self.is_on(BinaryInput::Cancel),
             player_left_down: self.is_on(BinaryInput::PlayerLeft),
             player_right_down: self.is_on(BinaryInput::PlayerRight),
+            player_up_down: self.is_on(BinaryInput::PlayerUp),
             player_crouch_down: self.is_on(BinaryInput::PlayerCrouch),
             player_jump_clicked: self.is_on(BinaryInput::PlayerJumpTrigger),
             player_jump_down: self.is_on(BinaryInput::PlayerJumpDown),
+            player_jump_without_up_clicked: self.is_on(BinaryInput::PlayerJumpWithoutUpTrigger),
             menu_down_clicked: self.is_on(BinaryInput::MenuDown),
             menu_up_clicked: self.is_on(BinaryInput::MenuUp),
             menu_left_clicked: self.is_on(BinaryInput::MenuLeft),
             menu_right_clicked: self.is_on(BinaryInput::MenuRight),
             mouse_button_left_down: self.is_on(BinaryInput::MouseButtonLeft),
+            save_state_clicked: self.is_on(BinaryInput::SaveState),
+            load_state_clicked: self.is_on(BinaryInput::LoadState),
+            rewind_down: self.is_on(BinaryInput::Rewind),
+            player_walk_x: self.player_walk_x(),
+            pressed: self.state.last_pressed.take(),
+            scroll_up_clicked: self.state.scroll > 0,
+            scroll_down_clicked: self.state.scroll < 0,
             mouse_position: self.state.mouse_position,
         };
+        self.state.scroll = 0;
         if Some(snapshot) != self.previous_snapshot {
             debug!("{:?}", snapshot);
             self.previous_snapshot = Some(snapshot);
@@ -847,6 +1217,86 @@ impl InputManager {
         snapshot
     }
 
+    // The seed for all of the game's randomness. It's saved with recordings, so that
+    // playback sees exactly the same random numbers as the original run.
+    pub fn seed(&self) -> u64 {
+        self.recorder.seed
+    }
+
+    // The scene the game should start in. For playback, this is where the recording started.
+    pub fn start_scene(&self) -> &str {
+        &self.recorder.start_scene
+    }
+
+    // Starts the game somewhere else, e.g. "level:assets/levels/x.tmx". Recordings remember
+    // this, but during playback the recording's own start scene always wins.
+    pub fn set_start_scene(&mut self, scene: &str) {
+        if let RecordOption::Playback(_) = self.record_option {
+            warn!("ignoring start scene {} during playback", scene);
+            return;
+        }
+        self.recorder.start_scene = scene.to_owned();
+    }
+
+    // How many bytes levels may use for rewinding. For playback, this is what it was when
+    // the recording was made, since rewinding differently would change what happens.
+    pub fn rewind_budget(&self) -> usize {
+        self.recorder.rewind_budget
+    }
+
+    // Recordings remember this, but during playback the recording's own budget always wins.
+    pub fn set_rewind_budget(&mut self, budget: usize) {
+        if let RecordOption::Playback(_) = self.record_option {
+            warn!("ignoring rewind budget {} during playback", budget);
+            return;
+        }
+        self.recorder.rewind_budget = budget;
+    }
+
+    /*
+     * Should be called with a hash of the game state after every frame is simulated.
+     *
+     * When recording, the hash is saved periodically. During playback, it's checked
+     * against the hash from the recording, to catch the first frame that diverges.
+     */
+    pub fn check_state(&mut self, frame: u64, state_hash: Option<u64>) {
+        match self.record_option {
+            RecordOption::None => {}
+            RecordOption::Record(_) => self.recorder.record_state(frame, state_hash),
+            RecordOption::Playback(_) => self.recorder.verify_state(frame, state_hash),
+        }
+    }
+
+    // For playback, the first frame where the game state didn't match the recording.
+    pub fn first_desync(&self) -> Option<u64> {
+        self.recorder.first_desync
+    }
+
+    // For playback, the number of frames in the recording, if the recording knows it.
+    pub fn frame_count(&self) -> Option<u64> {
+        self.recorder
+            .length_known
+            .then_some(self.recorder.frame_count)
+    }
+
+    fn player_walk_x(&self) -> i8 {
+        stick_walk_x(&self.state, &self.bindings)
+    }
+
+    /*
+     * Handles a finger touching the screen, in window coordinates. Frontends without
+     * winit, or tests, can call this directly. Touches on the on-screen buttons trigger
+     * their actions, and other touches act like the mouse.
+     */
+    pub fn handle_touch(&mut self, id: u64, phase: TouchPhase, x: i32, y: i32) {
+        self.state.touch(id, phase, x, y);
+    }
+
+    // Draws the on-screen touch buttons, if the screen has been touched.
+    pub fn draw_touch_controls(&self, context: &mut RenderContext, font: &Font) {
+        self.state.touches.draw(context, font);
+    }
+
     fn is_on(&self, hook: BinaryInput) -> bool {
         self.binary_hooks
             .get(hook)
@@ -908,16 +1358,18 @@ impl InputManager {
                 self.state.set_window_size(*new_width, *new_height);
             }
             Event::KeyDown {
-                keycode: Some(key), ..
+                scancode: Some(key),
+                ..
             } => {
-                if let Some(key) = KeyboardKey::from_sdl_key(*key) {
+                if let Some(key) = KeyboardKey::from_scancode(*key) {
                     self.state.set_key_down(key);
                 }
             }
             Event::KeyUp {
-                keycode: Some(key), ..
+                scancode: Some(key),
+                ..
             } => {
-                if let Some(key) = KeyboardKey::from_sdl_key(*key) {
+                if let Some(key) = KeyboardKey::from_scancode(*key) {
                     self.state.set_key_up(key);
                 }
             }
@@ -939,6 +1391,9 @@ impl InputManager {
                 self.state.set_mouse_position(*x, *y);
                 self.state.set_mouse_button_up(MouseButton::Left);
             }
+            Event::MouseWheel { y, .. } => {
+                self.state.add_scroll(*y);
+            }
             Event::MouseMotion { x, y, .. } => {
                 // info!("mouse moved to {x}, {y}");
                 self.state.set_mouse_position(*x, *y);
@@ -950,7 +1405,7 @@ impl InputManager {
     #[cfg(feature = "winit")]
     pub fn handle_winit_event(&mut self, event: &winit::event::WindowEvent) {
         use winit::dpi::{PhysicalPosition, PhysicalSize};
-        use winit::event::{ElementState, KeyEvent, WindowEvent};
+        use winit::event::{ElementState, KeyEvent, MouseScrollDelta, WindowEvent};
         use winit::keyboard::PhysicalKey;
 
         match event {
@@ -994,6 +1449,24 @@ impl InputManager {
                 // info!("mouse moved to {x}, {y}");
                 self.state.set_mouse_position(x, y);
             }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let amount = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => *y as f32,
+                };
+                self.state.add_scroll(amount.signum() as i32);
+            }
+            WindowEvent::Touch(touch) => {
+                let phase = match touch.phase {
+                    winit::event::TouchPhase::Started => TouchPhase::Started,
+                    winit::event::TouchPhase::Moved => TouchPhase::Moved,
+                    winit::event::TouchPhase::Ended | winit::event::TouchPhase::Cancelled => {
+                        TouchPhase::Ended
+                    }
+                };
+                let PhysicalPosition { x, y } = touch.location;
+                self.handle_touch(touch.id, phase, x as i32, y as i32);
+            }
             WindowEvent::MouseInput {
                 state,
                 button: winit::event::MouseButton::Left,
@@ -1017,3 +1490,170 @@ impl Drop for InputManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_round_trip() {
+        let snapshot = InputSnapshot {
+            ok_clicked: false,
+            ok_down: false,
+            cancel_clicked: true,
+            player_left_down: true,
+            player_right_down: false,
+            player_up_down: true,
+            player_crouch_down: false,
+            player_jump_clicked: false,
+            player_jump_down: true,
+            player_jump_without_up_clicked: true,
+            menu_down_clicked: false,
+            menu_up_clicked: false,
+            menu_left_clicked: true,
+            menu_right_clicked: false,
+            mouse_button_left_down: false,
+            save_state_clicked: false,
+            load_state_clicked: false,
+            rewind_down: true,
+            player_walk_x: -42,
+            pressed: Some(Binding::Key(KeyboardKey::Space)),
+            scroll_up_clicked: false,
+            scroll_down_clicked: true,
+            mouse_position: Point::new(Pixels::new(319), Pixels::new(179)),
+        };
+        assert_eq!(InputSnapshot::decode(snapshot.encode()), snapshot);
+    }
+
+    #[test]
+    fn held_inputs_repeat() {
+        let mut state = InputState::new(RENDER_WIDTH as i32, RENDER_HEIGHT as i32, false);
+        let mut input = RepeatInput::from(KeyInput::new(KeyboardKey::Down));
+        let mut frames_on = Vec::new();
+        state.set_key_down(KeyboardKey::Down);
+        for frame in 0..(MENU_REPEAT_DELAY + MENU_REPEAT_INTERVAL * 2) {
+            input.update(&state);
+            if input.is_on() {
+                frames_on.push(frame);
+            }
+        }
+        assert_eq!(
+            frames_on,
+            vec![
+                0,
+                MENU_REPEAT_DELAY,
+                MENU_REPEAT_DELAY + MENU_REPEAT_INTERVAL
+            ]
+        );
+
+        // Letting go starts over.
+        state.set_key_up(KeyboardKey::Down);
+        input.update(&state);
+        assert!(!input.is_on());
+        state.set_key_down(KeyboardKey::Down);
+        input.update(&state);
+        assert!(input.is_on());
+    }
+
+    #[test]
+    fn stick_walks_past_the_threshold() {
+        let mut state = InputState::new(RENDER_WIDTH as i32, RENDER_HEIGHT as i32, false);
+        let bindings = Bindings::default();
+        let threshold = bindings.get(Action::Left).threshold;
+
+        // Inside the threshold, left isn't held, so the stick doesn't walk either.
+        state.set_joy_axis(JoystickAxis::Horizontal, -threshold);
+        assert_eq!(stick_walk_x(&state, &bindings), 0);
+        state.set_joy_axis(JoystickAxis::Horizontal, -(threshold + 0.001));
+        assert_eq!(stick_walk_x(&state, &bindings), -1);
+        state.set_joy_axis(JoystickAxis::Horizontal, 1.0);
+        assert_eq!(stick_walk_x(&state, &bindings), i8::MAX);
+
+        // Keys and the d-pad always walk at full speed.
+        state.set_joystick_button_down(JoystickButton::DPadLeft);
+        assert_eq!(stick_walk_x(&state, &bindings), 0);
+        state.set_joystick_button_up(JoystickButton::DPadLeft);
+        state.set_key_down(KeyboardKey::D);
+        assert_eq!(stick_walk_x(&state, &bindings), 0);
+    }
+
+    #[test]
+    fn touches_trigger_actions() {
+        let mut state = InputState::new(RENDER_WIDTH as i32 * 2, RENDER_HEIGHT as i32 * 2, true);
+        let bindings = Bindings::default();
+        let mut inputs: Vec<AnyOfInput> = [
+            BinaryInput::PlayerRight,
+            BinaryInput::PlayerJumpTrigger,
+            BinaryInput::PlayerJumpDown,
+            BinaryInput::MouseButtonLeft,
+        ]
+        .into_iter()
+        .map(|input| create_input(input, &bindings))
+        .collect();
+        let mut update = |state: &InputState| -> Vec<bool> {
+            inputs
+                .iter_mut()
+                .map(|input| {
+                    input.update(state);
+                    input.is_on()
+                })
+                .collect()
+        };
+
+        // Hold the right arrow and the jump button, in window coordinates at twice the size.
+        let right = (2 * 64, 2 * (RENDER_HEIGHT as i32 - 40));
+        let jump = (
+            2 * (RENDER_WIDTH as i32 - 16),
+            2 * (RENDER_HEIGHT as i32 - 28),
+        );
+        state.touch(1, TouchPhase::Started, right.0, right.1);
+        state.touch(2, TouchPhase::Started, jump.0, jump.1);
+        assert_eq!(update(&state), vec![true, true, true, false]);
+        assert_eq!(update(&state), vec![true, false, true, false]);
+
+        // Tapping anywhere else clicks like the mouse.
+        state.touch(2, TouchPhase::Ended, jump.0, jump.1);
+        state.touch(3, TouchPhase::Started, 320, 180);
+        assert_eq!(update(&state), vec![true, false, false, true]);
+        assert_eq!(
+            state.mouse_position,
+            Point::new(Pixels::new(160), Pixels::new(90))
+        );
+    }
+
+    #[test]
+    fn jumping_off_ladders_skips_up() {
+        let mut state = InputState::new(RENDER_WIDTH as i32, RENDER_HEIGHT as i32, false);
+        let bindings = Bindings::default();
+        let mut input = create_input(BinaryInput::PlayerJumpWithoutUpTrigger, &bindings);
+
+        // W is both up and jump by default, but Space is only jump.
+        state.set_key_down(KeyboardKey::W);
+        input.update(&state);
+        assert!(!input.is_on());
+        state.set_key_down(KeyboardKey::Space);
+        input.update(&state);
+        assert!(input.is_on());
+    }
+
+    #[test]
+    fn old_snapshots_migrate() {
+        // Jump and the left mouse button down, with the mouse at (300, 170), in the original
+        // layout with 16-bit mouse coordinates.
+        let old = (1 << 7) | (1 << 12) | (300 << 32) | (170 << 48);
+        let migrated = InputSnapshot::migrate(MOUSE_ONLY_INPUT_SNAPSHOT_LAYOUT, old).unwrap();
+        let snapshot = InputSnapshot::decode(migrated);
+        assert!(snapshot.player_jump_down);
+        assert!(snapshot.mouse_button_left_down);
+        assert_eq!(snapshot.pressed, None);
+        assert!(!snapshot.scroll_up_clicked);
+        assert!(!snapshot.scroll_down_clicked);
+        assert_eq!(snapshot.player_walk_x, 0);
+        assert!(!snapshot.player_up_down);
+        assert_eq!(
+            snapshot.mouse_position,
+            Point::new(Pixels::new(300), Pixels::new(170))
+        );
+        assert!(InputSnapshot::migrate(INPUT_SNAPSHOT_LAYOUT + 1, old).is_err());
+    }
+}
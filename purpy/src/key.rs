@@ -0,0 +1,103 @@
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+
+use crate::geometry::{Pixels, Point, Rect, Subpixels};
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::tilemap::{MapObject, TileIndex, TileMap};
+
+// A key lying around the level, waiting to be picked up.
+pub struct Key {
+    pub id: i32,
+    pub color: String,
+    area: Rect<Subpixels>,
+    tilemap: Rc<TileMap>,
+    tile_gid: TileIndex,
+}
+
+impl Key {
+    pub fn new(obj: &MapObject, tilemap: Rc<TileMap>) -> Result<Key> {
+        let tile_gid = obj.gid.context("key must have gid")?;
+        let color = obj
+            .properties
+            .color
+            .clone()
+            .context("key must have color")?;
+        Ok(Key {
+            id: obj.id,
+            color,
+            area: obj.position.into(),
+            tilemap,
+            tile_gid,
+        })
+    }
+
+    pub fn intersects(&self, player_rect: Rect<Subpixels>) -> bool {
+        self.area.intersects(player_rect)
+    }
+
+    pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, offset: Point<Subpixels>) {
+        self.tilemap
+            .draw_tile(context, self.tile_gid, layer, self.area + offset);
+    }
+
+    // Draws the key with its top right corner at the given point, for the HUD.
+    fn draw_icon(
+        &self,
+        context: &mut RenderContext,
+        layer: RenderLayer,
+        top_right: Point<Subpixels>,
+    ) {
+        let dest = Rect {
+            x: top_right.x - self.area.w,
+            y: top_right.y,
+            w: self.area.w,
+            h: self.area.h,
+        };
+        self.tilemap.draw_tile(context, self.tile_gid, layer, dest);
+    }
+}
+
+// The keys the player is carrying, in the order they were picked up.
+#[derive(Default)]
+pub struct Inventory {
+    keys: Vec<Key>,
+}
+
+impl Inventory {
+    pub fn add(&mut self, key: Key) {
+        self.keys.push(key);
+    }
+
+    // Uses up a key of the given color, if there is one.
+    pub fn take(&mut self, color: &str) -> bool {
+        let Some(i) = self.keys.iter().position(|key| key.color == color) else {
+            return false;
+        };
+        self.keys.remove(i);
+        true
+    }
+
+    pub fn ids(&self) -> Vec<i32> {
+        self.keys.iter().map(|key| key.id).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    // Draws the held keys in a row from right to left, starting at the given point.
+    pub fn draw(
+        &self,
+        context: &mut RenderContext,
+        layer: RenderLayer,
+        top_right: Point<Subpixels>,
+    ) {
+        let spacing = Pixels::new(2).as_subpixels();
+        let mut top_right = top_right;
+        for key in self.keys.iter() {
+            key.draw_icon(context, layer, top_right);
+            top_right.x -= key.area.w + spacing;
+        }
+    }
+}
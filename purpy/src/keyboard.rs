@@ -0,0 +1,478 @@
+/*
+ * Every key on a standard keyboard, independent of whether SDL or winit reported it.
+ *
+ * The names are the ones used in bindings files. Both frontends report physical keys, so
+ * they're named for where they are on a US QWERTY keyboard, whatever the actual layout is.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyboardKey {
+    Escape = 0,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+    Backquote,
+    Num0,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    CapsLock,
+    Semicolon,
+    Quote,
+    Enter,
+    LeftShift,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftCtrl,
+    LeftSuper,
+    LeftAlt,
+    Space,
+    RightAlt,
+    RightSuper,
+    Menu,
+    RightCtrl,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    NumLock,
+    Keypad0,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+    KeypadPeriod,
+    KeypadPlus,
+    KeypadMinus,
+    KeypadMultiply,
+    KeypadDivide,
+    KeypadEnter,
+}
+
+impl KeyboardKey {
+    pub const ALL: [KeyboardKey; 104] = [
+        KeyboardKey::Escape,
+        KeyboardKey::F1,
+        KeyboardKey::F2,
+        KeyboardKey::F3,
+        KeyboardKey::F4,
+        KeyboardKey::F5,
+        KeyboardKey::F6,
+        KeyboardKey::F7,
+        KeyboardKey::F8,
+        KeyboardKey::F9,
+        KeyboardKey::F10,
+        KeyboardKey::F11,
+        KeyboardKey::F12,
+        KeyboardKey::PrintScreen,
+        KeyboardKey::ScrollLock,
+        KeyboardKey::Pause,
+        KeyboardKey::Backquote,
+        KeyboardKey::Num0,
+        KeyboardKey::Num1,
+        KeyboardKey::Num2,
+        KeyboardKey::Num3,
+        KeyboardKey::Num4,
+        KeyboardKey::Num5,
+        KeyboardKey::Num6,
+        KeyboardKey::Num7,
+        KeyboardKey::Num8,
+        KeyboardKey::Num9,
+        KeyboardKey::Minus,
+        KeyboardKey::Equals,
+        KeyboardKey::Backspace,
+        KeyboardKey::Tab,
+        KeyboardKey::A,
+        KeyboardKey::B,
+        KeyboardKey::C,
+        KeyboardKey::D,
+        KeyboardKey::E,
+        KeyboardKey::F,
+        KeyboardKey::G,
+        KeyboardKey::H,
+        KeyboardKey::I,
+        KeyboardKey::J,
+        KeyboardKey::K,
+        KeyboardKey::L,
+        KeyboardKey::M,
+        KeyboardKey::N,
+        KeyboardKey::O,
+        KeyboardKey::P,
+        KeyboardKey::Q,
+        KeyboardKey::R,
+        KeyboardKey::S,
+        KeyboardKey::T,
+        KeyboardKey::U,
+        KeyboardKey::V,
+        KeyboardKey::W,
+        KeyboardKey::X,
+        KeyboardKey::Y,
+        KeyboardKey::Z,
+        KeyboardKey::LeftBracket,
+        KeyboardKey::RightBracket,
+        KeyboardKey::Backslash,
+        KeyboardKey::CapsLock,
+        KeyboardKey::Semicolon,
+        KeyboardKey::Quote,
+        KeyboardKey::Enter,
+        KeyboardKey::LeftShift,
+        KeyboardKey::Comma,
+        KeyboardKey::Period,
+        KeyboardKey::Slash,
+        KeyboardKey::RightShift,
+        KeyboardKey::LeftCtrl,
+        KeyboardKey::LeftSuper,
+        KeyboardKey::LeftAlt,
+        KeyboardKey::Space,
+        KeyboardKey::RightAlt,
+        KeyboardKey::RightSuper,
+        KeyboardKey::Menu,
+        KeyboardKey::RightCtrl,
+        KeyboardKey::Insert,
+        KeyboardKey::Delete,
+        KeyboardKey::Home,
+        KeyboardKey::End,
+        KeyboardKey::PageUp,
+        KeyboardKey::PageDown,
+        KeyboardKey::Up,
+        KeyboardKey::Down,
+        KeyboardKey::Left,
+        KeyboardKey::Right,
+        KeyboardKey::NumLock,
+        KeyboardKey::Keypad0,
+        KeyboardKey::Keypad1,
+        KeyboardKey::Keypad2,
+        KeyboardKey::Keypad3,
+        KeyboardKey::Keypad4,
+        KeyboardKey::Keypad5,
+        KeyboardKey::Keypad6,
+        KeyboardKey::Keypad7,
+        KeyboardKey::Keypad8,
+        KeyboardKey::Keypad9,
+        KeyboardKey::KeypadPeriod,
+        KeyboardKey::KeypadPlus,
+        KeyboardKey::KeypadMinus,
+        KeyboardKey::KeypadMultiply,
+        KeyboardKey::KeypadDivide,
+        KeyboardKey::KeypadEnter,
+    ];
+
+    pub fn name(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        KeyboardKey::ALL
+            .iter()
+            .find(|key| key.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    #[cfg(feature = "sdl2")]
+    pub fn from_scancode(key: sdl2::keyboard::Scancode) -> Option<Self> {
+        use sdl2::keyboard::Scancode;
+        Some(match key {
+            Scancode::Escape => KeyboardKey::Escape,
+            Scancode::F1 => KeyboardKey::F1,
+            Scancode::F2 => KeyboardKey::F2,
+            Scancode::F3 => KeyboardKey::F3,
+            Scancode::F4 => KeyboardKey::F4,
+            Scancode::F5 => KeyboardKey::F5,
+            Scancode::F6 => KeyboardKey::F6,
+            Scancode::F7 => KeyboardKey::F7,
+            Scancode::F8 => KeyboardKey::F8,
+            Scancode::F9 => KeyboardKey::F9,
+            Scancode::F10 => KeyboardKey::F10,
+            Scancode::F11 => KeyboardKey::F11,
+            Scancode::F12 => KeyboardKey::F12,
+            Scancode::PrintScreen => KeyboardKey::PrintScreen,
+            Scancode::ScrollLock => KeyboardKey::ScrollLock,
+            Scancode::Pause => KeyboardKey::Pause,
+            Scancode::Grave => KeyboardKey::Backquote,
+            Scancode::Num0 => KeyboardKey::Num0,
+            Scancode::Num1 => KeyboardKey::Num1,
+            Scancode::Num2 => KeyboardKey::Num2,
+            Scancode::Num3 => KeyboardKey::Num3,
+            Scancode::Num4 => KeyboardKey::Num4,
+            Scancode::Num5 => KeyboardKey::Num5,
+            Scancode::Num6 => KeyboardKey::Num6,
+            Scancode::Num7 => KeyboardKey::Num7,
+            Scancode::Num8 => KeyboardKey::Num8,
+            Scancode::Num9 => KeyboardKey::Num9,
+            Scancode::Minus => KeyboardKey::Minus,
+            Scancode::Equals => KeyboardKey::Equals,
+            Scancode::Backspace => KeyboardKey::Backspace,
+            Scancode::Tab => KeyboardKey::Tab,
+            Scancode::A => KeyboardKey::A,
+            Scancode::B => KeyboardKey::B,
+            Scancode::C => KeyboardKey::C,
+            Scancode::D => KeyboardKey::D,
+            Scancode::E => KeyboardKey::E,
+            Scancode::F => KeyboardKey::F,
+            Scancode::G => KeyboardKey::G,
+            Scancode::H => KeyboardKey::H,
+            Scancode::I => KeyboardKey::I,
+            Scancode::J => KeyboardKey::J,
+            Scancode::K => KeyboardKey::K,
+            Scancode::L => KeyboardKey::L,
+            Scancode::M => KeyboardKey::M,
+            Scancode::N => KeyboardKey::N,
+            Scancode::O => KeyboardKey::O,
+            Scancode::P => KeyboardKey::P,
+            Scancode::Q => KeyboardKey::Q,
+            Scancode::R => KeyboardKey::R,
+            Scancode::S => KeyboardKey::S,
+            Scancode::T => KeyboardKey::T,
+            Scancode::U => KeyboardKey::U,
+            Scancode::V => KeyboardKey::V,
+            Scancode::W => KeyboardKey::W,
+            Scancode::X => KeyboardKey::X,
+            Scancode::Y => KeyboardKey::Y,
+            Scancode::Z => KeyboardKey::Z,
+            Scancode::LeftBracket => KeyboardKey::LeftBracket,
+            Scancode::RightBracket => KeyboardKey::RightBracket,
+            Scancode::Backslash => KeyboardKey::Backslash,
+            Scancode::CapsLock => KeyboardKey::CapsLock,
+            Scancode::Semicolon => KeyboardKey::Semicolon,
+            Scancode::Apostrophe => KeyboardKey::Quote,
+            Scancode::Return => KeyboardKey::Enter,
+            Scancode::LShift => KeyboardKey::LeftShift,
+            Scancode::Comma => KeyboardKey::Comma,
+            Scancode::Period => KeyboardKey::Period,
+            Scancode::Slash => KeyboardKey::Slash,
+            Scancode::RShift => KeyboardKey::RightShift,
+            Scancode::LCtrl => KeyboardKey::LeftCtrl,
+            Scancode::LGui => KeyboardKey::LeftSuper,
+            Scancode::LAlt => KeyboardKey::LeftAlt,
+            Scancode::Space => KeyboardKey::Space,
+            Scancode::RAlt => KeyboardKey::RightAlt,
+            Scancode::RGui => KeyboardKey::RightSuper,
+            Scancode::Application => KeyboardKey::Menu,
+            Scancode::RCtrl => KeyboardKey::RightCtrl,
+            Scancode::Insert => KeyboardKey::Insert,
+            Scancode::Delete => KeyboardKey::Delete,
+            Scancode::Home => KeyboardKey::Home,
+            Scancode::End => KeyboardKey::End,
+            Scancode::PageUp => KeyboardKey::PageUp,
+            Scancode::PageDown => KeyboardKey::PageDown,
+            Scancode::Up => KeyboardKey::Up,
+            Scancode::Down => KeyboardKey::Down,
+            Scancode::Left => KeyboardKey::Left,
+            Scancode::Right => KeyboardKey::Right,
+            Scancode::NumLockClear => KeyboardKey::NumLock,
+            Scancode::Kp0 => KeyboardKey::Keypad0,
+            Scancode::Kp1 => KeyboardKey::Keypad1,
+            Scancode::Kp2 => KeyboardKey::Keypad2,
+            Scancode::Kp3 => KeyboardKey::Keypad3,
+            Scancode::Kp4 => KeyboardKey::Keypad4,
+            Scancode::Kp5 => KeyboardKey::Keypad5,
+            Scancode::Kp6 => KeyboardKey::Keypad6,
+            Scancode::Kp7 => KeyboardKey::Keypad7,
+            Scancode::Kp8 => KeyboardKey::Keypad8,
+            Scancode::Kp9 => KeyboardKey::Keypad9,
+            Scancode::KpPeriod => KeyboardKey::KeypadPeriod,
+            Scancode::KpPlus => KeyboardKey::KeypadPlus,
+            Scancode::KpMinus => KeyboardKey::KeypadMinus,
+            Scancode::KpMultiply => KeyboardKey::KeypadMultiply,
+            Scancode::KpDivide => KeyboardKey::KeypadDivide,
+            Scancode::KpEnter => KeyboardKey::KeypadEnter,
+            _ => return None,
+        })
+    }
+
+    #[cfg(feature = "winit")]
+    pub fn from_keycode(key: winit::keyboard::KeyCode) -> Option<Self> {
+        use winit::keyboard::KeyCode;
+        Some(match key {
+            KeyCode::Escape => KeyboardKey::Escape,
+            KeyCode::F1 => KeyboardKey::F1,
+            KeyCode::F2 => KeyboardKey::F2,
+            KeyCode::F3 => KeyboardKey::F3,
+            KeyCode::F4 => KeyboardKey::F4,
+            KeyCode::F5 => KeyboardKey::F5,
+            KeyCode::F6 => KeyboardKey::F6,
+            KeyCode::F7 => KeyboardKey::F7,
+            KeyCode::F8 => KeyboardKey::F8,
+            KeyCode::F9 => KeyboardKey::F9,
+            KeyCode::F10 => KeyboardKey::F10,
+            KeyCode::F11 => KeyboardKey::F11,
+            KeyCode::F12 => KeyboardKey::F12,
+            KeyCode::PrintScreen => KeyboardKey::PrintScreen,
+            KeyCode::ScrollLock => KeyboardKey::ScrollLock,
+            KeyCode::Pause => KeyboardKey::Pause,
+            KeyCode::Backquote => KeyboardKey::Backquote,
+            KeyCode::Digit0 => KeyboardKey::Num0,
+            KeyCode::Digit1 => KeyboardKey::Num1,
+            KeyCode::Digit2 => KeyboardKey::Num2,
+            KeyCode::Digit3 => KeyboardKey::Num3,
+            KeyCode::Digit4 => KeyboardKey::Num4,
+            KeyCode::Digit5 => KeyboardKey::Num5,
+            KeyCode::Digit6 => KeyboardKey::Num6,
+            KeyCode::Digit7 => KeyboardKey::Num7,
+            KeyCode::Digit8 => KeyboardKey::Num8,
+            KeyCode::Digit9 => KeyboardKey::Num9,
+            KeyCode::Minus => KeyboardKey::Minus,
+            KeyCode::Equal => KeyboardKey::Equals,
+            KeyCode::Backspace => KeyboardKey::Backspace,
+            KeyCode::Tab => KeyboardKey::Tab,
+            KeyCode::KeyA => KeyboardKey::A,
+            KeyCode::KeyB => KeyboardKey::B,
+            KeyCode::KeyC => KeyboardKey::C,
+            KeyCode::KeyD => KeyboardKey::D,
+            KeyCode::KeyE => KeyboardKey::E,
+            KeyCode::KeyF => KeyboardKey::F,
+            KeyCode::KeyG => KeyboardKey::G,
+            KeyCode::KeyH => KeyboardKey::H,
+            KeyCode::KeyI => KeyboardKey::I,
+            KeyCode::KeyJ => KeyboardKey::J,
+            KeyCode::KeyK => KeyboardKey::K,
+            KeyCode::KeyL => KeyboardKey::L,
+            KeyCode::KeyM => KeyboardKey::M,
+            KeyCode::KeyN => KeyboardKey::N,
+            KeyCode::KeyO => KeyboardKey::O,
+            KeyCode::KeyP => KeyboardKey::P,
+            KeyCode::KeyQ => KeyboardKey::Q,
+            KeyCode::KeyR => KeyboardKey::R,
+            KeyCode::KeyS => KeyboardKey::S,
+            KeyCode::KeyT => KeyboardKey::T,
+            KeyCode::KeyU => KeyboardKey::U,
+            KeyCode::KeyV => KeyboardKey::V,
+            KeyCode::KeyW => KeyboardKey::W,
+            KeyCode::KeyX => KeyboardKey::X,
+            KeyCode::KeyY => KeyboardKey::Y,
+            KeyCode::KeyZ => KeyboardKey::Z,
+            KeyCode::BracketLeft => KeyboardKey::LeftBracket,
+            KeyCode::BracketRight => KeyboardKey::RightBracket,
+            KeyCode::Backslash => KeyboardKey::Backslash,
+            KeyCode::CapsLock => KeyboardKey::CapsLock,
+            KeyCode::Semicolon => KeyboardKey::Semicolon,
+            KeyCode::Quote => KeyboardKey::Quote,
+            KeyCode::Enter => KeyboardKey::Enter,
+            KeyCode::ShiftLeft => KeyboardKey::LeftShift,
+            KeyCode::Comma => KeyboardKey::Comma,
+            KeyCode::Period => KeyboardKey::Period,
+            KeyCode::Slash => KeyboardKey::Slash,
+            KeyCode::ShiftRight => KeyboardKey::RightShift,
+            KeyCode::ControlLeft => KeyboardKey::LeftCtrl,
+            KeyCode::SuperLeft => KeyboardKey::LeftSuper,
+            KeyCode::AltLeft => KeyboardKey::LeftAlt,
+            KeyCode::Space => KeyboardKey::Space,
+            KeyCode::AltRight => KeyboardKey::RightAlt,
+            KeyCode::SuperRight => KeyboardKey::RightSuper,
+            KeyCode::ContextMenu => KeyboardKey::Menu,
+            KeyCode::ControlRight => KeyboardKey::RightCtrl,
+            KeyCode::Insert => KeyboardKey::Insert,
+            KeyCode::Delete => KeyboardKey::Delete,
+            KeyCode::Home => KeyboardKey::Home,
+            KeyCode::End => KeyboardKey::End,
+            KeyCode::PageUp => KeyboardKey::PageUp,
+            KeyCode::PageDown => KeyboardKey::PageDown,
+            KeyCode::ArrowUp => KeyboardKey::Up,
+            KeyCode::ArrowDown => KeyboardKey::Down,
+            KeyCode::ArrowLeft => KeyboardKey::Left,
+            KeyCode::ArrowRight => KeyboardKey::Right,
+            KeyCode::NumLock => KeyboardKey::NumLock,
+            KeyCode::Numpad0 => KeyboardKey::Keypad0,
+            KeyCode::Numpad1 => KeyboardKey::Keypad1,
+            KeyCode::Numpad2 => KeyboardKey::Keypad2,
+            KeyCode::Numpad3 => KeyboardKey::Keypad3,
+            KeyCode::Numpad4 => KeyboardKey::Keypad4,
+            KeyCode::Numpad5 => KeyboardKey::Keypad5,
+            KeyCode::Numpad6 => KeyboardKey::Keypad6,
+            KeyCode::Numpad7 => KeyboardKey::Keypad7,
+            KeyCode::Numpad8 => KeyboardKey::Keypad8,
+            KeyCode::Numpad9 => KeyboardKey::Keypad9,
+            KeyCode::NumpadDecimal => KeyboardKey::KeypadPeriod,
+            KeyCode::NumpadAdd => KeyboardKey::KeypadPlus,
+            KeyCode::NumpadSubtract => KeyboardKey::KeypadMinus,
+            KeyCode::NumpadMultiply => KeyboardKey::KeypadMultiply,
+            KeyCode::NumpadDivide => KeyboardKey::KeypadDivide,
+            KeyCode::NumpadEnter => KeyboardKey::KeypadEnter,
+            _ => return None,
+        })
+    }
+}
+
+impl From<KeyboardKey> for usize {
+    fn from(value: KeyboardKey) -> Self {
+        value as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for key in KeyboardKey::ALL {
+            assert_eq!(KeyboardKey::from_name(&key.name()), Some(key));
+        }
+        assert_eq!(KeyboardKey::from_name("space"), Some(KeyboardKey::Space));
+        assert_eq!(KeyboardKey::from_name("Num1"), Some(KeyboardKey::Num1));
+        assert_eq!(KeyboardKey::from_name("Hyper"), None);
+    }
+}
@@ -5,97 +5,62 @@ use std::mem;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use log::{debug, info, log_enabled};
 use num_traits::Zero;
 
-use crate::constants::{
-    COYOTE_TIME, FALL_ACCELERATION, JUMP_ACCELERATION, JUMP_GRACE_TIME, JUMP_INITIAL_SPEED,
-    PLAYER_DEFAULT_X, PLAYER_DEFAULT_Y, SLIDE_SPEED_DECELERATION, SPRING_BOUNCE_DURATION,
-    SPRING_BOUNCE_VELOCITY, SPRING_JUMP_DURATION, SPRING_JUMP_VELOCITY, TARGET_WALK_SPEED,
-    TOAST_HEIGHT, TOAST_SPEED, TOAST_TIME, VIEWPORT_PAN_SPEED, WALK_SPEED_ACCELERATION,
-    WALK_SPEED_DECELERATION, WALL_JUMP_HORIZONTAL_SPEED, WALL_JUMP_VERTICAL_SPEED,
-    WALL_SLIDE_SPEED, WALL_SLIDE_TIME, WALL_STICK_TIME,
-};
+use crate::binary::{BinaryReader, BinaryWriter, Fnv1a};
+use crate::checkpoint::Checkpoint;
+use crate::constants::{REWIND_BUDGET, TOAST_HEIGHT, TOAST_SPEED, TOAST_TIME, VIEWPORT_PAN_SPEED};
 use crate::door::Door;
+use crate::enemy::{Enemy, EnemyContact};
 use crate::filemanager::FileManager;
 use crate::font::Font;
 use crate::geometry::{Pixels, Point, Rect, Subpixels};
+use crate::ghost::{Ghost, GhostRun};
 use crate::imagemanager::ImageLoader;
 use crate::inputmanager::InputSnapshot;
-use crate::platform::{Bagel, Button, Conveyor, MovingPlatform, Platform, PlatformType, Spring};
+use crate::key::{Inventory, Key};
+use crate::movement::{Movement, MovementState};
+use crate::physics::PhysicsConfig;
+use crate::platform::{Bagel, Button, Conveyor, Lock, MovingPlatform, Platform, Spring};
 use crate::player::{Player, PlayerState};
+use crate::progress::LevelProgress;
 use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::rewind::RewindBuffer;
 use crate::scene::{Scene, SceneResult};
-use crate::smallintset::SmallIntSet;
 use crate::soundmanager::{Sound, SoundManager};
 use crate::star::Star;
 use crate::switchstate::SwitchState;
-use crate::tilemap::{TileIndex, TileMap};
-use crate::tileset::TileProperties;
-use crate::utils::{cmp_in_direction, Color, Direction};
+use crate::tilemap::TileMap;
+use crate::turret::{Projectile, Turret};
+use crate::utils::{Color, Direction};
 use crate::warp::Warp;
 
-struct PlatformIntersectionResult {
-    offset: Subpixels,
-    platforms: SmallIntSet<usize>,
-}
-
-// The results of trying to move.
-struct TryMovePlayerResult {
-    offset: Subpixels,
-    tile_ids: SmallIntSet<TileIndex>,
-    platforms: SmallIntSet<usize>,
-}
+const SAVE_STATE_MAGIC: &[u8; 8] = b"PURPYSAV";
 
-struct MoveAndCheckResult {
-    on_ground: bool,
-    on_tile_ids: SmallIntSet<TileIndex>,
-    on_platforms: SmallIntSet<usize>,
-    hit_ceiling: bool,
-    against_wall: bool,
-    crushed_by_platform: bool,
-    stuck_in_wall: bool,
-}
-
-struct MovePlayerXResult {
-    pushing_against_wall: bool,
-    stuck_in_wall: bool,
-    crushed_by_platform: bool,
-}
+// Bump this whenever the layout of saved states changes. They're only meant to be
+// restored by the same build that saved them, so older versions are just rejected.
+const SAVE_STATE_VERSION: u16 = 8;
 
-struct MovePlayerYResult {
-    on_ground: bool,
-    _platforms: SmallIntSet<usize>,
-    _tile_ids: SmallIntSet<TileIndex>,
-    stuck_in_wall: bool,
-    crushed_by_platform: bool,
-}
-
-#[derive(Debug, Clone, Copy)]
-struct PlayerMovementResult {
-    on_ground: bool,
-    pushing_against_wall: bool,
-    jump_down: bool,
-    jump_triggered: bool,
-    crouch_down: bool,
-    _stuck_in_wall: bool,
-    crushed_by_platform: bool,
+// What the level goes back to when the player dies, as of the last checkpoint they touched.
+struct RespawnPoint {
+    checkpoint: usize,
+    // The ids of the stars that hadn't been collected yet.
+    stars: Vec<i32>,
+    star_count: i32,
+    switches: SwitchState,
+    // Every platform's saved state, so that buttons go back to matching the switches.
+    platforms: Vec<u8>,
 }
 
 pub struct Level {
     _name: String,
     map_path: PathBuf,
     map: Rc<TileMap>,
+    physics: PhysicsConfig,
     player: Player,
-
-    wall_stick_counter: i32,
-    wall_stick_facing_right: bool,
-    wall_slide_counter: i32,
-
-    coyote_counter: i32,
-    jump_grace_counter: i32,
-    spring_counter: i32,
+    movement_state: MovementState,
 
     map_offset: Point<Subpixels>,
     toast_text: String,
@@ -105,25 +70,43 @@ pub struct Level {
     // platforms, stars, and doors
     platforms: Vec<Platform>,
     stars: Vec<Star>,
+    keys: Vec<Key>,
     doors: Vec<Door>,
     warps: Vec<Warp>,
+    checkpoints: Vec<Checkpoint>,
+    enemies: Vec<Enemy>,
+    turrets: Vec<Turret>,
+    projectiles: Vec<Projectile>,
+    respawn_point: Option<RespawnPoint>,
 
     star_count: i32,
-    current_platform: Option<usize>,
-    current_slopes: SmallIntSet<TileIndex>,
+    inventory: Inventory,
     switches: SwitchState,
-    current_switch_tiles: SmallIntSet<TileIndex>,
     current_door: Option<usize>,
 
+    // The state at the start of each recent frame, for rewinding.
+    rewind: RewindBuffer,
+
+    // A player from a previous run, racing alongside this one.
+    ghost: Option<GhostRun>,
+
     previous_transition: String,
 }
 
-fn inc_player_x(player: &mut Player, offset: Subpixels) {
-    player.position.x += offset;
+fn write_object_ids(writer: &mut BinaryWriter, ids: &[i32]) {
+    writer.write_varint(ids.len() as u64);
+    for id in ids {
+        writer.write_i32(*id);
+    }
 }
 
-fn inc_player_y(player: &mut Player, offset: Subpixels) {
-    player.position.y += offset;
+fn read_object_ids(reader: &mut BinaryReader) -> Result<Vec<i32>> {
+    let count = reader.read_varint()?;
+    let mut ids = Vec::new();
+    for _ in 0..count {
+        ids.push(reader.read_i32()?);
+    }
+    Ok(ids)
 }
 
 impl Level {
@@ -132,14 +115,6 @@ impl Level {
         files: &FileManager,
         images: &mut dyn ImageLoader,
     ) -> Result<Level> {
-        let wall_stick_counter = WALL_STICK_TIME;
-        let wall_stick_facing_right = false;
-        let wall_slide_counter = WALL_SLIDE_TIME;
-
-        let coyote_counter = COYOTE_TIME;
-        let jump_grace_counter = 0;
-        let spring_counter = 0;
-
         let toast_position = TOAST_HEIGHT * -1;
         let toast_counter = TOAST_TIME;
 
@@ -151,21 +126,24 @@ impl Level {
         let toast_text = name.clone();
         let map_offset = Point::zero();
         let map = Rc::new(TileMap::from_file(map_path, files, images)?);
-        let mut player = Player::new(files, images)?;
-        player.position.x = PLAYER_DEFAULT_X;
-        player.position.y = PLAYER_DEFAULT_Y;
+        let physics = PhysicsConfig::for_map(&map.properties, files)
+            .with_context(|| format!("invalid physics in {:?}", map_path))?;
+
+        let player = Player::for_map(&map, files, images)?;
+        let movement_state = MovementState::new(&physics);
 
         let star_count = 0;
         let switches = SwitchState::new();
-        let current_switch_tiles = SmallIntSet::new();
-        let current_slopes = SmallIntSet::new();
-        let current_platform = None;
         let current_door = None;
 
         let mut platforms: Vec<Platform> = Vec::new();
         let mut stars = Vec::new();
+        let mut keys = Vec::new();
         let mut doors = Vec::new();
         let mut warps = Vec::new();
+        let mut checkpoints = Vec::new();
+        let mut enemies = Vec::new();
+        let mut turrets = Vec::new();
 
         for obj in map.objects.iter() {
             if obj.properties.platform {
@@ -183,13 +161,8 @@ impl Level {
             if obj.properties.button {
                 platforms.push(Button::new(obj, map.clone(), images)?);
             }
-            if obj.properties.spawn {
-                player.position.x = obj.position.x.as_subpixels();
-                player.position.y = obj.position.y.as_subpixels();
-                player.delta.x = obj.properties.dx.as_subpixels();
-                player.delta.y = obj.properties.dy.as_subpixels();
-                player.state = PlayerState::Jumping;
-                player.facing_right = !obj.properties.facing_left;
+            if obj.properties.lock {
+                platforms.push(Lock::new(obj, map.clone())?);
             }
             if obj.properties.door {
                 doors.push(Door::new(obj, images)?);
@@ -197,603 +170,70 @@ impl Level {
             if obj.properties.star {
                 stars.push(Star::new(obj, map.clone())?);
             }
+            if obj.properties.key {
+                keys.push(Key::new(obj, map.clone())?);
+            }
             if obj.properties.warp.is_some() {
                 warps.push(Warp::new(obj)?);
             }
+            if obj.properties.checkpoint {
+                checkpoints.push(Checkpoint::new(obj)?);
+            }
+            if obj.properties.enemy.is_some() {
+                enemies.push(Enemy::new(obj, &map, files, images)?);
+            }
+            if obj.properties.turret {
+                turrets.push(Turret::new(obj, map.clone())?);
+            }
         }
 
         let map_path = map_path.to_owned();
+        let rewind = RewindBuffer::new(REWIND_BUDGET);
         let previous_transition = "".to_owned();
 
         Ok(Level {
             _name: name,
             map_path,
             map,
+            physics,
             player,
-            wall_stick_counter,
-            wall_stick_facing_right,
-            wall_slide_counter,
-            coyote_counter,
-            jump_grace_counter,
-            spring_counter,
+            movement_state,
             map_offset,
             toast_text,
             toast_position,
             toast_counter,
             platforms,
             stars,
+            keys,
             doors,
             warps,
+            checkpoints,
+            enemies,
+            turrets,
+            projectiles: Vec::new(),
+            respawn_point: None,
             star_count,
-            current_platform,
-            current_slopes,
+            inventory: Inventory::default(),
             switches,
-            current_switch_tiles,
             current_door,
+            rewind,
+            ghost: None,
             previous_transition,
         })
     }
 }
 
 impl Level {
-    /*
-     * Movement.
-     */
-
-    fn update_player_trajectory_x(&mut self, inputs: &InputSnapshot) {
-        if matches!(self.player.state, PlayerState::Crouching) {
-            match self.player.delta.x.cmp(&Subpixels::zero()) {
-                Ordering::Greater => {
-                    self.player.delta.x =
-                        (self.player.delta.x - SLIDE_SPEED_DECELERATION).max(Subpixels::zero());
-                }
-                Ordering::Less => {
-                    self.player.delta.x =
-                        (self.player.delta.x + SLIDE_SPEED_DECELERATION).min(Subpixels::zero());
-                }
-                Ordering::Equal => {}
-            }
-            return;
-        }
-
-        // Apply controller input.
-        let mut target_dx = Subpixels::zero();
-        if inputs.player_left_down && !inputs.player_right_down {
-            target_dx = TARGET_WALK_SPEED * -1;
-        } else if inputs.player_right_down && !inputs.player_left_down {
-            target_dx = TARGET_WALK_SPEED;
-        }
-
-        // Change the velocity toward the target velocity.
-        match self.player.delta.x.cmp(&Subpixels::zero()) {
-            Ordering::Greater => {
-                // We're facing right.
-                if target_dx > self.player.delta.x {
-                    self.player.delta.x += WALK_SPEED_ACCELERATION;
-                    self.player.delta.x = self.player.delta.x.min(target_dx);
-                }
-                if target_dx < self.player.delta.x {
-                    self.player.delta.x -= WALK_SPEED_DECELERATION;
-                    self.player.delta.x = self.player.delta.x.max(target_dx);
-                }
-            }
-            Ordering::Less => {
-                // We're facing left.
-                if target_dx > self.player.delta.x {
-                    self.player.delta.x += WALK_SPEED_DECELERATION;
-                    self.player.delta.x = self.player.delta.x.min(target_dx);
-                }
-                if target_dx < self.player.delta.x {
-                    self.player.delta.x -= WALK_SPEED_ACCELERATION;
-                    self.player.delta.x = self.player.delta.x.max(target_dx);
-                }
-            }
-            Ordering::Equal => {
-                // We're stopped.
-                if target_dx > self.player.delta.x {
-                    self.player.delta.x += WALK_SPEED_ACCELERATION;
-                    self.player.delta.x = self.player.delta.x.min(target_dx);
-                }
-                if target_dx < self.player.delta.x {
-                    self.player.delta.x -= WALK_SPEED_ACCELERATION;
-                    self.player.delta.x = self.player.delta.x.max(target_dx);
-                }
-            }
-        }
-    }
-
-    fn update_player_trajectory_y(&mut self) {
-        let gravity = self.map.get_gravity();
-        match self.player.state {
-            PlayerState::Standing | PlayerState::Crouching => {
-                // Fall at least one pixel so that we hit the ground again.
-                self.player.delta.y = self.player.delta.y.max(Subpixels::new(1));
-            }
-            PlayerState::Jumping => {
-                // Apply gravity.
-                if self.player.delta.y < gravity {
-                    self.player.delta.y += JUMP_ACCELERATION;
-                }
-                self.player.delta.y = self.player.delta.y.min(gravity);
-            }
-            PlayerState::Falling => {
-                // Apply gravity.
-                if self.player.delta.y < gravity {
-                    self.player.delta.y += FALL_ACCELERATION;
-                }
-                self.player.delta.y = self.player.delta.y.min(gravity);
-            }
-            PlayerState::WallSliding => {
-                // When you first grab the wall, don't start sliding for a while.
-                if self.wall_slide_counter > 0 {
-                    self.wall_slide_counter -= 1;
-                    self.player.delta.y = Subpixels::zero();
-                } else {
-                    self.player.delta.y = WALL_SLIDE_SPEED;
-                }
-            }
-            PlayerState::Stopped => {}
-        }
-    }
-
-    fn find_platform_intersections(
-        &self,
-        player_rect: Rect<Subpixels>,
-        direction: Direction,
-        is_backwards: bool,
-    ) -> PlatformIntersectionResult {
-        let mut result = PlatformIntersectionResult {
-            offset: Subpixels::zero(),
-            platforms: SmallIntSet::new(),
-        };
-        for (i, platform) in self.platforms.iter().enumerate() {
-            let distance = platform.try_move_to(player_rect, direction, is_backwards);
-            if distance.is_zero() {
-                continue;
-            }
-
-            match cmp_in_direction(distance, result.offset, direction) {
-                Ordering::Less => {
-                    result.offset = distance;
-                    result.platforms = SmallIntSet::new();
-                    result.platforms.insert(i);
-                }
-                Ordering::Equal => {
-                    result.platforms.insert(i);
-                }
-                Ordering::Greater => {}
-            }
-        }
-        result
-    }
-
-    // Returns how far this player needs to move in direction to not intersect, in sub-pixels.
-    fn try_move_player(&self, direction: Direction, is_backwards: bool) -> TryMovePlayerResult {
-        let player_rect = self.player.get_target_bounds_rect(Some(direction));
-
-        let map_result = self
-            .map
-            .try_move_to(player_rect, direction, &self.switches, is_backwards);
-        let platform_result =
-            self.find_platform_intersections(player_rect, direction, is_backwards);
-
-        match cmp_in_direction(platform_result.offset, map_result.hard_offset, direction) {
-            Ordering::Less | Ordering::Equal => TryMovePlayerResult {
-                offset: platform_result.offset,
-                platforms: platform_result.platforms,
-                tile_ids: SmallIntSet::new(),
-            },
-            Ordering::Greater => TryMovePlayerResult {
-                offset: map_result.hard_offset,
-                platforms: SmallIntSet::new(),
-                tile_ids: map_result.tile_ids,
-            },
-        }
-    }
-
-    // Returns whether the first move hit a wall or platform.
-    fn move_and_check(
-        &mut self,
-        forward: Direction,
-        apply_offset: fn(&mut Player, Subpixels) -> (),
-    ) -> MoveAndCheckResult {
-        let move_result1 = self.try_move_player(forward, false);
-        apply_offset(&mut self.player, move_result1.offset);
-
-        // Try the opposite direction.
-        let move_result2 = self.try_move_player(forward.opposite(), true);
-        let offset = move_result2.offset;
-        apply_offset(&mut self.player, offset);
-
-        let mut hit_solid_platform1 = false;
-        for platform in move_result1.platforms.iter() {
-            if self.platforms[*platform].is_solid() {
-                hit_solid_platform1 = true;
-            }
-        }
-        let mut hit_solid_platform2 = false;
-        for platform in move_result2.platforms.iter() {
-            if self.platforms[*platform].is_solid() {
-                hit_solid_platform2 = true;
-            }
-        }
-
-        let mut result = MoveAndCheckResult {
-            on_ground: false,
-            on_tile_ids: SmallIntSet::new(),
-            on_platforms: SmallIntSet::new(),
-            hit_ceiling: false,
-            against_wall: false,
-            crushed_by_platform: false,
-            stuck_in_wall: false,
-        };
-        match forward {
-            Direction::Down => {
-                result.on_ground = !move_result1.offset.is_zero();
-                result.on_tile_ids = move_result1.tile_ids;
-                result.on_platforms = move_result1.platforms;
-            }
-            Direction::Up => {
-                // If we're traveling up, then if we hit something below, it's not the ground,
-                // unless we're standing on a platform.
-                if !matches!(self.player.state, PlayerState::Jumping)
-                    && !matches!(self.player.state, PlayerState::Falling)
-                {
-                    result.on_ground = !move_result2.offset.is_zero();
-                }
-                result.hit_ceiling = !move_result1.offset.is_zero();
-                result.on_tile_ids = move_result2.tile_ids;
-                result.on_platforms = move_result2.platforms;
-            }
-            Direction::Left | Direction::Right => {
-                result.against_wall = !move_result1.offset.is_zero()
-            }
-        }
-
-        // See if we're crushed.
-        if !offset.is_zero() {
-            let crush_check = self.try_move_player(forward, false);
-            if !crush_check.offset.is_zero() {
-                let crushed = hit_solid_platform1 || hit_solid_platform2;
-                if crushed {
-                    result.crushed_by_platform = true;
-                } else {
-                    result.stuck_in_wall = true;
-                }
-            }
-        }
-
-        result
-    }
-
-    fn move_player_x(&mut self, inputs: &InputSnapshot) -> MovePlayerXResult {
-        let mut dx = self.player.delta.x;
-        if let Some(current_platform) = self.current_platform {
-            dx += self.platforms[current_platform].dx();
-        }
-        self.player.position.x += dx;
-
-        let (move_result, pushing) =
-            if dx < Subpixels::zero() || (dx.is_zero() && !self.player.facing_right) {
-                // Moving left.
-                let move_result = self.move_and_check(Direction::Left, inc_player_x);
-                let pushing = inputs.player_left_down;
-                (move_result, pushing)
-            } else {
-                // Moving right.
-                let move_result = self.move_and_check(Direction::Right, inc_player_x);
-                let pushing = inputs.player_right_down;
-                (move_result, pushing)
-            };
-
-        let result = MovePlayerXResult {
-            pushing_against_wall: pushing && move_result.against_wall,
-            crushed_by_platform: move_result.crushed_by_platform,
-            stuck_in_wall: move_result.stuck_in_wall,
-        };
-
-        // If you're against the wall, you're stopped.
-        if result.pushing_against_wall {
-            self.player.delta.x = Subpixels::zero();
-        }
-
-        result
-    }
-
-    fn get_slope_dy(&self) -> Subpixels {
-        let mut slope_fall = Subpixels::zero();
-        for slope_id in self.current_slopes.iter() {
-            let slope = self.map.get_slope(*slope_id).expect("must be valid");
-            let left_y = slope.left_y;
-            let right_y = slope.right_y;
-            let mut fall: Subpixels = Subpixels::zero();
-            if self.player.delta.x > Subpixels::zero()
-                || (self.player.delta.x.is_zero() && self.player.facing_right)
-            {
-                // The player is facing right.
-                if right_y > left_y {
-                    fall = right_y - left_y;
-                }
-            } else {
-                // The player is facing left.
-                if left_y > right_y {
-                    fall = left_y - right_y;
-                }
-            }
-            slope_fall = slope_fall.max(fall);
-        }
-        slope_fall
-    }
-
-    fn move_player_y(&mut self, sounds: &mut SoundManager) -> MovePlayerYResult {
-        let mut dy = self.player.delta.y;
-        if let Some(current_platform) = self.current_platform {
-            // This could be positive or negative.
-            dy += self.platforms[current_platform].dy();
-        }
-
-        // If you're on a slope, make sure to fall at least the slope amount.
-        if dy >= Subpixels::zero() {
-            dy = dy.max(self.get_slope_dy());
-        }
-
-        self.player.position.y += dy;
-
-        if dy <= Subpixels::zero() {
-            // Moving up.
-            let move_result = self.move_and_check(Direction::Up, inc_player_y);
-            if move_result.hit_ceiling {
-                self.player.delta.y = Subpixels::zero();
-            }
-
-            self.handle_slopes(&move_result.on_tile_ids);
-            self.handle_current_platforms(&move_result.on_platforms);
-
-            MovePlayerYResult {
-                on_ground: move_result.on_ground,
-                crushed_by_platform: move_result.crushed_by_platform,
-                stuck_in_wall: move_result.stuck_in_wall,
-                _platforms: SmallIntSet::new(),
-                _tile_ids: SmallIntSet::new(),
-            }
-        } else {
-            // Moving down.
-            let move_result = self.move_and_check(Direction::Down, inc_player_y);
-
-            self.handle_spikes(&move_result.on_tile_ids);
-            self.handle_switch_tiles(&move_result.on_tile_ids, sounds);
-            self.handle_slopes(&move_result.on_tile_ids);
-            self.handle_current_platforms(&move_result.on_platforms);
-
-            MovePlayerYResult {
-                on_ground: move_result.on_ground,
-                _tile_ids: move_result.on_tile_ids,
-                _platforms: move_result.on_platforms,
-                crushed_by_platform: move_result.crushed_by_platform,
-                stuck_in_wall: move_result.stuck_in_wall,
-            }
-        }
-    }
-
-    fn handle_slopes(&mut self, tiles: &SmallIntSet<TileIndex>) {
-        self.current_slopes.clear();
-        for tile_id in tiles.iter() {
-            if let Some(TileProperties { slope: true, .. }) = self.map.get_tile_properties(*tile_id)
-            {
-                self.current_slopes.insert(*tile_id);
-            }
-        }
-    }
-
-    fn handle_spikes(&mut self, tiles: &SmallIntSet<TileIndex>) {
-        for tile_id in tiles.iter() {
-            if let Some(TileProperties { deadly: true, .. }) =
-                self.map.get_tile_properties(*tile_id)
-            {
-                self.player.is_dead = true;
-            }
-        }
-    }
-
-    fn handle_current_platforms(&mut self, platforms: &SmallIntSet<usize>) {
-        self.current_platform = None;
-        for platform in self.platforms.iter_mut() {
-            platform.set_occupied(false);
-        }
-
-        for platform_index in platforms.iter() {
-            let platform = &mut self.platforms[*platform_index];
-
-            platform.set_occupied(true);
-            // TODO: Be smarter about what platform we pick.
-            self.current_platform = Some(*platform_index);
-        }
-    }
-
-    fn handle_switch_tiles(&mut self, tiles: &SmallIntSet<TileIndex>, sounds: &mut SoundManager) {
-        let new_switch_tiles = SmallIntSet::new();
-        let previous = mem::replace(&mut self.current_switch_tiles, new_switch_tiles);
-        for t in tiles.iter() {
-            let Some(TileProperties {
-                switch: Some(switch),
-                ..
-            }) = self.map.get_tile_properties(*t)
-            else {
-                continue;
-            };
-            self.current_switch_tiles.insert(*t);
-            if previous.contains(*t) {
-                continue;
-            }
-            sounds.play(Sound::Click);
-            self.switches.apply_command(switch);
-        }
-    }
-
-    fn update_player_movement(
-        &mut self,
-        inputs: &InputSnapshot,
-        sounds: &mut SoundManager,
-    ) -> PlayerMovementResult {
-        self.update_player_trajectory_x(inputs);
-        self.update_player_trajectory_y();
-
-        let x_result = self.move_player_x(inputs);
-        let y_result = self.move_player_y(sounds);
-
-        PlayerMovementResult {
-            on_ground: y_result.on_ground,
-            pushing_against_wall: x_result.pushing_against_wall,
-            jump_down: inputs.player_jump_down,
-            jump_triggered: inputs.player_jump_clicked,
-            crouch_down: inputs.player_crouch_down,
-            _stuck_in_wall: x_result.stuck_in_wall || y_result.stuck_in_wall,
-            crushed_by_platform: x_result.crushed_by_platform || y_result.crushed_by_platform,
-        }
-    }
-
-    fn update_player_state(&mut self, movement: PlayerMovementResult) {
-        if movement.on_ground {
-            self.coyote_counter = COYOTE_TIME;
-        } else if self.coyote_counter > 0 {
-            self.coyote_counter -= 1;
-        }
-
-        if self.jump_grace_counter > 0 {
-            self.jump_grace_counter -= 1;
-        }
-
-        if movement.crushed_by_platform {
-            self.player.state = PlayerState::Stopped;
-            self.player.is_dead = true;
-        } else {
-            match self.player.state {
-                PlayerState::Stopped => {}
-                PlayerState::Standing => {
-                    let launch = if let Some(Platform {
-                        subtype: PlatformType::Spring(spring),
-                        ..
-                    }) = self.current_platform.map(|i| &self.platforms[i])
-                    {
-                        spring.launch
-                    } else {
-                        false
-                    };
-                    if launch {
-                        self.jump_grace_counter = 0;
-                        self.player.state = PlayerState::Jumping;
-                        if movement.jump_triggered || self.jump_grace_counter > 0 {
-                            self.spring_counter = SPRING_JUMP_DURATION;
-                            self.player.delta.y = SPRING_JUMP_VELOCITY * -1;
-                        } else {
-                            self.spring_counter = SPRING_BOUNCE_DURATION;
-                            self.player.delta.y = SPRING_BOUNCE_VELOCITY * -1;
-                        }
-                    } else if self.coyote_counter == 0 {
-                        self.player.state = PlayerState::Falling;
-                        self.player.delta.y = Subpixels::zero();
-                        if let Some(current_platform) = self.current_platform {
-                            self.player.delta.x = self.platforms[current_platform].dx();
-                        }
-                    } else if movement.crouch_down {
-                        self.player.state = PlayerState::Crouching;
-                    } else if movement.jump_triggered || self.jump_grace_counter > 0 {
-                        if let Some(current_door) = self.current_door {
-                            if self.doors[current_door].is_open() {
-                                self.player.state = PlayerState::Stopped;
-                                self.doors[current_door].close();
-                            }
-                        } else {
-                            self.jump_grace_counter = 0;
-                            self.player.state = PlayerState::Jumping;
-                            let should_boost = if let Some(Platform {
-                                subtype: PlatformType::Spring(spring),
-                                ..
-                            }) =
-                                self.current_platform.map(|i| &self.platforms[i])
-                            {
-                                spring.should_boost()
-                            } else {
-                                false
-                            };
-                            if should_boost {
-                                self.spring_counter = SPRING_JUMP_DURATION;
-                                self.player.delta.y = SPRING_JUMP_VELOCITY * -1;
-                            } else {
-                                self.spring_counter = 0;
-                                self.player.delta.y = JUMP_INITIAL_SPEED * -1;
-                            }
-                            if let Some(current_platform) = self.current_platform {
-                                self.player.delta.x += self.platforms[current_platform].dx();
-                            }
-                        }
-                    }
-                }
-                PlayerState::Falling => {
-                    if movement.jump_triggered {
-                        self.jump_grace_counter = JUMP_GRACE_TIME;
-                    }
-                    if movement.on_ground {
-                        self.player.state = PlayerState::Standing;
-                        self.player.delta.y = Subpixels::zero();
-                    } else {
-                        if movement.pushing_against_wall && self.player.delta.y >= Subpixels::zero()
-                        {
-                            self.player.state = PlayerState::WallSliding;
-                            self.wall_slide_counter = WALL_SLIDE_TIME;
-                        }
-                    }
-                }
-                PlayerState::Jumping => {
-                    if movement.on_ground {
-                        self.player.state = PlayerState::Standing;
-                        self.player.delta.y = Subpixels::zero();
-                    } else if self.player.delta.y >= Subpixels::zero() {
-                        self.player.state = PlayerState::Falling;
-                    } else {
-                        if !movement.jump_down {
-                            if self.spring_counter == 0 {
-                                self.player.state = PlayerState::Falling;
-                                self.player.delta.y = Subpixels::zero();
-                            } else {
-                                self.spring_counter -= 1;
-                            }
-                        }
-                    }
-                }
-                PlayerState::WallSliding => {
-                    if movement.jump_triggered {
-                        self.player.state = PlayerState::Jumping;
-                        self.player.delta.y = WALL_JUMP_VERTICAL_SPEED * -1;
-                        if self.player.facing_right {
-                            self.player.delta.x = WALL_JUMP_HORIZONTAL_SPEED * -1;
-                        } else {
-                            self.player.delta.x = WALL_JUMP_HORIZONTAL_SPEED;
-                        }
-                    } else if movement.on_ground {
-                        self.player.state = PlayerState::Standing;
-                    } else if movement.pushing_against_wall {
-                        self.wall_stick_counter = WALL_STICK_TIME;
-                        self.wall_stick_facing_right = self.player.facing_right;
-                    } else {
-                        if self.wall_stick_facing_right != self.player.facing_right {
-                            self.player.state = PlayerState::Falling;
-                        } else if self.wall_stick_counter > 0 {
-                            self.wall_stick_counter -= 1;
-                        } else {
-                            self.player.state = PlayerState::Falling;
-                        }
-                    }
-                }
-                PlayerState::Crouching => {
-                    if !movement.on_ground {
-                        self.player.state = PlayerState::Falling;
-                        self.player.delta.y = Subpixels::zero();
-                    } else if !movement.crouch_down {
-                        self.player.state = PlayerState::Standing;
-                    }
-                }
-            }
+    // The parts of the level that the player moves through.
+    fn movement(&mut self) -> Movement<'_> {
+        Movement {
+            player: &mut self.player,
+            state: &mut self.movement_state,
+            map: &self.map,
+            physics: &self.physics,
+            platforms: &mut self.platforms,
+            switches: &mut self.switches,
+            door: self.current_door.map(|i| &mut self.doors[i]),
         }
     }
 
@@ -853,6 +293,232 @@ impl Level {
     }
 }
 
+impl Level {
+    /*
+     * Save states.
+     */
+
+    fn write_state(&self, writer: &mut BinaryWriter) {
+        writer.write_bytes(SAVE_STATE_MAGIC);
+        writer.write_u16(SAVE_STATE_VERSION);
+        writer.write_string(&self.map_path.to_string_lossy());
+        self.write_state_body(writer);
+    }
+
+    // The state without any header, which is all that's needed for rewinding.
+    fn write_state_body(&self, writer: &mut BinaryWriter) {
+        self.player.save_state(writer);
+        self.movement_state.save_state(writer);
+
+        writer.write_point(self.map_offset);
+        writer.write_string(&self.toast_text);
+        writer.write_subpixels(self.toast_position);
+        writer.write_i32(self.toast_counter);
+
+        writer.write_varint(self.platforms.len() as u64);
+        for platform in self.platforms.iter() {
+            platform.save_state(writer);
+        }
+        // Stars can only be collected, so just remember which ones are left.
+        write_object_ids(writer, &self.star_ids());
+        // Likewise, keys are either still lying around, held, or used up.
+        write_object_ids(
+            writer,
+            &self.keys.iter().map(|key| key.id).collect::<Vec<_>>(),
+        );
+        write_object_ids(writer, &self.inventory.ids());
+        writer.write_varint(self.doors.len() as u64);
+        for door in self.doors.iter() {
+            door.save_state(writer);
+        }
+        writer.write_varint(self.enemies.len() as u64);
+        for enemy in self.enemies.iter() {
+            enemy.save_state(writer);
+        }
+        writer.write_varint(self.turrets.len() as u64);
+        for turret in self.turrets.iter() {
+            turret.save_state(writer);
+        }
+        writer.write_varint(self.projectiles.len() as u64);
+        for projectile in self.projectiles.iter() {
+            projectile.save_state(writer);
+        }
+
+        writer.write_i32(self.star_count);
+        self.switches.save_state(writer);
+        writer.write_index(self.current_door);
+
+        writer.write_bool(self.respawn_point.is_some());
+        if let Some(respawn_point) = &self.respawn_point {
+            writer.write_index(Some(respawn_point.checkpoint));
+            write_object_ids(writer, &respawn_point.stars);
+            writer.write_i32(respawn_point.star_count);
+            respawn_point.switches.save_state(writer);
+            writer.write_varint(respawn_point.platforms.len() as u64);
+            writer.write_bytes(&respawn_point.platforms);
+        }
+    }
+
+    fn read_state(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        if reader.read_bytes(SAVE_STATE_MAGIC.len())? != SAVE_STATE_MAGIC {
+            bail!("not a saved level state");
+        }
+        let version = reader.read_u16()?;
+        if version != SAVE_STATE_VERSION {
+            bail!(
+                "saved state version {} doesn't match this build ({})",
+                version,
+                SAVE_STATE_VERSION
+            );
+        }
+        let map_path = reader.read_string()?;
+        if Path::new(&map_path) != self.map_path {
+            bail!("saved state is for {}, not {:?}", map_path, self.map_path);
+        }
+        self.read_state_body(reader)
+    }
+
+    fn read_state_body(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        self.player.restore_state(reader)?;
+        self.movement_state
+            .restore_state(reader, self.platforms.len())?;
+
+        self.map_offset = reader.read_point()?;
+        self.toast_text = reader.read_string()?;
+        self.toast_position = reader.read_subpixels()?;
+        self.toast_counter = reader.read_i32()?;
+
+        let platform_count = reader.read_varint()? as usize;
+        if platform_count != self.platforms.len() {
+            bail!(
+                "saved state has {} platforms, but the level has {}",
+                platform_count,
+                self.platforms.len()
+            );
+        }
+        for platform in self.platforms.iter_mut() {
+            platform.restore_state(reader)?;
+        }
+
+        let star_ids = read_object_ids(reader)?;
+        self.reset_stars(&star_ids)?;
+        let key_ids = read_object_ids(reader)?;
+        let held_key_ids = read_object_ids(reader)?;
+        self.reset_keys(&key_ids, &held_key_ids)?;
+
+        let door_count = reader.read_varint()? as usize;
+        if door_count != self.doors.len() {
+            bail!(
+                "saved state has {} doors, but the level has {}",
+                door_count,
+                self.doors.len()
+            );
+        }
+        for door in self.doors.iter_mut() {
+            door.restore_state(reader)?;
+        }
+
+        let enemy_count = reader.read_varint()? as usize;
+        if enemy_count != self.enemies.len() {
+            bail!(
+                "saved state has {} enemies, but the level has {}",
+                enemy_count,
+                self.enemies.len()
+            );
+        }
+        for enemy in self.enemies.iter_mut() {
+            enemy.restore_state(reader)?;
+        }
+
+        let turret_count = reader.read_varint()? as usize;
+        if turret_count != self.turrets.len() {
+            bail!(
+                "saved state has {} turrets, but the level has {}",
+                turret_count,
+                self.turrets.len()
+            );
+        }
+        for turret in self.turrets.iter_mut() {
+            turret.restore_state(reader)?;
+        }
+        let projectile_count = reader.read_varint()? as usize;
+        self.projectiles.clear();
+        for _ in 0..projectile_count {
+            self.projectiles.push(Projectile::read_state(reader)?);
+        }
+
+        self.star_count = reader.read_i32()?;
+        self.switches.restore_state(reader)?;
+        self.current_door = reader.read_index(self.doors.len())?;
+
+        self.respawn_point = if reader.read_bool()? {
+            let checkpoint = reader
+                .read_index(self.checkpoints.len())?
+                .context("saved state has no checkpoint for its respawn point")?;
+            let stars = read_object_ids(reader)?;
+            let star_count = reader.read_i32()?;
+            let mut switches = SwitchState::new();
+            switches.restore_state(reader)?;
+            let platforms_len = reader.read_varint()? as usize;
+            let platforms = reader.read_bytes(platforms_len)?.to_vec();
+            Some(RespawnPoint {
+                checkpoint,
+                stars,
+                star_count,
+                switches,
+                platforms,
+            })
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    // Puts back the keys with the given ids, and gives the player the held ones, in order.
+    fn reset_keys(&mut self, key_ids: &[i32], held_key_ids: &[i32]) -> Result<()> {
+        self.keys.clear();
+        for obj in self.map.objects.iter() {
+            if obj.properties.key && key_ids.contains(&obj.id) {
+                self.keys.push(Key::new(obj, self.map.clone())?);
+            }
+        }
+        self.inventory.clear();
+        for id in held_key_ids {
+            let obj = self
+                .map
+                .objects
+                .iter()
+                .find(|obj| obj.properties.key && obj.id == *id)
+                .with_context(|| format!("missing key {}", id))?;
+            self.inventory.add(Key::new(obj, self.map.clone())?);
+        }
+        Ok(())
+    }
+
+    fn platform_states(&self) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+        for platform in self.platforms.iter() {
+            platform.save_state(&mut writer);
+        }
+        writer.into_bytes()
+    }
+
+    fn star_ids(&self) -> Vec<i32> {
+        self.stars.iter().map(|star| star.id).collect()
+    }
+
+    // Puts back the stars with the given ids, in case they've been collected since.
+    fn reset_stars(&mut self, star_ids: &[i32]) -> Result<()> {
+        self.stars.clear();
+        for obj in self.map.objects.iter() {
+            if obj.properties.star && star_ids.contains(&obj.id) {
+                self.stars.push(Star::new(obj, self.map.clone())?);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Scene for Level {
     fn update(
         &mut self,
@@ -866,25 +532,43 @@ impl Scene for Level {
             };
         }
 
+        // The ghost keeps going no matter what the live player does, even while rewinding.
+        if let Some(ghost) = self.ghost.as_mut() {
+            if !ghost.update() {
+                self.ghost = None;
+            }
+        }
+
+        // While rewinding, step back one frame for every frame the button is held.
+        if inputs.rewind_down {
+            if let Some(state) = self.rewind.pop() {
+                self.read_state_body(&mut BinaryReader::new(&state))
+                    .expect("rewind states should always be valid");
+            }
+            return SceneResult::Continue;
+        }
+        let mut state = BinaryWriter::new();
+        self.write_state_body(&mut state);
+        self.rewind.push(state.into_bytes());
+
         for platform in self.platforms.iter_mut() {
             platform.update(&mut self.switches, sounds);
         }
-
-        let movement = match self.player.state {
-            PlayerState::Stopped => PlayerMovementResult {
-                on_ground: false,
-                pushing_against_wall: false,
-                jump_down: false,
-                jump_triggered: false,
-                crouch_down: false,
-                _stuck_in_wall: false,
-                crushed_by_platform: false,
-            },
-            _ => self.update_player_movement(inputs, sounds),
-        };
+        for enemy in self.enemies.iter_mut() {
+            enemy
+                .update(&self.map, &self.switches)
+                .expect("state machine should be valid");
+        }
+        for turret in self.turrets.iter_mut() {
+            if let Some(projectile) = turret.update(&self.switches) {
+                self.projectiles.push(projectile);
+            }
+        }
+        self.projectiles
+            .retain_mut(|projectile| projectile.update(&self.map, &self.switches));
 
         let start_state: PlayerState = self.player.state;
-        self.update_player_state(movement);
+        let movement = self.movement().update(inputs, sounds);
         self.player
             .update_sprite()
             .expect("state machine should be valid");
@@ -894,7 +578,7 @@ impl Scene for Level {
 
         self.current_door = None;
         for (i, door) in self.doors.iter_mut().enumerate() {
-            door.update(player_rect, self.star_count);
+            door.update(player_rect, self.star_count, &mut self.inventory);
             if door.is_closed() {
                 return SceneResult::SwitchToLevel {
                     path: door
@@ -929,11 +613,31 @@ impl Scene for Level {
             }
         }
 
+        let old_keys = mem::take(&mut self.keys);
+        for key in old_keys.into_iter() {
+            if key.intersects(player_rect) {
+                sounds.play(Sound::Star);
+                self.toast_text = format!("{} KEY", key.color.to_uppercase());
+                self.toast_counter = TOAST_TIME;
+                self.inventory.add(key);
+            } else {
+                self.keys.push(key);
+            }
+        }
+        // Lock blocks only open when the player walks into them, not from above or below.
+        let left = self.player.get_target_bounds_rect(Some(Direction::Left));
+        let right = self.player.get_target_bounds_rect(Some(Direction::Right));
+        for platform in self.platforms.iter_mut() {
+            if platform.try_unlock(left, right, &mut self.inventory) {
+                sounds.play(Sound::Click);
+            }
+        }
+
         if log_enabled!(log::Level::Info) {
             // TODO: Include slopes.
             let attribs = format!(
                 "{:?}, idle={}, platform={:?}",
-                movement, self.player.is_idle, self.current_platform,
+                movement, self.player.is_idle, self.movement_state.current_platform,
             );
             let transition = format!("{:?} x {} -> {:?}", start_state, attribs, self.player.state);
             if transition != self.previous_transition {
@@ -942,12 +646,52 @@ impl Scene for Level {
             }
         }
 
+        if !matches!(self.player.state, PlayerState::Stopped) {
+            let feet = self.player.get_target_bounds_rect(Some(Direction::Down));
+            let falling = self.player.delta.y > Subpixels::zero();
+            let mut stomped = false;
+            for enemy in self.enemies.iter_mut() {
+                match enemy.touch(player_rect, feet, falling) {
+                    EnemyContact::None => {}
+                    EnemyContact::Stomped => stomped = true,
+                    EnemyContact::Hurt => self.player.is_dead = true,
+                }
+            }
+            if self
+                .projectiles
+                .iter()
+                .any(|projectile| projectile.intersects(player_rect))
+            {
+                self.player.is_dead = true;
+            }
+            if stomped && !self.player.is_dead {
+                // Stomping an enemy bounces the player, like a spring does.
+                self.movement().bounce(movement.jump_down);
+            }
+        }
+
         if self.player.is_dead {
             return SceneResult::PushKillScreen {
                 path: self.map_path.clone(),
             };
         }
 
+        for (i, checkpoint) in self.checkpoints.iter().enumerate() {
+            let current = self.respawn_point.as_ref().map(|p| p.checkpoint);
+            if current != Some(i) && checkpoint.is_inside(player_rect) {
+                self.respawn_point = Some(RespawnPoint {
+                    checkpoint: i,
+                    stars: self.star_ids(),
+                    star_count: self.star_count,
+                    switches: self.switches.clone(),
+                    platforms: self.platform_states(),
+                });
+                self.toast_text = "CHECKPOINT".to_owned();
+                self.toast_counter = TOAST_TIME;
+                break;
+            }
+        }
+
         if self.toast_counter == 0 {
             if self.toast_position > TOAST_HEIGHT * -1 {
                 self.toast_position -= TOAST_SPEED;
@@ -986,10 +730,27 @@ impl Scene for Level {
         for platform in self.platforms.iter() {
             platform.draw(context, RenderLayer::Player, self.map_offset);
         }
+        for enemy in self.enemies.iter() {
+            enemy.draw(context, RenderLayer::Player, self.map_offset);
+        }
+        for turret in self.turrets.iter() {
+            turret.draw(context, RenderLayer::Player, self.map_offset);
+        }
+        for projectile in self.projectiles.iter() {
+            projectile.draw(context, RenderLayer::Player, self.map_offset);
+        }
         for star in self.stars.iter() {
             star.draw(context, RenderLayer::Player, self.map_offset);
         }
+        for key in self.keys.iter() {
+            key.draw(context, RenderLayer::Player, self.map_offset);
+        }
+        if let Some(ghost) = &self.ghost {
+            ghost.draw(context, RenderLayer::Player, self.map_offset);
+        }
         self.player.draw(context, RenderLayer::Player, player_draw);
+        self.map
+            .draw_water(context, RenderLayer::Player, self.map_offset);
         for door in self.doors.iter() {
             door.draw_foreground(context, RenderLayer::Player, self.map_offset);
         }
@@ -1026,6 +787,12 @@ impl Scene for Level {
             );
         }
 
+        // Show the keys the player is holding in the top right corner.
+        let keys_offset = Point::new(Pixels::new(-2), Pixels::new(2));
+        let keys_offset: Point<Subpixels> = keys_offset.into();
+        let top_right = Point::new(dest.right(), dest.y) + keys_offset;
+        self.inventory.draw(context, RenderLayer::Hud, top_right);
+
         context.is_dark = self.map.properties.dark;
 
         let spotlight_pos =
@@ -1034,4 +801,443 @@ impl Scene for Level {
         let spotlight_radius = Subpixels::from_pixels(120);
         context.add_light(spotlight_pos, spotlight_radius);
     }
+
+    fn is_level(&self) -> bool {
+        true
+    }
+
+    fn respawn(&mut self) -> bool {
+        let Some(respawn_point) = &self.respawn_point else {
+            return false;
+        };
+        let position = self.checkpoints[respawn_point.checkpoint].respawn_position();
+        let star_ids = respawn_point.stars.clone();
+        self.star_count = respawn_point.star_count;
+        self.switches = respawn_point.switches.clone();
+        let mut platforms = BinaryReader::new(&respawn_point.platforms);
+        for platform in self.platforms.iter_mut() {
+            platform
+                .respawn(&mut platforms)
+                .expect("platforms were saved by this level");
+        }
+        self.reset_stars(&star_ids)
+            .expect("stars were already loaded once");
+        // Keys stay as they are, since the doors and locks they opened stay open too.
+        self.player.respawn(position);
+        // Otherwise, a bullet that was already on its way could kill them again right away.
+        self.projectiles.clear();
+
+        self.movement_state.respawn(&self.physics);
+        self.current_door = None;
+        // Rewinding shouldn't bring back the life that just ended.
+        self.rewind.clear();
+        true
+    }
+
+    fn state_hash(&self) -> Option<u64> {
+        let mut hasher = Fnv1a::new();
+        hasher.write_point(self.player.position);
+        hasher.write_point(self.player.delta);
+        hasher.write_u8(self.player.state as u8);
+        hasher.write_bool(self.player.facing_right);
+        hasher.write_bool(self.player.is_dead);
+        // Each list starts with its length, so that things can't shift from one to the next.
+        hasher.write_u32(self.platforms.len() as u32);
+        for platform in self.platforms.iter() {
+            platform.state_hash(&mut hasher);
+        }
+        hasher.write_u32(self.enemies.len() as u32);
+        for enemy in self.enemies.iter() {
+            enemy.state_hash(&mut hasher);
+        }
+        hasher.write_u32(self.turrets.len() as u32);
+        for turret in self.turrets.iter() {
+            turret.state_hash(&mut hasher);
+        }
+        hasher.write_u32(self.projectiles.len() as u32);
+        for projectile in self.projectiles.iter() {
+            projectile.state_hash(&mut hasher);
+        }
+        self.switches.state_hash(&mut hasher);
+        hasher.write_i32(self.star_count);
+        let inventory = self.inventory.ids();
+        hasher.write_u32(inventory.len() as u32);
+        for id in inventory {
+            hasher.write_i32(id);
+        }
+        Some(hasher.finish())
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut writer = BinaryWriter::new();
+        self.write_state(&mut writer);
+        Some(writer.into_bytes())
+    }
+
+    fn set_rewind_budget(&mut self, budget: usize) {
+        self.rewind.set_budget(budget);
+    }
+
+    fn start_ghost(
+        &mut self,
+        ghost: &Ghost,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<()> {
+        if ghost.level_path() == self.map_path {
+            self.ghost = Some(ghost.start(self.map.clone(), &self.physics, files, images)?);
+        }
+        Ok(())
+    }
+
+    fn progress(&self) -> Option<(PathBuf, LevelProgress)> {
+        let unlocked_doors = self
+            .doors
+            .iter()
+            .filter(|door| door.is_unlocked())
+            .map(|door| door.id)
+            .collect();
+        let progress = LevelProgress {
+            completed: false,
+            stars: self.star_count,
+            unlocked_doors,
+        };
+        Some((self.map_path.clone(), progress))
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> Result<()> {
+        // Reading can fail partway through, so keep a copy to go back to.
+        let mut backup = BinaryWriter::new();
+        self.write_state(&mut backup);
+
+        if let Err(e) = self.read_state(&mut BinaryReader::new(state)) {
+            self.read_state(&mut BinaryReader::new(&backup.into_bytes()))
+                .expect("restoring a fresh backup should always work");
+            return Err(e);
+        }
+        // The frames before loading led somewhere else, so rewinding shouldn't go back to them.
+        self.rewind.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+    use crate::switchexpr::Condition;
+    use crate::testing::{test_files, TestImages, TEST_MAP_PATH};
+
+    // A ledge with a checkpoint where the player starts, and a one-shot red button next to it.
+    const BUTTON_ROWS: &[&str] = &[
+        "............",
+        "............",
+        "............",
+        "............",
+        "########....",
+        "############",
+    ];
+    const BUTTON_OBJECTS: &str = r#"
+<object id="1" x="0" y="16">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+<object id="2" x="0" y="0" width="32" height="40">
+ <properties><property name="checkpoint" type="bool" value="true"/></properties>
+</object>
+<object id="3" gid="3" x="64" y="40" width="8" height="8">
+ <properties>
+  <property name="button" type="bool" value="true"/>
+  <property name="button_type" value="oneshot"/>
+  <property name="color" value="red"/>
+ </properties>
+</object>
+"#;
+
+    // Runs the level until done says to stop, and returns whether it did within the frames.
+    fn run_until(
+        level: &mut Level,
+        inputs: &InputSnapshot,
+        frames: usize,
+        done: impl Fn(&Level) -> bool,
+    ) -> bool {
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        for _ in 0..frames {
+            level.update(&context, inputs, &mut sounds);
+            if done(level) {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn respawning_lets_buttons_be_pressed_again() {
+        let files = test_files(BUTTON_ROWS, BUTTON_OBJECTS);
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let red: Condition = "red".parse().unwrap();
+        let walk_right = InputSnapshot {
+            player_right_down: true,
+            ..Default::default()
+        };
+
+        assert!(run_until(
+            &mut level,
+            &InputSnapshot::default(),
+            60,
+            |level| level.respawn_point.is_some()
+        ));
+        assert!(run_until(&mut level, &walk_right, 120, |level| level
+            .switches
+            .is_condition_true(&red)));
+
+        level.player.is_dead = true;
+        assert!(level.respawn());
+        assert!(!level.switches.is_condition_true(&red));
+        // The frames from before dying are gone, so rewinding can't bring them back.
+        assert_eq!(level.rewind.pop(), None);
+
+        assert!(run_until(&mut level, &walk_right, 120, |level| level
+            .switches
+            .is_condition_true(&red)));
+    }
+
+    // A walker stuck in a gap, so it stays in place.
+    const GAP_ROWS: &[&str] = &[
+        "................",
+        "................",
+        "................",
+        "................",
+        "................",
+        "................",
+        "......#.#.......",
+        "################",
+    ];
+
+    const FLOOR_ROWS: &[&str] = &[
+        "................",
+        "................",
+        "................",
+        "................",
+        "................",
+        "................",
+        "................",
+        "################",
+    ];
+
+    fn enemy_objects(player_x: i32, player_y: i32) -> String {
+        format!(
+            r#"
+<object id="1" x="{player_x}" y="{player_y}">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+<object id="2" x="56" y="48" width="8" height="8">
+ <properties>
+  <property name="enemy" value="walker"/>
+  <property name="sprite" value="assets/sprites/skelly2.png"/>
+ </properties>
+</object>
+"#
+        )
+    }
+
+    #[test]
+    fn landing_on_enemies_stomps_them() {
+        let files = test_files(GAP_ROWS, &enemy_objects(48, 0));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let idle = InputSnapshot::default();
+
+        // Landing on it bounces the player back up.
+        assert!(run_until(&mut level, &idle, 60, |level| level
+            .player
+            .delta
+            .y
+            < Subpixels::zero()));
+        assert!(!level.player.is_dead);
+        assert!(!run_until(&mut level, &idle, 120, |level| level
+            .player
+            .is_dead));
+    }
+
+    #[test]
+    fn touching_enemies_from_the_side_hurts() {
+        let files = test_files(FLOOR_ROWS, &enemy_objects(0, 32));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let walk_right = InputSnapshot {
+            player_right_down: true,
+            ..Default::default()
+        };
+        assert!(run_until(&mut level, &walk_right, 120, |level| level
+            .player
+            .is_dead));
+    }
+
+    // The player on the left, and a turret on the right shooting at them.
+    fn turret_objects(interval: i32) -> String {
+        format!(
+            r#"
+<object id="1" x="0" y="32">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+<object id="2" gid="3" x="120" y="48" width="8" height="8">
+ <properties>
+  <property name="turret" type="bool" value="true"/>
+  <property name="direction" value="W"/>
+  <property name="interval" type="int" value="{interval}"/>
+ </properties>
+</object>
+"#
+        )
+    }
+
+    #[test]
+    fn turrets_shoot_the_player() {
+        let files = test_files(FLOOR_ROWS, &turret_objects(10));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let idle = InputSnapshot::default();
+        assert!(run_until(&mut level, &idle, 20, |level| !level
+            .projectiles
+            .is_empty()));
+        assert!(!level.player.is_dead);
+        assert!(run_until(&mut level, &idle, 120, |level| level
+            .player
+            .is_dead));
+    }
+
+    #[test]
+    fn bullets_stop_at_walls() {
+        let rows = &[
+            "................",
+            "................",
+            "................",
+            "................",
+            "................",
+            "........#.......",
+            "........#.......",
+            "################",
+        ];
+        let files = test_files(rows, &turret_objects(200));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let idle = InputSnapshot::default();
+        assert!(run_until(&mut level, &idle, 200, |level| !level
+            .projectiles
+            .is_empty()));
+
+        // The bullet is gone once it reaches the wall, well before it could reach the player.
+        assert!(run_until(&mut level, &idle, 30, |level| level
+            .projectiles
+            .is_empty()));
+        assert!(!run_until(&mut level, &idle, 120, |level| level
+            .player
+            .is_dead
+            || !level.projectiles.is_empty()));
+    }
+
+    // The player on the left, with a key to pick up on the way to whatever's on the right.
+    fn key_objects(key_color: &str, other: &str) -> String {
+        format!(
+            r#"
+<object id="1" x="0" y="32">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+<object id="2" gid="3" x="24" y="48" width="8" height="8">
+ <properties>
+  <property name="key" type="bool" value="true"/>
+  <property name="color" value="{key_color}"/>
+ </properties>
+</object>
+{other}
+"#
+        )
+    }
+
+    const RED_DOOR: &str = r#"
+<object id="3" x="48" y="24" width="32" height="32">
+ <properties>
+  <property name="door" type="bool" value="true"/>
+  <property name="color" value="red"/>
+ </properties>
+</object>
+"#;
+
+    const RED_LOCK: &str = r#"
+<object id="3" gid="3" x="64" y="48" width="8" height="8">
+ <properties>
+  <property name="lock" type="bool" value="true"/>
+  <property name="color" value="red"/>
+ </properties>
+</object>
+"#;
+
+    #[test]
+    fn keys_open_doors_of_their_color() {
+        let files = test_files(FLOOR_ROWS, &key_objects("red", RED_DOOR));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let walk_right = InputSnapshot {
+            player_right_down: true,
+            ..Default::default()
+        };
+        let door_unlocked = |level: &Level| level.doors[0].is_unlocked();
+        assert!(!door_unlocked(&level));
+
+        assert!(run_until(&mut level, &walk_right, 60, |level| level
+            .keys
+            .is_empty()));
+        assert_eq!(level.inventory.ids(), vec![2]);
+
+        // Standing in front of the door uses up the key.
+        assert!(run_until(&mut level, &walk_right, 60, door_unlocked));
+        assert_eq!(level.inventory.ids(), vec![]);
+
+        // A key of another color stays in the inventory.
+        let files = test_files(FLOOR_ROWS, &key_objects("blue", RED_DOOR));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        assert!(!run_until(&mut level, &walk_right, 120, door_unlocked));
+        assert_eq!(level.inventory.ids(), vec![2]);
+    }
+
+    #[test]
+    fn keys_open_locks_of_their_color() {
+        let walk_right = InputSnapshot {
+            player_right_down: true,
+            ..Default::default()
+        };
+        let files = test_files(FLOOR_ROWS, &key_objects("red", RED_LOCK));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let past_lock = |level: &Level| level.player.position.x > Subpixels::from_pixels(72);
+        assert!(run_until(&mut level, &walk_right, 120, past_lock));
+        assert!(level.keys.is_empty());
+        assert_eq!(level.inventory.ids(), vec![]);
+
+        // A key of another color is picked up, but the lock doesn't take it.
+        let files = test_files(FLOOR_ROWS, &key_objects("blue", RED_LOCK));
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        assert!(!run_until(&mut level, &walk_right, 120, past_lock));
+        assert!(level.keys.is_empty());
+        assert_eq!(level.inventory.ids(), vec![2]);
+        assert!(level.player.position.x < Subpixels::from_pixels(64));
+    }
+
+    #[test]
+    fn loading_a_state_forgets_rewinding() {
+        let files = test_files(BUTTON_ROWS, BUTTON_OBJECTS);
+        let mut level = Level::new(Path::new(TEST_MAP_PATH), &files, &mut TestImages).unwrap();
+        let state = level.save_state().unwrap();
+        let walk_right = InputSnapshot {
+            player_right_down: true,
+            ..Default::default()
+        };
+        assert!(!run_until(&mut level, &walk_right, 10, |_| false));
+        assert!(level.rewind.pop().is_some());
+
+        assert!(!run_until(&mut level, &walk_right, 10, |_| false));
+        level.restore_state(&state).unwrap();
+        assert_eq!(level.rewind.pop(), None);
+
+        // A state that can't be read leaves the level, and what it can rewind to, as it was.
+        assert!(!run_until(&mut level, &walk_right, 10, |_| false));
+        assert!(level.restore_state(&state[..state.len() / 2]).is_err());
+        assert!(level.rewind.pop().is_some());
+    }
 }
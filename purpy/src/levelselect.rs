@@ -3,27 +3,63 @@ use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use log::debug;
+use num_traits::Zero;
 
+use crate::cursor::Cursor;
 use crate::filemanager::DirEntry;
 use crate::filemanager::DirEntryType;
 use crate::filemanager::FileManager;
 use crate::font::Font;
+use crate::geometry::{Pixels, Point, Rect, Subpixels};
+use crate::imagemanager::ImageLoader;
 use crate::inputmanager::InputSnapshot;
+use crate::progress::{LevelProgress, Progress};
 use crate::rendercontext::RenderContext;
 use crate::rendercontext::RenderLayer;
 use crate::scene::Scene;
 use crate::scene::SceneResult;
 use crate::soundmanager::SoundManager;
+use crate::utils::Color;
+
+// How many entries fit on the screen at once.
+const VISIBLE_ENTRIES: i32 = 11;
+
+// Each line is the height of the font, with half that again between lines.
+const LINE_HEIGHT: Subpixels = Subpixels::from_pixels(12);
+const LINE_SPACING: Subpixels = Subpixels::from_pixels(4);
+
+// Lines 0 and 1 are the directory and the "..." above the entries.
+const FIRST_ENTRY_LINE: i32 = 2;
+
+// Something on the screen that can be clicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Entry(i32),
+    ScrollUp,
+    ScrollDown,
+}
 
 pub struct LevelSelect {
     directory: PathBuf,
     files: Vec<DirEntry>,
+    // Saved progress for each entry in files, if it's a level that's been played.
+    progress: Vec<Option<LevelProgress>>,
     current: i32,
     start: i32,
+    cursor: Cursor,
+    // What the mouse is over, and what it was over when the button was pressed.
+    hovered: Option<Target>,
+    mouse_pressed: Option<Target>,
+    mouse_was_down: bool,
+    previous_mouse_position: Point<Pixels>,
 }
 
 impl LevelSelect {
-    pub fn new(directory: &Path, file_manager: &FileManager) -> Result<LevelSelect> {
+    pub fn new(
+        directory: &Path,
+        file_manager: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<LevelSelect> {
         debug!("Scanning directory {:?}", directory);
         let mut files = Vec::new();
         let file_list = file_manager
@@ -37,25 +73,130 @@ impl LevelSelect {
         files.sort_by_key(|entry| entry.name.clone());
 
         let directory = directory.to_owned();
+        let progress = vec![None; files.len()];
         Ok(LevelSelect {
             directory,
             files,
+            progress,
             current: 0,
             start: 0,
+            cursor: Cursor::new(images)?,
+            hovered: None,
+            mouse_pressed: None,
+            mouse_was_down: false,
+            previous_mouse_position: Point::new(Pixels::new(-1), Pixels::new(-1)),
         })
     }
+
+    fn line_position(line: i32) -> Point<Subpixels> {
+        Point::new(LINE_SPACING, LINE_SPACING + LINE_HEIGHT * line)
+    }
+
+    // The area for a line, including half the spacing around it, so the lines don't overlap.
+    fn line_area(context: &RenderContext, line: i32) -> Rect<Subpixels> {
+        Rect {
+            x: Subpixels::zero(),
+            y: Self::line_position(line).y - LINE_SPACING / 2,
+            w: context.logical_area_in_subpixels().w,
+            h: LINE_HEIGHT,
+        }
+    }
+
+    fn can_scroll_up(&self) -> bool {
+        self.start != 0
+    }
+
+    fn can_scroll_down(&self) -> bool {
+        self.start + VISIBLE_ENTRIES < self.files.len() as i32
+    }
+
+    fn target_at(&self, context: &RenderContext, point: Point<Pixels>) -> Option<Target> {
+        let point: Point<Subpixels> = point.into();
+        let line = (0..FIRST_ENTRY_LINE + VISIBLE_ENTRIES + 1)
+            .find(|line| Self::line_area(context, *line).contains(point))?;
+        if line == FIRST_ENTRY_LINE - 1 {
+            return self.can_scroll_up().then_some(Target::ScrollUp);
+        }
+        if line == FIRST_ENTRY_LINE + VISIBLE_ENTRIES {
+            return self.can_scroll_down().then_some(Target::ScrollDown);
+        }
+        let entry = self.start + line - FIRST_ENTRY_LINE;
+        (line >= FIRST_ENTRY_LINE && entry < self.files.len() as i32)
+            .then_some(Target::Entry(entry))
+    }
+
+    // Moves a whole screen of entries up or down.
+    fn page(&mut self, pages: i32) {
+        let last_start = (self.files.len() as i32 - VISIBLE_ENTRIES).max(0);
+        let last = (self.files.len() as i32 - 1).max(0);
+        self.start = (self.start + pages * VISIBLE_ENTRIES).clamp(0, last_start);
+        self.current = (self.current + pages * VISIBLE_ENTRIES).clamp(0, last);
+    }
+
+    fn open_current(&self) -> SceneResult {
+        let entry = &self.files[self.current as usize];
+        let new_path = entry.full_path.clone();
+        if matches!(entry.file_type, DirEntryType::Directory) {
+            SceneResult::PushLevelSelect { path: new_path }
+        } else {
+            SceneResult::PushLevel { path: new_path }
+        }
+    }
+
+    // Returns what was clicked, once the mouse button is let go over what it was pressed on.
+    fn update_mouse(&mut self, context: &RenderContext, inputs: &InputSnapshot) -> Option<Target> {
+        self.cursor.update(inputs);
+        self.hovered = self.target_at(context, inputs.mouse_position);
+
+        // Only follow the mouse when it moves, so that it doesn't fight with the keyboard.
+        if inputs.mouse_position != self.previous_mouse_position {
+            self.previous_mouse_position = inputs.mouse_position;
+            if let Some(Target::Entry(entry)) = self.hovered {
+                self.current = entry;
+            }
+        }
+
+        let mut clicked = None;
+        if inputs.mouse_button_left_down && !self.mouse_was_down {
+            self.mouse_pressed = self.hovered;
+        }
+        if !inputs.mouse_button_left_down && self.mouse_was_down {
+            if self.mouse_pressed == self.hovered {
+                clicked = self.hovered;
+            }
+            self.mouse_pressed = None;
+        }
+        self.mouse_was_down = inputs.mouse_button_left_down;
+        clicked
+    }
 }
 
 impl Scene for LevelSelect {
     fn update(
         &mut self,
-        _context: &RenderContext,
+        context: &RenderContext,
         inputs: &InputSnapshot,
         _sounds: &mut SoundManager,
     ) -> SceneResult {
         if inputs.cancel_clicked {
             return SceneResult::Pop;
         }
+        let clicked = self.update_mouse(context, inputs);
+        match clicked {
+            Some(Target::Entry(entry)) => {
+                self.current = entry;
+                return self.open_current();
+            }
+            Some(Target::ScrollUp) => self.page(-1),
+            Some(Target::ScrollDown) => self.page(1),
+            None => {}
+        }
+        if inputs.scroll_up_clicked {
+            self.page(-1);
+        }
+        if inputs.scroll_down_clicked {
+            self.page(1);
+        }
         if inputs.menu_up_clicked {
             self.current = ((self.current - 1) + self.files.len() as i32) % self.files.len() as i32;
         }
@@ -63,59 +204,175 @@ impl Scene for LevelSelect {
             self.current = (self.current + 1) % self.files.len() as i32;
         }
         if inputs.ok_clicked {
-            let entry = &self.files[self.current as usize];
-            let new_path = entry.full_path.clone();
-            if matches!(entry.file_type, DirEntryType::Directory) {
-                SceneResult::PushLevelSelect { path: new_path }
-            } else {
-                SceneResult::PushLevel { path: new_path }
-            }
+            self.open_current()
         } else {
             if self.current < self.start {
                 // You scrolled up past what was visible.
                 self.start = self.current;
             }
-            if self.current >= self.start + 10 {
+            if self.current >= self.start + VISIBLE_ENTRIES {
                 // You scrolled off the bottom.
-                self.start = self.current - 10;
+                self.start = self.current - (VISIBLE_ENTRIES - 1);
             }
 
             SceneResult::Continue
         }
     }
 
+    fn set_progress(&mut self, progress: &Progress) {
+        self.progress = self
+            .files
+            .iter()
+            .map(|file| progress.level(&file.full_path).cloned())
+            .collect();
+    }
+
     fn draw(&self, context: &mut RenderContext, font: &Font, _previous: Option<&dyn Scene>) {
         let layer = RenderLayer::Hud;
-        let font_height = font.char_height;
-        let line_spacing = font_height / 2;
 
-        let x = line_spacing;
-        let mut y = line_spacing;
+        let hovered_line = match self.hovered {
+            Some(Target::Entry(entry)) => Some(FIRST_ENTRY_LINE + entry - self.start),
+            Some(Target::ScrollUp) => Some(FIRST_ENTRY_LINE - 1),
+            Some(Target::ScrollDown) => Some(FIRST_ENTRY_LINE + VISIBLE_ENTRIES),
+            None => None,
+        };
+        if let Some(line) = hovered_line {
+            let color = Color {
+                r: 0xff,
+                g: 0xff,
+                b: 0xff,
+                a: 0x33,
+            };
+            context.fill_rect(Self::line_area(context, line), layer, color);
+        }
+
         let dir_str = self.directory.to_string_lossy();
-        font.draw_string(context, layer, (x, y).into(), &dir_str);
-        y += font_height + line_spacing;
+        font.draw_string(context, layer, Self::line_position(0), &dir_str);
 
-        if self.start != 0 {
-            font.draw_string(context, layer, (x, y).into(), " ...")
+        if self.can_scroll_up() {
+            let pos = Self::line_position(FIRST_ENTRY_LINE - 1);
+            font.draw_string(context, layer, pos, " ...")
         }
-        y += font_height + line_spacing;
 
-        for i in self.start..self.start + 11 {
+        for (line, i) in (self.start..self.start + VISIBLE_ENTRIES).enumerate() {
             if i < 0 || i >= self.files.len() as i32 {
                 continue;
             }
             let cursor = if i == self.current { '>' } else { ' ' };
+            let (done, stars) = match &self.progress[i as usize] {
+                Some(progress) => (
+                    if progress.completed { '*' } else { ' ' },
+                    if progress.stars > 0 {
+                        format!(" - {} STARS", progress.stars)
+                    } else {
+                        String::new()
+                    },
+                ),
+                None => (' ', String::new()),
+            };
             font.draw_string(
                 context,
                 layer,
-                (x, y).into(),
-                &format!("{}{}", cursor, &self.files[i as usize].name),
+                Self::line_position(FIRST_ENTRY_LINE + line as i32),
+                &format!(
+                    "{}{}{}{}",
+                    cursor, done, &self.files[i as usize].name, stars
+                ),
             );
-            y += font_height + line_spacing;
         }
 
-        if self.start + 12 <= self.files.len() as i32 {
-            font.draw_string(context, layer, (x, y).into(), " ...");
+        if self.can_scroll_down() {
+            let pos = Self::line_position(FIRST_ENTRY_LINE + VISIBLE_ENTRIES);
+            font.draw_string(context, layer, pos, " ...");
         }
+
+        self.cursor.draw(context, layer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+    use crate::testing::{test_archive, TestImages};
+
+    // The middle of the given line on the screen, in pixels.
+    fn line_y(line: i32) -> i32 {
+        4 + 12 * line + 4
+    }
+
+    fn mouse(y: i32, down: bool) -> InputSnapshot {
+        InputSnapshot {
+            mouse_position: Point::new(Pixels::new(40), Pixels::new(y)),
+            mouse_button_left_down: down,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn follows_the_mouse() {
+        let names: Vec<String> = (0..15)
+            .map(|i| format!("assets/levels/{:02}.tmx", i))
+            .collect();
+        let entries: Vec<(&str, &[u8])> =
+            names.iter().map(|name| (name.as_str(), &b""[..])).collect();
+        let files = test_archive(&entries);
+        let mut select =
+            LevelSelect::new(Path::new("assets/levels"), &files, &mut TestImages).unwrap();
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let mut update = |select: &mut LevelSelect, inputs: InputSnapshot| {
+            select.update(&context, &inputs, &mut sounds)
+        };
+        let entry_y = |entry: i32| line_y(FIRST_ENTRY_LINE + entry);
+        let top_marker = line_y(FIRST_ENTRY_LINE - 1);
+        let bottom_marker = line_y(FIRST_ENTRY_LINE + VISIBLE_ENTRIES);
+
+        // Hovering selects an entry.
+        update(&mut select, mouse(entry_y(3), false));
+        assert_eq!(select.current, 3);
+
+        // Letting go over something else isn't a click.
+        update(&mut select, mouse(entry_y(3), true));
+        update(&mut select, mouse(entry_y(4), true));
+        assert!(matches!(
+            update(&mut select, mouse(entry_y(4), false)),
+            SceneResult::Continue
+        ));
+
+        // The scroll wheel moves a page at a time, without going past the last page. The
+        // archive has the test tileset in it too, so that's 16 entries.
+        let scroll_down = InputSnapshot {
+            scroll_down_clicked: true,
+            ..mouse(entry_y(4), false)
+        };
+        update(&mut select, scroll_down);
+        assert_eq!((select.start, select.current), (5, 15));
+        let scroll_up = InputSnapshot {
+            scroll_up_clicked: true,
+            ..mouse(entry_y(4), false)
+        };
+        update(&mut select, scroll_up);
+        assert_eq!((select.start, select.current), (0, 4));
+
+        // The "..." at the top only does anything when there's more above.
+        update(&mut select, mouse(top_marker, true));
+        update(&mut select, mouse(top_marker, false));
+        assert_eq!(select.start, 0);
+        update(&mut select, mouse(bottom_marker, true));
+        update(&mut select, mouse(bottom_marker, false));
+        assert_eq!(select.start, 5);
+        update(&mut select, mouse(top_marker, true));
+        update(&mut select, mouse(top_marker, false));
+        assert_eq!(select.start, 0);
+
+        // Clicking an entry opens it.
+        update(&mut select, mouse(entry_y(2), true));
+        let result = update(&mut select, mouse(entry_y(2), false));
+        let SceneResult::PushLevel { path } = result else {
+            panic!("clicking a level should open it");
+        };
+        assert_eq!(path, Path::new("assets/levels/02.tmx"));
     }
 }
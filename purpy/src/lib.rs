@@ -1,43 +1,69 @@
+mod binary;
+mod bindings;
+mod checkpoint;
 mod constants;
+mod controls;
 mod cursor;
 mod door;
+mod enemy;
 mod filemanager;
 mod font;
 mod geometry;
+mod ghost;
 mod imagemanager;
 mod inputmanager;
+mod key;
+mod keyboard;
 mod level;
 mod levelselect;
 mod menu;
+mod movement;
+mod physics;
 mod platform;
 mod player;
+mod progress;
 mod properties;
 mod rendercontext;
 mod renderer;
+mod replay;
+mod rewind;
 mod scene;
 mod slope;
 mod smallintmap;
 mod smallintset;
+mod software;
 mod soundmanager;
+mod speedrun;
 mod sprite;
 mod stagemanager;
 mod star;
+mod storage;
+mod switchexpr;
 mod switchstate;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod tilemap;
 mod tileset;
+mod touch;
+mod turret;
 mod uibutton;
 mod utils;
 mod warp;
 
-pub use constants::{FRAME_RATE, RENDER_HEIGHT, RENDER_WIDTH};
+pub use bindings::Bindings;
+pub use constants::{FRAME_RATE, RENDER_HEIGHT, RENDER_WIDTH, START_SCENE};
 
 pub use filemanager::FileManager;
 pub use font::Font;
-pub use imagemanager::ImageManager;
+pub use imagemanager::{ImageLoader, ImageManager};
 pub use inputmanager::{InputManager, RecordOption};
 pub use rendercontext::RenderContext;
+pub use scene::SceneResult;
+pub use software::renderer::SoftwareRenderer;
 pub use soundmanager::{Sound, SoundManager, SoundPlayer};
 pub use stagemanager::StageManager;
+pub use storage::{FileStorage, Storage};
+pub use touch::TouchPhase;
 
 #[cfg(feature = "sdl2")]
 mod sdl;
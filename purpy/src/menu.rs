@@ -10,29 +10,28 @@ use crate::font::Font;
 use crate::geometry::{Point, Subpixels};
 use crate::imagemanager::ImageLoader;
 use crate::inputmanager::InputSnapshot;
+use crate::progress::Progress;
 use crate::rendercontext::{RenderContext, RenderLayer};
 use crate::scene::{Scene, SceneResult};
 use crate::soundmanager::SoundManager;
 use crate::switchstate::SwitchState;
 use crate::tilemap::TileMap;
-use crate::uibutton::UiButton;
+use crate::uibutton::{ButtonOrder, ButtonOrderDirection, UiButton};
 use crate::utils::Color;
 
 pub struct Menu {
     cancel_action: String,
     reload_path: Option<PathBuf>,
+    // On the death screen, reloading goes back to the last checkpoint instead of the start.
+    respawn: bool,
     cursor: Cursor,
     tilemap: TileMap,
     buttons: Vec<UiButton>,
-    horizontal_button_order: Vec<usize>,
-    vertical_button_order: Vec<usize>,
+    button_order: ButtonOrder,
     selected: usize,
     switches: SwitchState,
-}
-
-enum ButtonOrderDirection {
-    Vertical,
-    Horizontal,
+    // A summary of saved progress, for the level being paused, or for the whole game.
+    progress_text: Option<String>,
 }
 
 impl Menu {
@@ -50,7 +49,9 @@ impl Menu {
         images: &mut dyn ImageLoader,
     ) -> Result<Self> {
         let path = Path::new("assets/menus/dead.tmx");
-        Self::new(path, Some(level_path), files, images)
+        let mut menu = Self::new(path, Some(level_path), files, images)?;
+        menu.respawn = true;
+        Ok(menu)
     }
 
     pub fn new_pause_screen(
@@ -59,7 +60,9 @@ impl Menu {
         images: &mut dyn ImageLoader,
     ) -> Result<Self> {
         let path = Path::new("assets/menus/pause.tmx");
-        Self::new(path, Some(level_path), files, images)
+        let mut menu = Self::new(path, Some(level_path), files, images)?;
+        menu.add_controls_button(images)?;
+        Ok(menu)
     }
 
     fn new(
@@ -86,46 +89,52 @@ impl Menu {
             }
         }
 
-        let mut button_positions: Vec<(usize, Subpixels, Subpixels)> = buttons
-            .iter()
-            .enumerate()
-            .map(|(i, button)| (i, button.position.x, button.position.y))
-            .collect();
-
-        button_positions.sort_by_key(|(_, x, y)| (*y, *x));
-        let horizontal_button_order: Vec<usize> =
-            button_positions.iter().map(|(i, _, _)| *i).collect();
-
-        button_positions.sort_by_key(|(_, x, y)| (*x, *y));
-        let vertical_button_order: Vec<usize> =
-            button_positions.iter().map(|(i, _, _)| *i).collect();
-
-        let selected = vertical_button_order[0];
+        let button_order = ButtonOrder::new(&buttons);
+        let selected = button_order.first();
 
         Ok(Self {
             cancel_action,
             reload_path,
+            respawn: false,
             cursor,
             tilemap,
             buttons,
-            vertical_button_order,
-            horizontal_button_order,
+            button_order,
             selected,
             switches,
+            progress_text: None,
         })
     }
 
-    fn next_button(&mut self, delta: i32, direction: ButtonOrderDirection) {
-        let order: &[usize] = match direction {
-            ButtonOrderDirection::Horizontal => &self.horizontal_button_order,
-            ButtonOrderDirection::Vertical => &self.vertical_button_order,
-        };
-        let Some(pos) = order.iter().position(|i| *i == self.selected) else {
-            error!("invalid button index: {}", self.selected);
-            return;
+    // Makes sure the controls can always be changed, by adding a button below the others if
+    // the map doesn't have one.
+    fn add_controls_button(&mut self, images: &mut dyn ImageLoader) -> Result<()> {
+        let has_controls = self
+            .buttons
+            .iter()
+            .any(|button| button.action().is_some_and(|a| a.starts_with("controls:")));
+        if has_controls {
+            return Ok(());
+        }
+        let Some(lowest) = self.buttons.iter().max_by_key(|button| button.position.y) else {
+            return Ok(());
         };
-        let new_pos = ((pos + order.len()) as i32 + delta) as usize % order.len();
-        self.selected = order[new_pos];
+        let mut position = lowest.position;
+        position.y += position.h + Subpixels::from_pixels(4);
+        self.buttons.push(UiButton::with_label(
+            position,
+            "CONTROLS".to_owned(),
+            Some("controls:".to_owned()),
+            self.tilemap.tilewidth,
+            self.tilemap.tileheight,
+            images,
+        )?);
+        self.button_order = ButtonOrder::new(&self.buttons);
+        Ok(())
+    }
+
+    fn next_button(&mut self, delta: i32, direction: ButtonOrderDirection) {
+        self.selected = self.button_order.next(self.selected, delta, direction);
     }
 
     fn perform_action(&self, action: &str) -> Option<SceneResult> {
@@ -141,14 +150,22 @@ impl Menu {
             SceneResult::PushMenu {
                 path: Path::new(path).to_owned(),
             }
+        } else if let Some(path) = action.strip_prefix("controls:") {
+            // The path is an optional map to draw behind the controls.
+            SceneResult::PushControls {
+                path: (!path.is_empty()).then(|| Path::new(path).to_owned()),
+            }
         } else if action == "pop" {
             SceneResult::Pop
         } else if action == "pop2" {
             SceneResult::PopTwo
         } else if action == "reload" {
             if let Some(reload_path) = &self.reload_path {
-                SceneResult::ReloadLevel {
-                    path: reload_path.clone(),
+                let path = reload_path.clone();
+                if self.respawn {
+                    SceneResult::RespawnLevel { path }
+                } else {
+                    SceneResult::ReloadLevel { path }
                 }
             } else {
                 error!("menu button triggered reload, but no reload_path set");
@@ -205,6 +222,23 @@ impl Scene for Menu {
         SceneResult::Continue
     }
 
+    fn set_progress(&mut self, progress: &Progress) {
+        self.progress_text = if let Some(path) = &self.reload_path {
+            progress.level(path).map(|level| {
+                let completed = if level.completed { " - COMPLETED" } else { "" };
+                format!("BEST: {} STARS{}", level.stars, completed)
+            })
+        } else if progress.completed_count() > 0 || progress.star_count() > 0 {
+            Some(format!(
+                "LEVELS: {} - STARS: {}",
+                progress.completed_count(),
+                progress.star_count()
+            ))
+        } else {
+            None
+        };
+    }
+
     fn draw(&self, context: &mut RenderContext, font: &Font, previous: Option<&dyn Scene>) {
         context.player_batch.fill_rect(
             context.logical_area_in_subpixels(),
@@ -238,6 +272,52 @@ impl Scene for Menu {
         for button in self.buttons.iter() {
             button.draw(context, RenderLayer::Hud, font);
         }
+        if let Some(text) = &self.progress_text {
+            let margin = font.char_height / 2;
+            let area = context.logical_area_in_subpixels();
+            let pos = Point::new(margin, area.bottom() - font.char_height - margin);
+            font.draw_string(context, RenderLayer::Hud, pos, text);
+        }
         self.cursor.draw(context, RenderLayer::Hud);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+    use crate::testing::{test_archive, test_map, TestImages};
+
+    const RESUME_BUTTON: &str = r#"
+<object id="1" x="8" y="8" width="64" height="16">
+ <properties>
+  <property name="uibutton" type="bool" value="true"/>
+  <property name="action" value="pop"/>
+ </properties>
+</object>
+"#;
+
+    #[test]
+    fn pause_screen_reaches_controls() {
+        let pause = test_map(&["........"], RESUME_BUTTON);
+        let files = test_archive(&[("assets/menus/pause.tmx", pause.as_bytes())]);
+        let mut menu =
+            Menu::new_pause_screen(PathBuf::from("level.tmx"), &files, &mut TestImages).unwrap();
+        assert_eq!(menu.buttons.len(), 2);
+
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let mut update = |inputs: InputSnapshot| menu.update(&context, &inputs, &mut sounds);
+        update(InputSnapshot {
+            menu_down_clicked: true,
+            ..Default::default()
+        });
+        update(InputSnapshot {
+            ok_down: true,
+            ..Default::default()
+        });
+        let result = update(InputSnapshot::default());
+        assert!(matches!(result, SceneResult::PushControls { path: None }));
+    }
+}
@@ -0,0 +1,1199 @@
+#![allow(clippy::collapsible_else_if)]
+
+use std::cmp::Ordering;
+use std::mem;
+
+use anyhow::Result;
+use num_traits::Zero;
+
+use crate::binary::{BinaryReader, BinaryWriter};
+use crate::constants::{
+    SPRING_BOUNCE_DURATION, SPRING_BOUNCE_VELOCITY, SPRING_JUMP_DURATION, SPRING_JUMP_VELOCITY,
+};
+use crate::door::Door;
+use crate::geometry::{Point, Rect, Subpixels};
+use crate::inputmanager::InputSnapshot;
+use crate::physics::PhysicsConfig;
+use crate::platform::{Platform, PlatformType};
+use crate::player::{Player, PlayerState};
+use crate::smallintset::SmallIntSet;
+use crate::soundmanager::{Sound, SoundManager};
+use crate::switchstate::SwitchState;
+use crate::tilemap::{TileIndex, TileMap};
+use crate::tileset::TileProperties;
+use crate::utils::{cmp_in_direction, Direction};
+
+struct PlatformIntersectionResult {
+    offset: Subpixels,
+    platforms: SmallIntSet<usize>,
+}
+
+// The results of trying to move.
+struct TryMovePlayerResult {
+    offset: Subpixels,
+    tile_ids: SmallIntSet<TileIndex>,
+    platforms: SmallIntSet<usize>,
+}
+
+struct MoveAndCheckResult {
+    on_ground: bool,
+    on_tile_ids: SmallIntSet<TileIndex>,
+    on_platforms: SmallIntSet<usize>,
+    hit_ceiling: bool,
+    against_wall: bool,
+    crushed_by_platform: bool,
+    stuck_in_wall: bool,
+}
+
+struct MovePlayerXResult {
+    pushing_against_wall: bool,
+    stuck_in_wall: bool,
+    crushed_by_platform: bool,
+}
+
+struct MovePlayerYResult {
+    on_ground: bool,
+    _platforms: SmallIntSet<usize>,
+    _tile_ids: SmallIntSet<TileIndex>,
+    stuck_in_wall: bool,
+    crushed_by_platform: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerMovementResult {
+    on_ground: bool,
+    pushing_against_wall: bool,
+    pub jump_down: bool,
+    jump_triggered: bool,
+    jump_without_up_triggered: bool,
+    crouch_down: bool,
+    up_down: bool,
+    on_ladder: bool,
+    // Whether the player's feet are right on top of a ladder.
+    ladder_below: bool,
+    in_water: bool,
+    _stuck_in_wall: bool,
+    crushed_by_platform: bool,
+}
+
+fn inc_player_x(player: &mut Player, offset: Subpixels) {
+    player.position.x += offset;
+}
+
+fn inc_player_y(player: &mut Player, offset: Subpixels) {
+    player.position.y += offset;
+}
+
+/*
+ * Scales a change in speed by a percentage, rounded to the nearest subpixel.
+ *
+ * The normal changes are only a few subpixels, so a slippery surface could otherwise round
+ * them down to nothing. As long as there's any change at all, it's at least one subpixel.
+ */
+fn scale_by_percent(change: Subpixels, percent: i32) -> Subpixels {
+    if change <= Subpixels::zero() || percent <= 0 {
+        return Subpixels::zero();
+    }
+    ((change * percent + Subpixels::new(50)) / 100).max(Subpixels::new(1))
+}
+
+// The parts of the player's movement that carry over from one frame to the next.
+pub struct MovementState {
+    wall_stick_counter: i32,
+    wall_stick_facing_right: bool,
+    wall_slide_counter: i32,
+
+    coyote_counter: i32,
+    jump_grace_counter: i32,
+    spring_counter: i32,
+
+    // How slippery or sticky the tiles under the player are, as percentages.
+    surface_acceleration: i32,
+    surface_friction: i32,
+
+    pub current_platform: Option<usize>,
+    current_slopes: SmallIntSet<TileIndex>,
+    current_switch_tiles: SmallIntSet<TileIndex>,
+}
+
+fn write_tiles(writer: &mut BinaryWriter, tiles: &SmallIntSet<TileIndex>) {
+    writer.write_varint(tiles.iter().len() as u64);
+    for tile in tiles.iter() {
+        writer.write_varint(usize::from(*tile) as u64);
+    }
+}
+
+fn read_tiles(reader: &mut BinaryReader) -> Result<SmallIntSet<TileIndex>> {
+    let mut tiles = SmallIntSet::new();
+    let count = reader.read_varint()?;
+    for _ in 0..count {
+        tiles.insert((reader.read_varint()? as usize).into());
+    }
+    Ok(tiles)
+}
+
+impl MovementState {
+    pub fn new(physics: &PhysicsConfig) -> MovementState {
+        MovementState {
+            wall_stick_counter: physics.wall_stick_time,
+            wall_stick_facing_right: false,
+            wall_slide_counter: physics.wall_slide_time,
+            coyote_counter: physics.coyote_time,
+            jump_grace_counter: 0,
+            spring_counter: 0,
+            surface_acceleration: 100,
+            surface_friction: 100,
+            current_platform: None,
+            current_slopes: SmallIntSet::new(),
+            current_switch_tiles: SmallIntSet::new(),
+        }
+    }
+
+    // Forgets everything the player was doing when they died.
+    pub fn respawn(&mut self, physics: &PhysicsConfig) {
+        self.wall_stick_counter = physics.wall_stick_time;
+        self.wall_slide_counter = physics.wall_slide_time;
+        self.coyote_counter = physics.coyote_time;
+        self.jump_grace_counter = 0;
+        self.spring_counter = 0;
+        self.surface_acceleration = 100;
+        self.surface_friction = 100;
+        self.current_platform = None;
+        self.current_slopes.clear();
+        self.current_switch_tiles.clear();
+    }
+
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        writer.write_i32(self.wall_stick_counter);
+        writer.write_bool(self.wall_stick_facing_right);
+        writer.write_i32(self.wall_slide_counter);
+        writer.write_i32(self.coyote_counter);
+        writer.write_i32(self.jump_grace_counter);
+        writer.write_i32(self.spring_counter);
+        writer.write_i32(self.surface_acceleration);
+        writer.write_i32(self.surface_friction);
+        writer.write_index(self.current_platform);
+        write_tiles(writer, &self.current_slopes);
+        write_tiles(writer, &self.current_switch_tiles);
+    }
+
+    pub fn restore_state(
+        &mut self,
+        reader: &mut BinaryReader,
+        platform_count: usize,
+    ) -> Result<()> {
+        self.wall_stick_counter = reader.read_i32()?;
+        self.wall_stick_facing_right = reader.read_bool()?;
+        self.wall_slide_counter = reader.read_i32()?;
+        self.coyote_counter = reader.read_i32()?;
+        self.jump_grace_counter = reader.read_i32()?;
+        self.spring_counter = reader.read_i32()?;
+        self.surface_acceleration = reader.read_i32()?;
+        self.surface_friction = reader.read_i32()?;
+        self.current_platform = reader.read_index(platform_count)?;
+        self.current_slopes = read_tiles(reader)?;
+        self.current_switch_tiles = read_tiles(reader)?;
+        Ok(())
+    }
+}
+
+/*
+ * Moves a player by the rules of the game, for one frame.
+ *
+ * This borrows just the parts of a level that the player can bump into, so the same rules
+ * can also move a player that only runs against a map, like a ghost.
+ */
+pub struct Movement<'a> {
+    pub player: &'a mut Player,
+    pub state: &'a mut MovementState,
+    pub map: &'a TileMap,
+    pub physics: &'a PhysicsConfig,
+    pub platforms: &'a mut [Platform],
+    pub switches: &'a mut SwitchState,
+    // The door the player is standing in front of, if any.
+    pub door: Option<&'a mut Door>,
+}
+
+impl Movement<'_> {
+    // Moves the player and updates their state, and returns what happened along the way.
+    pub fn update(
+        &mut self,
+        inputs: &InputSnapshot,
+        sounds: &mut SoundManager,
+    ) -> PlayerMovementResult {
+        let movement = match self.player.state {
+            PlayerState::Stopped => PlayerMovementResult {
+                on_ground: false,
+                pushing_against_wall: false,
+                jump_down: false,
+                jump_triggered: false,
+                jump_without_up_triggered: false,
+                crouch_down: false,
+                up_down: false,
+                on_ladder: false,
+                ladder_below: false,
+                in_water: false,
+                _stuck_in_wall: false,
+                crushed_by_platform: false,
+            },
+            _ => self.update_player_movement(inputs, sounds),
+        };
+        self.update_player_state(movement);
+        movement
+    }
+
+    fn update_player_trajectory_x(&mut self, inputs: &InputSnapshot) {
+        // Ice and sticky tiles change how quickly the player speeds up and slows down.
+        let acceleration = scale_by_percent(
+            self.physics.walk_speed_acceleration,
+            self.state.surface_acceleration,
+        );
+        let deceleration = scale_by_percent(
+            self.physics.walk_speed_deceleration,
+            self.state.surface_friction,
+        );
+        let slide_deceleration = scale_by_percent(
+            self.physics.slide_speed_deceleration,
+            self.state.surface_friction,
+        );
+
+        if matches!(self.player.state, PlayerState::Crouching) {
+            match self.player.delta.x.cmp(&Subpixels::zero()) {
+                Ordering::Greater => {
+                    self.player.delta.x =
+                        (self.player.delta.x - slide_deceleration).max(Subpixels::zero());
+                }
+                Ordering::Less => {
+                    self.player.delta.x =
+                        (self.player.delta.x + slide_deceleration).min(Subpixels::zero());
+                }
+                Ordering::Equal => {}
+            }
+            return;
+        }
+
+        // Apply controller input. A stick that's only pushed part of the way walks more slowly,
+        // so that gamepad players can position themselves precisely.
+        let mut target_dx = Subpixels::zero();
+        if inputs.player_walk_x != 0 {
+            target_dx =
+                self.physics.target_walk_speed * inputs.player_walk_x as i32 / i8::MAX as i32;
+        } else if inputs.player_left_down && !inputs.player_right_down {
+            target_dx = self.physics.target_walk_speed * -1;
+        } else if inputs.player_right_down && !inputs.player_left_down {
+            target_dx = self.physics.target_walk_speed;
+        }
+
+        // Change the velocity toward the target velocity.
+        match self.player.delta.x.cmp(&Subpixels::zero()) {
+            Ordering::Greater => {
+                // We're facing right.
+                if target_dx > self.player.delta.x {
+                    self.player.delta.x += acceleration;
+                    self.player.delta.x = self.player.delta.x.min(target_dx);
+                }
+                if target_dx < self.player.delta.x {
+                    self.player.delta.x -= deceleration;
+                    self.player.delta.x = self.player.delta.x.max(target_dx);
+                }
+            }
+            Ordering::Less => {
+                // We're facing left.
+                if target_dx > self.player.delta.x {
+                    self.player.delta.x += deceleration;
+                    self.player.delta.x = self.player.delta.x.min(target_dx);
+                }
+                if target_dx < self.player.delta.x {
+                    self.player.delta.x -= acceleration;
+                    self.player.delta.x = self.player.delta.x.max(target_dx);
+                }
+            }
+            Ordering::Equal => {
+                // We're stopped.
+                if target_dx > self.player.delta.x {
+                    self.player.delta.x += acceleration;
+                    self.player.delta.x = self.player.delta.x.min(target_dx);
+                }
+                if target_dx < self.player.delta.x {
+                    self.player.delta.x -= acceleration;
+                    self.player.delta.x = self.player.delta.x.max(target_dx);
+                }
+            }
+        }
+    }
+
+    fn update_player_trajectory_y(&mut self, inputs: &InputSnapshot) {
+        let gravity = self.physics.max_gravity;
+        match self.player.state {
+            PlayerState::Standing | PlayerState::Crouching => {
+                // Fall at least one pixel so that we hit the ground again.
+                self.player.delta.y = self.player.delta.y.max(Subpixels::new(1));
+            }
+            PlayerState::Jumping => {
+                // Apply gravity.
+                if self.player.delta.y < gravity {
+                    self.player.delta.y += self.physics.jump_acceleration;
+                }
+                self.player.delta.y = self.player.delta.y.min(gravity);
+            }
+            PlayerState::Falling => {
+                // Apply gravity.
+                if self.player.delta.y < gravity {
+                    self.player.delta.y += self.physics.fall_acceleration;
+                }
+                self.player.delta.y = self.player.delta.y.min(gravity);
+            }
+            PlayerState::WallSliding => {
+                // When you first grab the wall, don't start sliding for a while.
+                if self.state.wall_slide_counter > 0 {
+                    self.state.wall_slide_counter -= 1;
+                    self.player.delta.y = Subpixels::zero();
+                } else {
+                    self.player.delta.y = self.physics.wall_slide_speed;
+                }
+            }
+            PlayerState::Swimming => {
+                // Water slows the player's fall.
+                if self.player.delta.y < self.physics.swim_max_gravity {
+                    self.player.delta.y += self.physics.swim_fall_acceleration;
+                }
+                self.player.delta.y = self.player.delta.y.min(self.physics.swim_max_gravity);
+            }
+            PlayerState::Climbing => {
+                // There's no gravity on a ladder, so the player only moves while climbing.
+                self.player.delta.y = Subpixels::zero();
+                if inputs.player_up_down && !inputs.player_crouch_down {
+                    self.player.delta.y = self.physics.climb_speed * -1;
+                }
+                if inputs.player_crouch_down && !inputs.player_up_down {
+                    self.player.delta.y = self.physics.climb_speed;
+                }
+            }
+            PlayerState::Stopped => {}
+        }
+    }
+
+    fn find_platform_intersections(
+        &self,
+        player_rect: Rect<Subpixels>,
+        direction: Direction,
+        is_backwards: bool,
+    ) -> PlatformIntersectionResult {
+        let mut result = PlatformIntersectionResult {
+            offset: Subpixels::zero(),
+            platforms: SmallIntSet::new(),
+        };
+        for (i, platform) in self.platforms.iter().enumerate() {
+            let distance = platform.try_move_to(player_rect, direction, is_backwards);
+            if distance.is_zero() {
+                continue;
+            }
+
+            match cmp_in_direction(distance, result.offset, direction) {
+                Ordering::Less => {
+                    result.offset = distance;
+                    result.platforms = SmallIntSet::new();
+                    result.platforms.insert(i);
+                }
+                Ordering::Equal => {
+                    result.platforms.insert(i);
+                }
+                Ordering::Greater => {}
+            }
+        }
+        result
+    }
+
+    // Returns how far this player needs to move in direction to not intersect, in sub-pixels.
+    fn try_move_player(&self, direction: Direction, is_backwards: bool) -> TryMovePlayerResult {
+        let player_rect = self.player.get_target_bounds_rect(Some(direction));
+
+        let mut map_result =
+            self.map
+                .try_move_to(player_rect, direction, self.switches, is_backwards);
+        // The top of a ladder holds the player up, unless they're climbing down through it.
+        if matches!(direction, Direction::Down)
+            && !is_backwards
+            && !matches!(self.player.state, PlayerState::Climbing)
+        {
+            let ladder_offset = self.map.try_move_onto_ladder(player_rect, self.switches);
+            if matches!(
+                cmp_in_direction(ladder_offset, map_result.hard_offset, direction),
+                Ordering::Less
+            ) {
+                map_result.hard_offset = ladder_offset;
+                map_result.tile_ids = SmallIntSet::new();
+            }
+        }
+        let platform_result =
+            self.find_platform_intersections(player_rect, direction, is_backwards);
+
+        match cmp_in_direction(platform_result.offset, map_result.hard_offset, direction) {
+            Ordering::Less | Ordering::Equal => TryMovePlayerResult {
+                offset: platform_result.offset,
+                platforms: platform_result.platforms,
+                tile_ids: SmallIntSet::new(),
+            },
+            Ordering::Greater => TryMovePlayerResult {
+                offset: map_result.hard_offset,
+                platforms: SmallIntSet::new(),
+                tile_ids: map_result.tile_ids,
+            },
+        }
+    }
+
+    // Returns whether the first move hit a wall or platform.
+    fn move_and_check(
+        &mut self,
+        forward: Direction,
+        apply_offset: fn(&mut Player, Subpixels) -> (),
+    ) -> MoveAndCheckResult {
+        let move_result1 = self.try_move_player(forward, false);
+        apply_offset(self.player, move_result1.offset);
+
+        // Try the opposite direction.
+        let move_result2 = self.try_move_player(forward.opposite(), true);
+        let offset = move_result2.offset;
+        apply_offset(self.player, offset);
+
+        let mut hit_solid_platform1 = false;
+        for platform in move_result1.platforms.iter() {
+            if self.platforms[*platform].is_solid() {
+                hit_solid_platform1 = true;
+            }
+        }
+        let mut hit_solid_platform2 = false;
+        for platform in move_result2.platforms.iter() {
+            if self.platforms[*platform].is_solid() {
+                hit_solid_platform2 = true;
+            }
+        }
+
+        let mut result = MoveAndCheckResult {
+            on_ground: false,
+            on_tile_ids: SmallIntSet::new(),
+            on_platforms: SmallIntSet::new(),
+            hit_ceiling: false,
+            against_wall: false,
+            crushed_by_platform: false,
+            stuck_in_wall: false,
+        };
+        match forward {
+            Direction::Down => {
+                result.on_ground = !move_result1.offset.is_zero();
+                result.on_tile_ids = move_result1.tile_ids;
+                result.on_platforms = move_result1.platforms;
+            }
+            Direction::Up => {
+                // If we're traveling up, then if we hit something below, it's not the ground,
+                // unless we're standing on a platform.
+                if !matches!(
+                    self.player.state,
+                    PlayerState::Jumping | PlayerState::Falling | PlayerState::Climbing
+                ) {
+                    result.on_ground = !move_result2.offset.is_zero();
+                }
+                result.hit_ceiling = !move_result1.offset.is_zero();
+                result.on_tile_ids = move_result2.tile_ids;
+                result.on_platforms = move_result2.platforms;
+            }
+            Direction::Left | Direction::Right => {
+                result.against_wall = !move_result1.offset.is_zero()
+            }
+        }
+
+        // See if we're crushed.
+        if !offset.is_zero() {
+            let crush_check = self.try_move_player(forward, false);
+            if !crush_check.offset.is_zero() {
+                let crushed = hit_solid_platform1 || hit_solid_platform2;
+                if crushed {
+                    result.crushed_by_platform = true;
+                } else {
+                    result.stuck_in_wall = true;
+                }
+            }
+        }
+
+        result
+    }
+
+    fn move_player_x(&mut self, inputs: &InputSnapshot) -> MovePlayerXResult {
+        let mut dx = self.player.delta.x;
+        if let Some(current_platform) = self.state.current_platform {
+            dx += self.platforms[current_platform].dx();
+        }
+        self.player.position.x += dx;
+
+        let (move_result, pushing) =
+            if dx < Subpixels::zero() || (dx.is_zero() && !self.player.facing_right) {
+                // Moving left.
+                let move_result = self.move_and_check(Direction::Left, inc_player_x);
+                let pushing = inputs.player_left_down;
+                (move_result, pushing)
+            } else {
+                // Moving right.
+                let move_result = self.move_and_check(Direction::Right, inc_player_x);
+                let pushing = inputs.player_right_down;
+                (move_result, pushing)
+            };
+
+        let result = MovePlayerXResult {
+            pushing_against_wall: pushing && move_result.against_wall,
+            crushed_by_platform: move_result.crushed_by_platform,
+            stuck_in_wall: move_result.stuck_in_wall,
+        };
+
+        // If you're against the wall, you're stopped.
+        if result.pushing_against_wall {
+            self.player.delta.x = Subpixels::zero();
+        }
+
+        result
+    }
+
+    fn get_slope_dy(&self) -> Subpixels {
+        let mut slope_fall = Subpixels::zero();
+        for slope_id in self.state.current_slopes.iter() {
+            let slope = self.map.get_slope(*slope_id).expect("must be valid");
+            let left_y = slope.left_y;
+            let right_y = slope.right_y;
+            let mut fall: Subpixels = Subpixels::zero();
+            if self.player.delta.x > Subpixels::zero()
+                || (self.player.delta.x.is_zero() && self.player.facing_right)
+            {
+                // The player is facing right.
+                if right_y > left_y {
+                    fall = right_y - left_y;
+                }
+            } else {
+                // The player is facing left.
+                if left_y > right_y {
+                    fall = left_y - right_y;
+                }
+            }
+            slope_fall = slope_fall.max(fall);
+        }
+        slope_fall
+    }
+
+    fn move_player_y(&mut self, sounds: &mut SoundManager) -> MovePlayerYResult {
+        let mut dy = self.player.delta.y;
+        if let Some(current_platform) = self.state.current_platform {
+            // This could be positive or negative.
+            dy += self.platforms[current_platform].dy();
+        }
+
+        // If you're on a slope, make sure to fall at least the slope amount.
+        if dy >= Subpixels::zero() {
+            dy = dy.max(self.get_slope_dy());
+        }
+
+        self.player.position.y += dy;
+
+        if dy <= Subpixels::zero() {
+            // Moving up.
+            let move_result = self.move_and_check(Direction::Up, inc_player_y);
+            if move_result.hit_ceiling {
+                self.player.delta.y = Subpixels::zero();
+            }
+
+            self.handle_slopes(&move_result.on_tile_ids);
+            self.handle_surfaces(&move_result.on_tile_ids);
+            self.handle_current_platforms(&move_result.on_platforms);
+
+            MovePlayerYResult {
+                on_ground: move_result.on_ground,
+                crushed_by_platform: move_result.crushed_by_platform,
+                stuck_in_wall: move_result.stuck_in_wall,
+                _platforms: SmallIntSet::new(),
+                _tile_ids: SmallIntSet::new(),
+            }
+        } else {
+            // Moving down.
+            let move_result = self.move_and_check(Direction::Down, inc_player_y);
+
+            self.handle_spikes(&move_result.on_tile_ids);
+            self.handle_switch_tiles(&move_result.on_tile_ids, sounds);
+            self.handle_slopes(&move_result.on_tile_ids);
+            self.handle_surfaces(&move_result.on_tile_ids);
+            self.handle_current_platforms(&move_result.on_platforms);
+
+            MovePlayerYResult {
+                on_ground: move_result.on_ground,
+                _tile_ids: move_result.on_tile_ids,
+                _platforms: move_result.on_platforms,
+                crushed_by_platform: move_result.crushed_by_platform,
+                stuck_in_wall: move_result.stuck_in_wall,
+            }
+        }
+    }
+
+    fn handle_slopes(&mut self, tiles: &SmallIntSet<TileIndex>) {
+        self.state.current_slopes.clear();
+        for tile_id in tiles.iter() {
+            if let Some(TileProperties { slope: true, .. }) = self.map.get_tile_properties(*tile_id)
+            {
+                self.state.current_slopes.insert(*tile_id);
+            }
+        }
+    }
+
+    // If the player is on more than one kind of surface, the one with the most grip wins.
+    fn handle_surfaces(&mut self, tiles: &SmallIntSet<TileIndex>) {
+        let surfaces: Vec<(i32, i32)> = tiles
+            .iter()
+            .map(|tile_id| match self.map.get_tile_properties(*tile_id) {
+                Some(properties) => (properties.acceleration, properties.friction),
+                None => (100, 100),
+            })
+            .collect();
+        self.state.surface_acceleration = surfaces.iter().map(|s| s.0).max().unwrap_or(100);
+        self.state.surface_friction = surfaces.iter().map(|s| s.1).max().unwrap_or(100);
+    }
+
+    fn handle_spikes(&mut self, tiles: &SmallIntSet<TileIndex>) {
+        for tile_id in tiles.iter() {
+            if let Some(TileProperties { deadly: true, .. }) =
+                self.map.get_tile_properties(*tile_id)
+            {
+                self.player.is_dead = true;
+            }
+        }
+    }
+
+    fn handle_current_platforms(&mut self, platforms: &SmallIntSet<usize>) {
+        self.state.current_platform = None;
+        for platform in self.platforms.iter_mut() {
+            platform.set_occupied(false);
+        }
+
+        for platform_index in platforms.iter() {
+            let platform = &mut self.platforms[*platform_index];
+
+            platform.set_occupied(true);
+            // TODO: Be smarter about what platform we pick.
+            self.state.current_platform = Some(*platform_index);
+        }
+    }
+
+    fn handle_switch_tiles(&mut self, tiles: &SmallIntSet<TileIndex>, sounds: &mut SoundManager) {
+        let new_switch_tiles = SmallIntSet::new();
+        let previous = mem::replace(&mut self.state.current_switch_tiles, new_switch_tiles);
+        for t in tiles.iter() {
+            let Some(TileProperties {
+                switch: Some(switch),
+                ..
+            }) = self.map.get_tile_properties(*t)
+            else {
+                continue;
+            };
+            self.state.current_switch_tiles.insert(*t);
+            if previous.contains(*t) {
+                continue;
+            }
+            sounds.play(Sound::Click);
+            self.switches.apply_commands(switch);
+        }
+    }
+
+    fn update_player_movement(
+        &mut self,
+        inputs: &InputSnapshot,
+        sounds: &mut SoundManager,
+    ) -> PlayerMovementResult {
+        self.update_player_trajectory_x(inputs);
+        self.update_player_trajectory_y(inputs);
+
+        let x_result = self.move_player_x(inputs);
+        let y_result = self.move_player_y(sounds);
+
+        let player_rect = self.player.get_target_bounds_rect(None);
+        let on_ladder = self.map.is_climbable(player_rect, self.switches);
+        let feet = Rect {
+            x: player_rect.x,
+            y: player_rect.bottom(),
+            w: player_rect.w,
+            h: Subpixels::new(1),
+        };
+        let ladder_below = self.map.is_climbable(feet, self.switches);
+        let in_water = self.map.is_in_water(player_rect);
+
+        PlayerMovementResult {
+            on_ground: y_result.on_ground,
+            pushing_against_wall: x_result.pushing_against_wall,
+            jump_down: inputs.player_jump_down,
+            jump_triggered: inputs.player_jump_clicked,
+            jump_without_up_triggered: inputs.player_jump_without_up_clicked,
+            crouch_down: inputs.player_crouch_down,
+            up_down: inputs.player_up_down,
+            on_ladder,
+            ladder_below,
+            in_water,
+            _stuck_in_wall: x_result.stuck_in_wall || y_result.stuck_in_wall,
+            crushed_by_platform: x_result.crushed_by_platform || y_result.crushed_by_platform,
+        }
+    }
+
+    fn update_player_state(&mut self, movement: PlayerMovementResult) {
+        if movement.on_ground {
+            self.state.coyote_counter = self.physics.coyote_time;
+        } else if self.state.coyote_counter > 0 {
+            self.state.coyote_counter -= 1;
+        }
+
+        if self.state.jump_grace_counter > 0 {
+            self.state.jump_grace_counter -= 1;
+        }
+
+        if movement.crushed_by_platform {
+            self.player.state = PlayerState::Stopped;
+            self.player.is_dead = true;
+        } else if movement.in_water
+            && !matches!(
+                self.player.state,
+                PlayerState::Swimming | PlayerState::Stopped
+            )
+        {
+            // Landing in water slows the player down right away.
+            self.player.state = PlayerState::Swimming;
+            self.player.delta.y = self.player.delta.y.min(self.physics.swim_max_gravity);
+        } else {
+            match self.player.state {
+                PlayerState::Stopped => {}
+                PlayerState::Standing => {
+                    let launch = if let Some(Platform {
+                        subtype: PlatformType::Spring(spring),
+                        ..
+                    }) = self.state.current_platform.map(|i| &self.platforms[i])
+                    {
+                        spring.launch
+                    } else {
+                        false
+                    };
+                    if launch {
+                        self.state.jump_grace_counter = 0;
+                        self.bounce(movement.jump_triggered);
+                    } else if self.state.coyote_counter == 0 {
+                        self.player.state = PlayerState::Falling;
+                        self.player.delta.y = Subpixels::zero();
+                        if let Some(current_platform) = self.state.current_platform {
+                            self.player.delta.x = self.platforms[current_platform].dx();
+                        }
+                    } else if movement.on_ladder
+                        && movement.up_down
+                        && !movement.jump_without_up_triggered
+                    {
+                        // Up is usually also jump, so check for ladders first.
+                        self.start_climbing();
+                    } else if movement.ladder_below && movement.crouch_down {
+                        self.start_climbing();
+                    } else if movement.crouch_down {
+                        self.player.state = PlayerState::Crouching;
+                    } else if movement.jump_triggered || self.state.jump_grace_counter > 0 {
+                        if let Some(door) = self.door.as_mut() {
+                            if door.is_open() {
+                                self.player.state = PlayerState::Stopped;
+                                door.close();
+                            }
+                        } else {
+                            self.state.jump_grace_counter = 0;
+                            self.player.state = PlayerState::Jumping;
+                            let should_boost = if let Some(Platform {
+                                subtype: PlatformType::Spring(spring),
+                                ..
+                            }) =
+                                self.state.current_platform.map(|i| &self.platforms[i])
+                            {
+                                spring.should_boost()
+                            } else {
+                                false
+                            };
+                            if should_boost {
+                                self.state.spring_counter = SPRING_JUMP_DURATION;
+                                self.player.delta.y = SPRING_JUMP_VELOCITY * -1;
+                            } else {
+                                self.state.spring_counter = 0;
+                                self.player.delta.y = self.physics.jump_initial_speed * -1;
+                            }
+                            if let Some(current_platform) = self.state.current_platform {
+                                self.player.delta.x += self.platforms[current_platform].dx();
+                            }
+                        }
+                    }
+                }
+                PlayerState::Falling => {
+                    if movement.jump_triggered {
+                        self.state.jump_grace_counter = self.physics.jump_grace_time;
+                    }
+                    if movement.on_ground {
+                        self.player.state = PlayerState::Standing;
+                        self.player.delta.y = Subpixels::zero();
+                    } else if movement.on_ladder && (movement.up_down || movement.crouch_down) {
+                        self.start_climbing();
+                    } else {
+                        if movement.pushing_against_wall && self.player.delta.y >= Subpixels::zero()
+                        {
+                            self.player.state = PlayerState::WallSliding;
+                            self.state.wall_slide_counter = self.physics.wall_slide_time;
+                        }
+                    }
+                }
+                PlayerState::Jumping => {
+                    if movement.on_ground {
+                        self.player.state = PlayerState::Standing;
+                        self.player.delta.y = Subpixels::zero();
+                    } else if movement.on_ladder && (movement.up_down || movement.crouch_down) {
+                        self.start_climbing();
+                    } else if self.player.delta.y >= Subpixels::zero() {
+                        self.player.state = PlayerState::Falling;
+                    } else {
+                        if !movement.jump_down {
+                            if self.state.spring_counter == 0 {
+                                self.player.state = PlayerState::Falling;
+                                self.player.delta.y = Subpixels::zero();
+                            } else {
+                                self.state.spring_counter -= 1;
+                            }
+                        }
+                    }
+                }
+                PlayerState::WallSliding => {
+                    if movement.jump_triggered {
+                        self.player.state = PlayerState::Jumping;
+                        self.player.delta.y = self.physics.wall_jump_vertical_speed * -1;
+                        if self.player.facing_right {
+                            self.player.delta.x = self.physics.wall_jump_horizontal_speed * -1;
+                        } else {
+                            self.player.delta.x = self.physics.wall_jump_horizontal_speed;
+                        }
+                    } else if movement.on_ground {
+                        self.player.state = PlayerState::Standing;
+                    } else if movement.pushing_against_wall {
+                        self.state.wall_stick_counter = self.physics.wall_stick_time;
+                        self.state.wall_stick_facing_right = self.player.facing_right;
+                    } else {
+                        if self.state.wall_stick_facing_right != self.player.facing_right {
+                            self.player.state = PlayerState::Falling;
+                        } else if self.state.wall_stick_counter > 0 {
+                            self.state.wall_stick_counter -= 1;
+                        } else {
+                            self.player.state = PlayerState::Falling;
+                        }
+                    }
+                }
+                PlayerState::Crouching => {
+                    if !movement.on_ground {
+                        self.player.state = PlayerState::Falling;
+                        self.player.delta.y = Subpixels::zero();
+                    } else if !movement.crouch_down {
+                        self.player.state = PlayerState::Standing;
+                    }
+                }
+                PlayerState::Climbing => {
+                    // Jump keys that are also up keep climbing, since they're for going up.
+                    if movement.jump_without_up_triggered {
+                        self.player.state = PlayerState::Jumping;
+                        self.state.spring_counter = 0;
+                        self.player.delta.y = self.physics.jump_initial_speed * -1;
+                    } else if movement.on_ground {
+                        self.player.state = PlayerState::Standing;
+                        self.player.delta.y = Subpixels::zero();
+                    } else if !movement.on_ladder {
+                        if self.player.delta.y < Subpixels::zero() && self.step_onto_ladder() {
+                            // Climbing off the top of a ladder leaves the player standing on it.
+                            self.player.state = PlayerState::Standing;
+                        } else {
+                            self.player.state = PlayerState::Falling;
+                        }
+                        self.player.delta.y = Subpixels::zero();
+                    }
+                }
+                PlayerState::Swimming => {
+                    if !movement.in_water {
+                        // Holding jump at the surface leaps out of the water, so the player
+                        // can get back onto land.
+                        if movement.jump_down && self.player.delta.y < Subpixels::zero() {
+                            self.player.state = PlayerState::Jumping;
+                            self.state.spring_counter = 0;
+                            self.player.delta.y = self.physics.jump_initial_speed * -1;
+                        } else {
+                            self.player.state = PlayerState::Falling;
+                        }
+                    } else if movement.jump_triggered {
+                        // Every press of jump is another stroke upward.
+                        self.player.delta.y = self.physics.swim_stroke_speed * -1;
+                    } else if movement.on_ground {
+                        self.player.delta.y = Subpixels::zero();
+                    }
+                }
+            }
+        }
+    }
+
+    // Launches the player into the air, higher if they're jumping at the same time.
+    pub fn bounce(&mut self, jumping: bool) {
+        self.player.state = PlayerState::Jumping;
+        if jumping {
+            self.state.spring_counter = SPRING_JUMP_DURATION;
+            self.player.delta.y = SPRING_JUMP_VELOCITY * -1;
+        } else {
+            self.state.spring_counter = SPRING_BOUNCE_DURATION;
+            self.player.delta.y = SPRING_BOUNCE_VELOCITY * -1;
+        }
+    }
+
+    // Moves the player down onto the top of the ladder they just climbed off of, if any.
+    fn step_onto_ladder(&mut self) -> bool {
+        let player_rect = self.player.get_target_bounds_rect(None);
+        let tileheight = self.map.tileheight.as_subpixels();
+        let rows = (player_rect.bottom() + tileheight - Subpixels::new(1)) / tileheight;
+        let top = tileheight * rows;
+        let feet = Rect {
+            x: player_rect.x,
+            y: top,
+            w: player_rect.w,
+            h: Subpixels::new(1),
+        };
+        if !self.map.is_climbable(feet, self.switches) {
+            return false;
+        }
+        self.player.position.y += top - player_rect.bottom();
+        true
+    }
+
+    // Grabs onto a ladder, stopping wherever the player was headed.
+    fn start_climbing(&mut self) {
+        self.player.state = PlayerState::Climbing;
+        self.player.delta = Point::zero();
+        self.state.jump_grace_counter = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use crate::geometry::Pixels;
+    use crate::testing::{test_files, TestImages, TEST_MAP_PATH};
+
+    // A ladder from a hole in an upper floor down to the ground.
+    const LADDER_ROWS: &[&str] = &[
+        "............",
+        "............",
+        "............",
+        "#H##########",
+        ".H..........",
+        ".H..........",
+        ".H..........",
+        "############",
+    ];
+    // Normal ground, then ice, then sticky ground.
+    const SURFACE_ROWS: &[&str] = &[
+        "..................................................",
+        "..................................................",
+        "..................................................",
+        "##########IIIIIIIIIIIIIIIIIIIISSSSSSSSSSSSSSSSSSSS",
+    ];
+    const SPAWN_OBJECTS: &str = r#"
+<object id="1" x="0" y="0">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+"#;
+
+    struct Runner {
+        player: Player,
+        state: MovementState,
+        map: TileMap,
+        physics: PhysicsConfig,
+        switches: SwitchState,
+    }
+
+    impl Runner {
+        fn new(rows: &[&str]) -> Runner {
+            Runner::with_objects(rows, SPAWN_OBJECTS)
+        }
+
+        fn with_objects(rows: &[&str], objects: &str) -> Runner {
+            let files = test_files(rows, objects);
+            let map = TileMap::from_file(Path::new(TEST_MAP_PATH), &files, &mut TestImages)
+                .expect("test map should load");
+            let physics = PhysicsConfig::default();
+            let player =
+                Player::for_map(&map, &files, &mut TestImages).expect("test player should load");
+            Runner {
+                player,
+                state: MovementState::new(&physics),
+                map,
+                physics,
+                switches: SwitchState::new(),
+            }
+        }
+
+        // Runs until done says to stop, and returns whether it did within the frames.
+        fn run_until(
+            &mut self,
+            inputs: &InputSnapshot,
+            frames: usize,
+            done: impl Fn(&Player) -> bool,
+        ) -> bool {
+            let mut sounds = SoundManager::noop_manager();
+            for _ in 0..frames {
+                Movement {
+                    player: &mut self.player,
+                    state: &mut self.state,
+                    map: &self.map,
+                    physics: &self.physics,
+                    platforms: &mut [],
+                    switches: &mut self.switches,
+                    door: None,
+                }
+                .update(inputs, &mut sounds);
+                if done(&self.player) {
+                    return true;
+                }
+            }
+            false
+        }
+    }
+
+    fn is_standing(player: &Player) -> bool {
+        matches!(player.state, PlayerState::Standing)
+    }
+
+    #[test]
+    fn climbs_up_and_down_ladders() {
+        let mut runner = Runner::new(LADDER_ROWS);
+        let up = InputSnapshot {
+            player_up_down: true,
+            ..Default::default()
+        };
+        let down = InputSnapshot {
+            player_crouch_down: true,
+            ..Default::default()
+        };
+        // The top of the ladder is 24 pixels down, and the player's feet are 23 pixels down.
+        let on_top = Pixels::new(1).as_subpixels();
+        let on_ground = Pixels::new(33).as_subpixels();
+
+        // The player lands on top of the ladder, and can climb down from there.
+        assert!(runner.run_until(&InputSnapshot::default(), 60, is_standing));
+        assert_eq!(runner.player.position.y, on_top);
+        assert!(runner.run_until(&down, 60, is_standing));
+        assert_eq!(runner.player.position.y, on_ground);
+
+        // Climbing back up stops on top, even if up is still held.
+        assert!(runner.run_until(&up, 60, is_standing));
+        assert_eq!(runner.player.position.y, on_top);
+        assert!(!runner.run_until(&up, 30, |player| !is_standing(player)));
+        assert_eq!(runner.player.position.y, on_top);
+
+        // A jump key that's also up keeps climbing, but any other jump lets go.
+        runner.run_until(&down, 10, |_| false);
+        let climbing_jump = InputSnapshot {
+            player_jump_clicked: true,
+            ..up
+        };
+        runner.run_until(&climbing_jump, 1, |_| false);
+        assert!(matches!(runner.player.state, PlayerState::Climbing));
+        let jump = InputSnapshot {
+            player_jump_without_up_clicked: true,
+            ..climbing_jump
+        };
+        runner.run_until(&jump, 1, |_| false);
+        assert!(matches!(runner.player.state, PlayerState::Jumping));
+    }
+
+    // A pool that fills the bottom of the map, below where the player spawns.
+    const POOL_ROWS: &[&str] = &[
+        "....", "....", "....", "....", "....", "....", "....", "....", "....", "....", "....",
+        "####",
+    ];
+
+    const POOL_OBJECTS: &str = r#"
+<object id="1" x="0" y="0">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+<object id="2" x="0" y="40" width="32" height="48">
+ <properties><property name="water" type="bool" value="true"/></properties>
+</object>
+"#;
+
+    #[test]
+    fn swims_in_water() {
+        let mut runner = Runner::with_objects(POOL_ROWS, POOL_OBJECTS);
+        let is_swimming = |player: &Player| matches!(player.state, PlayerState::Swimming);
+        let max_gravity = runner.physics.swim_max_gravity;
+
+        // Falling into the water slows the player down right away.
+        assert!(runner.run_until(&InputSnapshot::default(), 60, is_swimming));
+        assert!(runner.player.delta.y <= max_gravity);
+
+        // The fall speed never goes over the cap, all the way down to the bottom.
+        let mut sinking = Vec::new();
+        let on_bottom = |player: &Player| player.position.y == Pixels::new(65).as_subpixels();
+        while !on_bottom(&runner.player) {
+            assert!(sinking.len() < 120, "player should reach the bottom");
+            runner.run_until(&InputSnapshot::default(), 1, |_| false);
+            sinking.push(runner.player.delta.y);
+        }
+        assert!(sinking.iter().all(|delta| *delta <= max_gravity));
+        assert!(sinking.contains(&max_gravity));
+        assert!(is_swimming(&runner.player));
+
+        // Each press of jump is a stroke upward, and then the player sinks again, more
+        // slowly than they'd fall in the air.
+        let stroke = InputSnapshot {
+            player_jump_clicked: true,
+            player_jump_down: true,
+            ..Default::default()
+        };
+        runner.run_until(&stroke, 1, |_| false);
+        assert_eq!(runner.player.delta.y, runner.physics.swim_stroke_speed * -1);
+        let y = runner.player.position.y;
+        runner.run_until(&InputSnapshot::default(), 1, |_| false);
+        assert!(runner.player.position.y < y);
+        assert_eq!(
+            runner.player.delta.y,
+            runner.physics.swim_stroke_speed * -1 + runner.physics.swim_fall_acceleration
+        );
+        assert!(runner.run_until(&InputSnapshot::default(), 120, on_bottom));
+        assert!(is_swimming(&runner.player));
+    }
+
+    #[test]
+    fn ice_and_sticky_tiles_change_speed() {
+        let mut runner = Runner::new(SURFACE_ROWS);
+        let right = InputSnapshot {
+            player_right_down: true,
+            ..Default::default()
+        };
+        let stopped = |player: &Player| player.delta.x.is_zero();
+        let walk_speed = runner.physics.target_walk_speed;
+        assert!(runner.run_until(&InputSnapshot::default(), 60, is_standing));
+
+        // Walk all the way onto the ice, then let go. Ice has a tenth of the friction, which
+        // is less than a subpixel, so it slows by just one subpixel each frame.
+        let on_ice = Pixels::new(80).as_subpixels();
+        assert!(runner.run_until(&right, 60, |player| player.position.x >= on_ice));
+        assert_eq!(runner.player.delta.x, walk_speed);
+        runner.run_until(&InputSnapshot::default(), 10, |_| false);
+        assert_eq!(runner.player.delta.x, walk_speed - Subpixels::new(10));
+        assert!(runner.run_until(&InputSnapshot::default(), 60, stopped));
+
+        // Starting to walk on ice is slow too, but the player can still get going.
+        runner.run_until(&right, 10, |_| false);
+        assert_eq!(runner.player.delta.x, Subpixels::new(10));
+
+        // Sticky ground has three times the friction, so the player stops almost right away.
+        let on_sticky = Pixels::new(248).as_subpixels();
+        assert!(runner.run_until(&right, 200, |player| player.position.x >= on_sticky));
+        assert_eq!(runner.player.delta.x, walk_speed);
+        runner.run_until(&InputSnapshot::default(), 1, |_| false);
+        let deceleration = runner.physics.walk_speed_deceleration * 3;
+        assert_eq!(runner.player.delta.x, walk_speed - deceleration);
+        assert!(runner.run_until(&InputSnapshot::default(), 3, stopped));
+    }
+}
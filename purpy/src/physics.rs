@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::constants::{
+    CLIMB_SPEED, COYOTE_TIME, FALL_ACCELERATION, JUMP_ACCELERATION, JUMP_GRACE_TIME,
+    JUMP_INITIAL_SPEED, MAX_GRAVITY, SLIDE_SPEED_DECELERATION, SWIM_FALL_ACCELERATION,
+    SWIM_MAX_GRAVITY, SWIM_STROKE_SPEED, TARGET_WALK_SPEED, WALK_SPEED_ACCELERATION,
+    WALK_SPEED_DECELERATION, WALL_JUMP_HORIZONTAL_SPEED, WALL_JUMP_VERTICAL_SPEED,
+    WALL_SLIDE_SPEED, WALL_SLIDE_TIME, WALL_STICK_TIME,
+};
+use crate::filemanager::FileManager;
+use crate::geometry::Subpixels;
+use crate::properties::PropertyMap;
+use crate::tilemap::TileMapProperties;
+
+/*
+ * How the player moves. The defaults are in constants.rs.
+ *
+ * Levels can override any of these with a map property of the same name, and can share a
+ * set of overrides by naming a profile file in the "physics_profile" map property. Speeds
+ * and accelerations are in subpixels per frame, and times are in frames.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhysicsConfig {
+    // Horizontal speed.
+    pub target_walk_speed: Subpixels,
+    pub walk_speed_acceleration: Subpixels,
+    pub walk_speed_deceleration: Subpixels,
+    pub slide_speed_deceleration: Subpixels,
+
+    // Vertical speed.
+    pub coyote_time: i32,
+    pub jump_grace_time: i32,
+    pub jump_initial_speed: Subpixels,
+    pub jump_acceleration: Subpixels,
+    pub fall_acceleration: Subpixels,
+    pub max_gravity: Subpixels,
+
+    // Wall sliding.
+    pub wall_slide_speed: Subpixels,
+    pub wall_jump_horizontal_speed: Subpixels,
+    pub wall_jump_vertical_speed: Subpixels,
+    pub wall_stick_time: i32,
+    pub wall_slide_time: i32,
+
+    // Ladders.
+    pub climb_speed: Subpixels,
+
+    // Swimming.
+    pub swim_fall_acceleration: Subpixels,
+    pub swim_max_gravity: Subpixels,
+    pub swim_stroke_speed: Subpixels,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        PhysicsConfig {
+            target_walk_speed: TARGET_WALK_SPEED,
+            walk_speed_acceleration: WALK_SPEED_ACCELERATION,
+            walk_speed_deceleration: WALK_SPEED_DECELERATION,
+            slide_speed_deceleration: SLIDE_SPEED_DECELERATION,
+            coyote_time: COYOTE_TIME,
+            jump_grace_time: JUMP_GRACE_TIME,
+            jump_initial_speed: JUMP_INITIAL_SPEED,
+            jump_acceleration: JUMP_ACCELERATION,
+            fall_acceleration: FALL_ACCELERATION,
+            max_gravity: MAX_GRAVITY,
+            wall_slide_speed: WALL_SLIDE_SPEED,
+            wall_jump_horizontal_speed: WALL_JUMP_HORIZONTAL_SPEED,
+            wall_jump_vertical_speed: WALL_JUMP_VERTICAL_SPEED,
+            wall_stick_time: WALL_STICK_TIME,
+            wall_slide_time: WALL_SLIDE_TIME,
+            climb_speed: CLIMB_SPEED,
+            swim_fall_acceleration: SWIM_FALL_ACCELERATION,
+            swim_max_gravity: SWIM_MAX_GRAVITY,
+            swim_stroke_speed: SWIM_STROKE_SPEED,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    pub const NAMES: [&'static str; 19] = [
+        "target_walk_speed",
+        "walk_speed_acceleration",
+        "walk_speed_deceleration",
+        "slide_speed_deceleration",
+        "coyote_time",
+        "jump_grace_time",
+        "jump_initial_speed",
+        "jump_acceleration",
+        "fall_acceleration",
+        "max_gravity",
+        "wall_slide_speed",
+        "wall_jump_horizontal_speed",
+        "wall_jump_vertical_speed",
+        "wall_stick_time",
+        "wall_slide_time",
+        "climb_speed",
+        "swim_fall_acceleration",
+        "swim_max_gravity",
+        "swim_stroke_speed",
+    ];
+
+    // Builds the config for a map, from its profile and then its own properties.
+    pub fn for_map(properties: &TileMapProperties, files: &FileManager) -> Result<PhysicsConfig> {
+        let mut physics = PhysicsConfig::default();
+        if let Some(profile) = &properties.physics_profile {
+            let path = Path::new(profile);
+            let text = files.read_to_string(path)?;
+            physics
+                .parse(&text)
+                .map_err(|e| anyhow!("unable to read physics profile {:?}: {}", path, e))?;
+        }
+        for (name, value) in properties.physics.iter() {
+            physics.set(name, *value)?;
+        }
+        Ok(physics)
+    }
+
+    // Finds the map properties that override physics parameters.
+    pub fn overrides(properties: &PropertyMap) -> Result<Vec<(&'static str, i32)>> {
+        let mut overrides = Vec::new();
+        // Older maps only had "gravity", which is the same as "max_gravity".
+        if let Some(gravity) = properties.get_int("gravity")? {
+            overrides.push(("max_gravity", gravity));
+        }
+        for name in PhysicsConfig::NAMES {
+            if let Some(value) = properties.get_int(name)? {
+                overrides.push((name, value));
+            }
+        }
+        Ok(overrides)
+    }
+
+    // A profile has one parameter per line, like "max_gravity 48", with # for comments.
+    fn parse(&mut self, text: &str) -> Result<()> {
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("missing value: {}", line))?;
+            let value = value.trim();
+            let value = value
+                .parse()
+                .with_context(|| format!("invalid value for {}: {}", name, value))?;
+            self.set(name, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, name: &str, value: i32) -> Result<()> {
+        match name {
+            "target_walk_speed" => self.target_walk_speed = Subpixels::new(value),
+            "walk_speed_acceleration" => self.walk_speed_acceleration = Subpixels::new(value),
+            "walk_speed_deceleration" => self.walk_speed_deceleration = Subpixels::new(value),
+            "slide_speed_deceleration" => self.slide_speed_deceleration = Subpixels::new(value),
+            "coyote_time" => self.coyote_time = value,
+            "jump_grace_time" => self.jump_grace_time = value,
+            "jump_initial_speed" => self.jump_initial_speed = Subpixels::new(value),
+            "jump_acceleration" => self.jump_acceleration = Subpixels::new(value),
+            "fall_acceleration" => self.fall_acceleration = Subpixels::new(value),
+            "max_gravity" => self.max_gravity = Subpixels::new(value),
+            "wall_slide_speed" => self.wall_slide_speed = Subpixels::new(value),
+            "wall_jump_horizontal_speed" => self.wall_jump_horizontal_speed = Subpixels::new(value),
+            "wall_jump_vertical_speed" => self.wall_jump_vertical_speed = Subpixels::new(value),
+            "wall_stick_time" => self.wall_stick_time = value,
+            "wall_slide_time" => self.wall_slide_time = value,
+            "climb_speed" => self.climb_speed = Subpixels::new(value),
+            "swim_fall_acceleration" => self.swim_fall_acceleration = Subpixels::new(value),
+            "swim_max_gravity" => self.swim_max_gravity = Subpixels::new(value),
+            "swim_stroke_speed" => self.swim_stroke_speed = Subpixels::new(value),
+            _ => bail!("unknown physics parameter: {}", name),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_name_can_be_set() {
+        for (i, name) in PhysicsConfig::NAMES.iter().enumerate() {
+            let mut physics = PhysicsConfig::default();
+            physics.set(name, 1000 + i as i32).unwrap();
+            assert_ne!(physics, PhysicsConfig::default(), "{}", name);
+        }
+        assert!(PhysicsConfig::default().set("gravity", 1).is_err());
+    }
+
+    #[test]
+    fn profiles_override_defaults() {
+        let mut physics = PhysicsConfig::default();
+        physics
+            .parse("# A floaty profile.\nmax_gravity 16\n\n  coyote_time   12  # frames\n")
+            .unwrap();
+        assert_eq!(physics.max_gravity, Subpixels::new(16));
+        assert_eq!(physics.coyote_time, 12);
+        assert_eq!(physics.jump_initial_speed, JUMP_INITIAL_SPEED);
+
+        assert!(physics.parse("max_gravity").is_err());
+        assert!(physics.parse("max_gravity fast").is_err());
+        assert!(physics.parse("moon_gravity 3").is_err());
+    }
+}
@@ -5,19 +5,22 @@ use std::mem;
 use std::path::Path;
 use std::rc::Rc;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use num_traits::Zero;
-use rand::random;
+use rand::Rng;
 
+use crate::binary::{BinaryReader, BinaryWriter, Fnv1a};
 use crate::constants::{
     BAGEL_FALL_TIME, BAGEL_GRAVITY_ACCELERATION, BAGEL_MAX_GRAVITY, BAGEL_WAIT_TIME, BUTTON_DELAY,
     BUTTON_MAX_LEVEL, SPRING_SPEED, SPRING_STALL_FRAMES, SPRING_STEPS,
 };
 use crate::geometry::{Pixels, Point, Rect, Subpixels};
 use crate::imagemanager::ImageLoader;
+use crate::key::Inventory;
 use crate::rendercontext::{RenderContext, RenderLayer};
 use crate::soundmanager::{Sound, SoundManager};
 use crate::sprite::SpriteSheet;
+use crate::switchexpr::{Commands, Condition};
 use crate::switchstate::SwitchState;
 use crate::tilemap::TileIndex;
 use crate::tilemap::{ButtonType, ConveyorDirection, MapObject, Overflow, TileMap};
@@ -29,10 +32,11 @@ pub enum PlatformType {
     Conveyor(Conveyor),
     Spring(Spring),
     Button(Button),
+    Lock(Lock),
 }
 
 pub struct Platform {
-    _id: i32,
+    id: i32,
     tilemap: Rc<TileMap>,
     tile_gid: TileIndex,
     position: Rect<Subpixels>,
@@ -43,10 +47,20 @@ pub struct Platform {
 }
 
 impl Platform {
+    // Only the parts of the platform that affect the simulation are hashed.
+    pub fn state_hash(&self, hasher: &mut Fnv1a) {
+        hasher.write_rect(self.position);
+        hasher.write_point(self.delta);
+        hasher.write_bool(self.occupied);
+        if let PlatformType::Lock(lock) = &self.subtype {
+            hasher.write_bool(lock.open);
+        }
+    }
+
     fn new(obj: &MapObject, tilemap: Rc<TileMap>, subtype: PlatformType) -> Result<Platform> {
         // TODO: This shouldn't compile.
         Ok(Platform {
-            _id: obj.id,
+            id: obj.id,
             tilemap,
             tile_gid: obj.gid.context("gid required for platforms")?,
             position: obj.position.into(),
@@ -67,6 +81,7 @@ impl Platform {
             PlatformType::Conveyor(_) => {}
             PlatformType::Spring(spring) => spring.update(self, switches, sounds),
             PlatformType::Button(button) => button.update(self, switches, sounds),
+            PlatformType::Lock(_) => {}
         }
         self.subtype = subtype;
     }
@@ -76,6 +91,7 @@ impl Platform {
             PlatformType::Bagel(bagel) => bagel.draw(self, context, layer, offset),
             PlatformType::Spring(spring) => spring.draw(self, context, layer, offset),
             PlatformType::Button(button) => button.draw(self, context, layer, offset),
+            PlatformType::Lock(lock) if lock.open => {}
             _ => {
                 let x = self.position.x + offset.x;
                 let y = self.position.y + offset.y;
@@ -103,6 +119,11 @@ impl Platform {
         if let PlatformType::Spring(spring) = &self.subtype {
             return spring.try_move_to(self, player_rect, direction, is_backwards);
         }
+        if let PlatformType::Lock(lock) = &self.subtype {
+            if lock.open {
+                return Subpixels::zero();
+            }
+        }
 
         let area = if self.solid {
             self.position
@@ -135,6 +156,130 @@ impl Platform {
     pub fn set_occupied(&mut self, occupied: bool) {
         self.occupied = occupied;
     }
+
+    /*
+     * Opens a lock block the player is up against from the left or right, if they have the
+     * right key. The rects are the player's left and right sides, like for moving.
+     */
+    pub fn try_unlock(
+        &mut self,
+        left: Rect<Subpixels>,
+        right: Rect<Subpixels>,
+        inventory: &mut Inventory,
+    ) -> bool {
+        let PlatformType::Lock(lock) = &mut self.subtype else {
+            return false;
+        };
+        if lock.open {
+            return false;
+        }
+        // Walls stop the player just short of them, so look one pixel further out.
+        let one = Pixels::new(1).as_subpixels();
+        let left = left + Point::new(one * -1, Subpixels::zero());
+        let right = right + Point::new(one, Subpixels::zero());
+        let touching = self.position.intersects(left) || self.position.intersects(right);
+        if !touching || !inventory.take(&lock.color) {
+            return false;
+        }
+        lock.open = true;
+        true
+    }
+
+    fn kind(&self) -> u8 {
+        match &self.subtype {
+            PlatformType::MovingPlatform(_) => 0,
+            PlatformType::Bagel(_) => 1,
+            PlatformType::Conveyor(_) => 2,
+            PlatformType::Spring(_) => 3,
+            PlatformType::Button(_) => 4,
+            PlatformType::Lock(_) => 5,
+        }
+    }
+
+    // Only the parts that change during the level are saved. The rest comes from the map.
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        writer.write_u8(self.kind());
+        writer.write_rect(self.position);
+        writer.write_point(self.delta);
+        writer.write_bool(self.occupied);
+        match &self.subtype {
+            PlatformType::MovingPlatform(platform) => {
+                writer.write_bool(platform.moving_forward);
+            }
+            PlatformType::Bagel(bagel) => {
+                writer.write_bool(bagel.falling);
+                writer.write_i32(bagel.remaining);
+            }
+            PlatformType::Conveyor(_) => {}
+            PlatformType::Spring(spring) => {
+                writer.write_bool(spring.up);
+                writer.write_subpixels(spring.pos);
+                writer.write_i32(spring.stall_counter);
+                writer.write_bool(spring.launch);
+            }
+            PlatformType::Button(button) => {
+                writer.write_varint(button.level as u64);
+                writer.write_bool(button.clicked);
+                writer.write_bool(button.was_occupied);
+            }
+            PlatformType::Lock(lock) => {
+                writer.write_bool(lock.open);
+            }
+        }
+    }
+
+    pub fn restore_state(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        let kind = reader.read_u8()?;
+        if kind != self.kind() {
+            bail!(
+                "platform {} has kind {}, but the saved state has kind {}",
+                self.id,
+                self.kind(),
+                kind
+            );
+        }
+        self.position = reader.read_rect()?;
+        self.delta = reader.read_point()?;
+        self.occupied = reader.read_bool()?;
+        match &mut self.subtype {
+            PlatformType::MovingPlatform(platform) => {
+                platform.moving_forward = reader.read_bool()?;
+            }
+            PlatformType::Bagel(bagel) => {
+                bagel.falling = reader.read_bool()?;
+                bagel.remaining = reader.read_i32()?;
+            }
+            PlatformType::Conveyor(_) => {}
+            PlatformType::Spring(spring) => {
+                spring.up = reader.read_bool()?;
+                spring.pos = reader.read_subpixels()?;
+                spring.stall_counter = reader.read_i32()?;
+                spring.launch = reader.read_bool()?;
+            }
+            PlatformType::Button(button) => {
+                button.level = reader.read_varint()? as u32;
+                button.clicked = reader.read_bool()?;
+                button.was_occupied = reader.read_bool()?;
+            }
+            PlatformType::Lock(lock) => {
+                lock.open = reader.read_bool()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Goes back to a saved state when the player respawns. Locks that were opened since then
+    // stay open, since the keys that opened them are used up.
+    pub fn respawn(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        let was_open = matches!(&self.subtype, PlatformType::Lock(lock) if lock.open);
+        self.restore_state(reader)?;
+        if let PlatformType::Lock(lock) = &mut self.subtype {
+            lock.open |= was_open;
+        }
+        // The player comes back somewhere else, so they aren't on it anymore.
+        self.occupied = false;
+        Ok(())
+    }
 }
 
 pub struct MovingPlatform {
@@ -144,7 +289,7 @@ pub struct MovingPlatform {
     start: Point<Subpixels>,
     end: Point<Subpixels>,
     moving_forward: bool,
-    condition: Option<String>,
+    condition: Option<Condition>,
     overflow: Overflow,
 }
 
@@ -183,7 +328,7 @@ impl MovingPlatform {
     }
 
     fn update(&mut self, base: &mut Platform, switches: &mut SwitchState, _sounds: &SoundManager) {
-        if let Some(condition) = self.condition.as_deref() {
+        if let Some(condition) = &self.condition {
             if !switches.is_condition_true(condition) {
                 self.moving_forward = false;
                 if base.position.top_left() == self.start {
@@ -311,8 +456,8 @@ impl Bagel {
         let mut x = base.position.x + offset.x;
         let mut y = base.position.y + offset.y;
         if base.occupied {
-            x += Subpixels::new((random::<u8>() % 3) as i32 - 1);
-            y += Subpixels::new((random::<u8>() % 3) as i32 - 1);
+            x += Subpixels::new((context.rng.gen::<u8>() % 3) as i32 - 1);
+            y += Subpixels::new((context.rng.gen::<u8>() % 3) as i32 - 1);
         }
         let dest = Rect {
             x,
@@ -504,6 +649,8 @@ pub struct Button {
     button_type: ButtonType,
     was_occupied: bool,
     color: String,
+    // What smart buttons do to the switches. Other buttons just toggle their color.
+    commands: Option<Commands>,
 }
 
 fn get_button_image_path(color: &str) -> String {
@@ -530,6 +677,12 @@ impl Button {
         let sprite =
             images.load_spritesheet(Path::new(&image_path), Pixels::new(8), Pixels::new(8))?;
         let button_type = obj.properties.button_type;
+        let commands = match (button_type, &obj.properties.commands) {
+            (ButtonType::Smart, Some(commands)) => Some(commands.clone()),
+            (ButtonType::Smart, None) => Some(color.parse()?),
+            (_, Some(_)) => bail!("button {} has commands, but isn't smart", obj.id),
+            (_, None) => None,
+        };
 
         let original_y = obj.position.y.as_subpixels();
         let button = Button {
@@ -540,6 +693,7 @@ impl Button {
             button_type,
             was_occupied,
             color,
+            commands,
         };
 
         let mut base = Platform::new(obj, tileset, PlatformType::Button(button))?;
@@ -575,8 +729,8 @@ impl Button {
     ) {
         let was_clicked = self.clicked;
 
-        if matches!(self.button_type, ButtonType::Smart) {
-            self.clicked = switches.is_condition_true(&self.color);
+        if let Some(commands) = &self.commands {
+            self.clicked = switches.are_commands_applied(commands);
         }
 
         if base.occupied && !self.was_occupied {
@@ -606,9 +760,9 @@ impl Button {
 
         if self.clicked != was_clicked {
             sounds.play(Sound::Click);
-            if matches!(self.button_type, ButtonType::Smart) {
+            if let Some(commands) = &self.commands {
                 if self.clicked && base.occupied {
-                    switches.apply_command(&self.color);
+                    switches.apply_commands(commands);
                 }
             } else if self.clicked || !matches!(self.button_type, ButtonType::OneShot) {
                 switches.toggle(&self.color);
@@ -616,3 +770,23 @@ impl Button {
         }
     }
 }
+
+// A solid block that goes away when the player walks into it with a key of its color.
+pub struct Lock {
+    color: String,
+    open: bool,
+}
+
+impl Lock {
+    pub fn new(obj: &MapObject, tilemap: Rc<TileMap>) -> Result<Platform> {
+        let color = obj
+            .properties
+            .color
+            .clone()
+            .context("lock must have color")?;
+        let lock = Lock { color, open: false };
+        let mut base = Platform::new(obj, tilemap, PlatformType::Lock(lock))?;
+        base.solid = true;
+        Ok(base)
+    }
+}
@@ -1,26 +1,50 @@
 use std::{cmp::Ordering, path::Path};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use num_traits::Zero;
 
 use crate::{
-    constants::{IDLE_TIME, PLAYER_FRAMES_PER_FRAME},
+    binary::{BinaryReader, BinaryWriter},
+    constants::{
+        GHOST_TINT, IDLE_TIME, PLAYER_DEFAULT_X, PLAYER_DEFAULT_Y, PLAYER_FRAMES_PER_FRAME,
+    },
     filemanager::FileManager,
     geometry::{Pixels, Point, Rect, Subpixels},
     imagemanager::ImageLoader,
     rendercontext::{RenderContext, RenderLayer},
     sprite::{AnimationStateMachine, SpriteSheet},
+    tilemap::TileMap,
     utils::Direction,
 };
 
 #[derive(Debug, Clone, Copy)]
 pub enum PlayerState {
-    Falling,
+    Falling = 0,
     Standing,
     Crouching,
     WallSliding,
     Stopped,
     Jumping,
+    Climbing,
+    Swimming,
+}
+
+impl TryFrom<u8> for PlayerState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => PlayerState::Falling,
+            1 => PlayerState::Standing,
+            2 => PlayerState::Crouching,
+            3 => PlayerState::WallSliding,
+            4 => PlayerState::Stopped,
+            5 => PlayerState::Jumping,
+            6 => PlayerState::Climbing,
+            7 => PlayerState::Swimming,
+            _ => bail!("invalid player state: {}", value),
+        })
+    }
 }
 
 pub struct Player {
@@ -66,6 +90,38 @@ impl Player {
         })
     }
 
+    // A new player, wherever the map's spawn point puts them.
+    pub fn for_map(
+        map: &TileMap,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<Player> {
+        let mut player = Player::new(files, images)?;
+        player.position.x = PLAYER_DEFAULT_X;
+        player.position.y = PLAYER_DEFAULT_Y;
+        for obj in map.objects.iter() {
+            if obj.properties.spawn {
+                player.position.x = obj.position.x.as_subpixels();
+                player.position.y = obj.position.y.as_subpixels();
+                player.delta.x = obj.properties.dx.as_subpixels();
+                player.delta.y = obj.properties.dy.as_subpixels();
+                player.state = PlayerState::Jumping;
+                player.facing_right = !obj.properties.facing_left;
+            }
+        }
+        Ok(player)
+    }
+
+    // Brings the player back to life somewhere else, falling from a standstill.
+    pub fn respawn(&mut self, position: Point<Subpixels>) {
+        self.position = position;
+        self.delta = Point::zero();
+        self.state = PlayerState::Falling;
+        self.is_idle = false;
+        self.is_dead = false;
+        self.idle_counter = IDLE_TIME;
+    }
+
     pub fn update_sprite(&mut self) -> Result<()> {
         self.facing_right = match self.delta.x.cmp(&Subpixels::zero()) {
             Ordering::Less => false,
@@ -87,6 +143,22 @@ impl Player {
                 PlayerState::Jumping => "JUMPING",
                 PlayerState::WallSliding => "WALL_SLIDING",
                 PlayerState::Crouching => "CROUCHING",
+                // Sprite sheets from before ladders and water existed don't have animations
+                // for them.
+                PlayerState::Climbing => {
+                    if self.animation_state_machine.has_state("CLIMBING") {
+                        "CLIMBING"
+                    } else {
+                        "JUMPING"
+                    }
+                }
+                PlayerState::Swimming => {
+                    if self.animation_state_machine.has_state("SWIMMING") {
+                        "SWIMMING"
+                    } else {
+                        "FALLING"
+                    }
+                }
                 PlayerState::Standing | PlayerState::Stopped => {
                     if !self.delta.x.is_zero() {
                         "RUNNING"
@@ -131,6 +203,30 @@ impl Player {
             .blit(context, layer, dest, self.frame, 0, !self.facing_right);
     }
 
+    // Draws the player translucently, as a ghost from another run.
+    pub fn draw_ghost(
+        &self,
+        context: &mut RenderContext,
+        layer: RenderLayer,
+        pos: Point<Subpixels>,
+    ) {
+        let dest = Rect {
+            x: pos.x,
+            y: pos.y,
+            w: Subpixels::from_pixels(24),
+            h: Subpixels::from_pixels(24),
+        };
+        self.sprite.blit_tinted(
+            context,
+            layer,
+            dest,
+            self.frame,
+            0,
+            !self.facing_right,
+            GHOST_TINT,
+        );
+    }
+
     fn get_raw_target_bounds(&self, direction: Option<Direction>) -> Rect<Pixels> {
         let (x, y, w, h) = match self.state {
             PlayerState::Crouching => match direction {
@@ -158,6 +254,31 @@ impl Player {
         let raw_bounds: Rect<Subpixels> = self.get_raw_target_bounds(direction).into();
         raw_bounds + self.position
     }
+
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        writer.write_point(self.position);
+        writer.write_point(self.delta);
+        writer.write_bool(self.facing_right);
+        writer.write_u8(self.state as u8);
+        writer.write_bool(self.is_idle);
+        writer.write_bool(self.is_dead);
+        writer.write_varint(self.frame as u64);
+        writer.write_i32(self.frames_to_next_frame);
+        writer.write_i32(self.idle_counter);
+    }
+
+    pub fn restore_state(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        self.position = reader.read_point()?;
+        self.delta = reader.read_point()?;
+        self.facing_right = reader.read_bool()?;
+        self.state = reader.read_u8()?.try_into()?;
+        self.is_idle = reader.read_bool()?;
+        self.is_dead = reader.read_bool()?;
+        self.frame = reader.read_varint()? as u32;
+        self.frames_to_next_frame = reader.read_i32()?;
+        self.idle_counter = reader.read_i32()?;
+        Ok(())
+    }
 }
 
 impl std::fmt::Debug for Player {
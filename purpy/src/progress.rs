@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+
+use crate::storage::{NoopStorage, Storage};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelProgress {
+    pub completed: bool,
+    // The most stars collected in one attempt at the level.
+    pub stars: i32,
+    // The map object ids of locked doors that have been opened.
+    pub unlocked_doors: BTreeSet<i32>,
+}
+
+impl LevelProgress {
+    // Adds anything new from another attempt. Returns whether anything changed.
+    fn merge(&mut self, other: &LevelProgress) -> bool {
+        let old = self.clone();
+        self.completed |= other.completed;
+        self.stars = self.stars.max(other.stars);
+        self.unlocked_doors.extend(other.unlocked_doors.iter());
+        *self != old
+    }
+}
+
+// Each line is one level, like "1 3 12,15 assets/levels/x.tmx", with "-" for no doors.
+fn parse_progress(text: &str) -> Result<BTreeMap<PathBuf, LevelProgress>> {
+    let mut levels = BTreeMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parts = line.splitn(4, ' ');
+        let (Some(completed), Some(stars), Some(doors), Some(path)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(anyhow!("invalid progress: {}", line));
+        };
+        let completed = match completed {
+            "0" => false,
+            "1" => true,
+            _ => return Err(anyhow!("invalid completed flag: {}", completed)),
+        };
+        let stars = stars
+            .parse()
+            .with_context(|| format!("invalid star count: {}", stars))?;
+        let mut unlocked_doors = BTreeSet::new();
+        if doors != "-" {
+            for door in doors.split(',') {
+                unlocked_doors.insert(
+                    door.parse()
+                        .with_context(|| format!("invalid door id: {}", door))?,
+                );
+            }
+        }
+        levels.insert(
+            PathBuf::from(path),
+            LevelProgress {
+                completed,
+                stars,
+                unlocked_doors,
+            },
+        );
+    }
+    Ok(levels)
+}
+
+fn encode_progress(levels: &BTreeMap<PathBuf, LevelProgress>) -> String {
+    let mut text = String::new();
+    for (path, level) in levels.iter() {
+        let doors = if level.unlocked_doors.is_empty() {
+            "-".to_owned()
+        } else {
+            let doors: Vec<String> = level.unlocked_doors.iter().map(i32::to_string).collect();
+            doors.join(",")
+        };
+        text.push_str(&format!(
+            "{} {} {} {}\n",
+            level.completed as u8,
+            level.stars,
+            doors,
+            path.display()
+        ));
+    }
+    text
+}
+
+// Which levels have been completed, and what was found in them, across runs of the game.
+pub struct Progress {
+    storage: Box<dyn Storage>,
+    levels: BTreeMap<PathBuf, LevelProgress>,
+}
+
+impl Progress {
+    /*
+     * Progress that can't be read starts over, rather than keeping the game from starting.
+     * The storage is kept either way, so the next level played replaces whatever was there.
+     */
+    pub fn load(storage: Box<dyn Storage>) -> Progress {
+        let levels = Progress::read(storage.as_ref()).unwrap_or_else(|e| {
+            warn!("unable to load progress, starting over: {:?}", e);
+            BTreeMap::new()
+        });
+        Progress { storage, levels }
+    }
+
+    fn read(storage: &dyn Storage) -> Result<BTreeMap<PathBuf, LevelProgress>> {
+        match storage.load()? {
+            Some(text) => parse_progress(&text),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    pub fn noop() -> Progress {
+        Progress {
+            storage: Box::new(NoopStorage {}),
+            levels: BTreeMap::new(),
+        }
+    }
+
+    pub fn level(&self, path: &Path) -> Option<&LevelProgress> {
+        self.levels.get(path)
+    }
+
+    pub fn completed_count(&self) -> usize {
+        self.levels.values().filter(|level| level.completed).count()
+    }
+
+    pub fn star_count(&self) -> i32 {
+        self.levels.values().map(|level| level.stars).sum()
+    }
+
+    // Merges an attempt at a level into the progress, and saves it if anything changed.
+    pub fn record(&mut self, path: &Path, attempt: &LevelProgress) {
+        let level = self.levels.entry(path.to_owned()).or_default();
+        if !level.merge(attempt) {
+            return;
+        }
+        if let Err(e) = self.storage.save(&encode_progress(&self.levels)) {
+            warn!("unable to save progress: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn progress_round_trip() {
+        let text = "1 3 12,15 assets/levels/a b.tmx\n\n0 0 - assets/levels/c.tmx\n";
+        let levels = parse_progress(text).unwrap();
+        assert_eq!(levels.len(), 2);
+        let level = &levels[Path::new("assets/levels/a b.tmx")];
+        assert!(level.completed);
+        assert_eq!(level.stars, 3);
+        assert_eq!(level.unlocked_doors, BTreeSet::from([12, 15]));
+        assert_eq!(encode_progress(&levels), text.replace("\n\n", "\n"));
+
+        assert!(parse_progress("1 3 assets/levels/a.tmx").is_err());
+        assert!(parse_progress("2 3 - assets/levels/a.tmx").is_err());
+        assert!(parse_progress("1 3 x - assets/levels/a.tmx").is_err());
+    }
+
+    #[test]
+    fn attempts_only_add_progress() {
+        let mut progress = Progress::noop();
+        let path = Path::new("a.tmx");
+        progress.record(
+            path,
+            &LevelProgress {
+                completed: true,
+                stars: 2,
+                unlocked_doors: BTreeSet::from([4]),
+            },
+        );
+        progress.record(
+            path,
+            &LevelProgress {
+                completed: false,
+                stars: 1,
+                unlocked_doors: BTreeSet::from([5]),
+            },
+        );
+        let level = progress.level(path).unwrap();
+        assert!(level.completed);
+        assert_eq!(level.stars, 2);
+        assert_eq!(level.unlocked_doors, BTreeSet::from([4, 5]));
+        assert_eq!(progress.completed_count(), 1);
+        assert_eq!(progress.star_count(), 2);
+    }
+
+    // Keeps the text where the test can see it.
+    struct MemoryStorage(Rc<RefCell<Option<String>>>);
+
+    impl Storage for MemoryStorage {
+        fn load(&self) -> Result<Option<String>> {
+            Ok(self.0.borrow().clone())
+        }
+
+        fn save(&mut self, data: &str) -> Result<()> {
+            *self.0.borrow_mut() = Some(data.to_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn garbage_progress_starts_over() {
+        let text = Rc::new(RefCell::new(Some("not progress\n".to_owned())));
+        let mut progress = Progress::load(Box::new(MemoryStorage(text.clone())));
+        assert_eq!(progress.completed_count(), 0);
+
+        let path = Path::new("a.tmx");
+        progress.record(
+            path,
+            &LevelProgress {
+                completed: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(text.borrow().as_deref(), Some("1 0 - a.tmx\n"));
+    }
+}
@@ -1,6 +1,8 @@
 use anyhow::Result;
 use log::warn;
 use num_traits::Zero;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
 use crate::constants::MAX_LIGHTS;
 use crate::geometry::{Pixels, Point, Rect, Subpixels};
@@ -13,6 +15,8 @@ pub enum SpriteBatchEntry {
         source: Rect<Pixels>,
         destination: Rect<Pixels>,
         reversed: bool,
+        // Each channel of the sprite, including alpha, is multiplied by this.
+        tint: Color,
     },
     FillRect {
         destination: Rect<Pixels>,
@@ -45,6 +49,7 @@ impl SpriteBatch {
         dst: Rect<Subpixels>,
         src: Rect<Pixels>,
         reversed: bool,
+        tint: Color,
     ) {
         let dst = dst.as_pixels();
         self.entries.push(SpriteBatchEntry::Sprite {
@@ -52,6 +57,7 @@ impl SpriteBatch {
             source: src,
             destination: dst,
             reversed,
+            tint,
         });
     }
 
@@ -75,6 +81,13 @@ pub enum RenderLayer {
     Hud,
 }
 
+// Every frame gets its own generator, so that a frame's randomness only depends on the seed
+// and the frame number, and never on how many numbers earlier frames used. Unlike StdRng,
+// ChaCha8 always generates the same numbers, so recordings play back the same everywhere.
+fn frame_rng(seed: u64, frame: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed ^ frame.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
 pub struct RenderContext {
     pub player_batch: SpriteBatch,
     pub hud_batch: SpriteBatch,
@@ -83,14 +96,16 @@ pub struct RenderContext {
     pub frame: u64,
     pub lights: Vec<Light>,
     pub is_dark: bool,
+    pub rng: ChaCha8Rng,
 }
 
 impl RenderContext {
-    pub fn new(width: u32, height: u32, frame: u64) -> Result<RenderContext> {
+    pub fn new(width: u32, height: u32, frame: u64, seed: u64) -> Result<RenderContext> {
         let player_batch = SpriteBatch::new();
         let hud_batch = SpriteBatch::new();
         let lights = Vec::new();
         let is_dark = false;
+        let rng = frame_rng(seed, frame);
         Ok(RenderContext {
             player_batch,
             hud_batch,
@@ -99,9 +114,19 @@ impl RenderContext {
             frame,
             lights,
             is_dark,
+            rng,
         })
     }
 
+    // Gets a context that's already been used ready for another frame, as if it were new.
+    pub fn start_frame(&mut self, frame: u64, seed: u64) {
+        self.clear();
+        self.lights.clear();
+        self.is_dark = false;
+        self.frame = frame;
+        self.rng = frame_rng(seed, frame);
+    }
+
     pub fn logical_area_in_subpixels(&self) -> Rect<Subpixels> {
         // TODO: This should be cacheable.
         Rect {
@@ -120,10 +145,7 @@ impl RenderContext {
         dst: Rect<Subpixels>,
         src: Rect<Pixels>,
     ) {
-        match layer {
-            RenderLayer::Player => self.player_batch.draw(sprite, dst, src, false),
-            RenderLayer::Hud => self.hud_batch.draw(sprite, dst, src, false),
-        }
+        self.draw_tinted(sprite, layer, dst, src, false, Color::WHITE);
     }
 
     pub fn draw_reversed(
@@ -132,10 +154,22 @@ impl RenderContext {
         layer: RenderLayer,
         dst: Rect<Subpixels>,
         src: Rect<Pixels>,
+    ) {
+        self.draw_tinted(sprite, layer, dst, src, true, Color::WHITE);
+    }
+
+    pub fn draw_tinted(
+        &mut self,
+        sprite: Sprite,
+        layer: RenderLayer,
+        dst: Rect<Subpixels>,
+        src: Rect<Pixels>,
+        reversed: bool,
+        tint: Color,
     ) {
         match layer {
-            RenderLayer::Player => self.player_batch.draw(sprite, dst, src, true),
-            RenderLayer::Hud => self.hud_batch.draw(sprite, dst, src, true),
+            RenderLayer::Player => self.player_batch.draw(sprite, dst, src, reversed, tint),
+            RenderLayer::Hud => self.hud_batch.draw(sprite, dst, src, reversed, tint),
         }
     }
 
@@ -171,3 +205,17 @@ impl RenderContext {
         self.lights.push(Light { position, radius });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::RngCore;
+
+    #[test]
+    fn frames_always_get_the_same_numbers() {
+        // If this changes, every recording with randomness in it will desync.
+        let mut rng = frame_rng(1234, 56);
+        assert_eq!([rng.next_u32(), rng.next_u32()], [1703336649, 2377848688]);
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{bail, Context, Result};
+
+use crate::binary::{checksum, BinaryReader, BinaryWriter};
+use crate::constants::REWIND_BUDGET;
+
+const REPLAY_MAGIC: &[u8; 8] = b"PURPYRPL";
+
+// Bump this whenever the layout of the replay file itself changes, and keep decode()
+// able to read the older versions.
+pub const REPLAY_FORMAT_VERSION: u16 = 1;
+
+// Files from before the binary format existed were just lines of "frame,snapshot".
+pub const LEGACY_FORMAT_VERSION: u16 = 0;
+
+// Frames are stored as the number of frames since the previous one.
+fn next_frame(frame: u64, delta: u64) -> Result<u64> {
+    frame
+        .checked_add(delta)
+        .context("replay is corrupt: frame number is too large")
+}
+
+// Puts the header in front of an already encoded body, with a checksum covering both.
+fn encode_with_body(header: &ReplayHeader, entry_count: usize, body: &[u8]) -> Vec<u8> {
+    let mut writer = BinaryWriter::new();
+    writer.write_bytes(REPLAY_MAGIC);
+    writer.write_u16(REPLAY_FORMAT_VERSION);
+    writer.write_string(&header.game_version);
+    writer.write_u16(header.input_layout);
+    writer.write_string(&header.start_scene);
+    writer.write_u64(header.seed);
+    writer.write_varint(header.frame_count);
+    writer.write_varint(header.rewind_budget);
+    writer.write_varint(entry_count as u64);
+    let checksum = checksum(&[writer.bytes(), body].concat());
+    writer.write_u64(checksum);
+    writer.write_bytes(body);
+    writer.into_bytes()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayHeader {
+    pub format_version: u16,
+    pub game_version: String,
+    // The version of the InputSnapshot bit layout that the snapshots were encoded with.
+    pub input_layout: u16,
+    // The scene the game started in, in the same "menu:" or "level:" form as menu actions.
+    pub start_scene: String,
+    pub seed: u64,
+    pub frame_count: u64,
+    // How many bytes levels could use for rewinding, which changes what rewinding does.
+    pub rewind_budget: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayEntry {
+    pub frame: u64,
+    pub snapshot: u64,
+}
+
+// A hash of the game state after the given frame was simulated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateHash {
+    pub frame: u64,
+    pub hash: u64,
+}
+
+/*
+ * A recording of every input to the game, with enough metadata to play it back exactly.
+ *
+ * Only the frames where the input changed are stored, along with periodic hashes of the
+ * game state that playback can check against.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub entries: Vec<ReplayEntry>,
+    pub state_hashes: Vec<StateHash>,
+}
+
+impl Replay {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = BinaryWriter::new();
+        let mut previous_frame = 0;
+        for entry in self.entries.iter() {
+            let delta = entry.frame.checked_sub(previous_frame);
+            body.write_varint(delta.expect("replay entries should be in frame order"));
+            body.write_varint(entry.snapshot);
+            previous_frame = entry.frame;
+        }
+        body.write_varint(self.state_hashes.len() as u64);
+        let mut previous_frame = 0;
+        for state_hash in self.state_hashes.iter() {
+            let delta = state_hash.frame.checked_sub(previous_frame);
+            body.write_varint(delta.expect("state hashes should be in frame order"));
+            body.write_u64(state_hash.hash);
+            previous_frame = state_hash.frame;
+        }
+        encode_with_body(&self.header, self.entries.len(), &body.into_bytes())
+    }
+
+    /*
+     * Reads a replay in any format this build understands.
+     *
+     * Snapshots are returned exactly as they were stored. It's up to the caller to
+     * migrate them from the header's input_layout to the current one.
+     */
+    pub fn decode(bytes: &[u8], default_start_scene: &str) -> Result<Replay> {
+        if !bytes.starts_with(REPLAY_MAGIC) {
+            let text = std::str::from_utf8(bytes).context("replay has an unknown format")?;
+            return Replay::decode_legacy(text, default_start_scene);
+        }
+
+        let mut reader = BinaryReader::new(bytes);
+        reader.read_bytes(REPLAY_MAGIC.len())?;
+        let format_version = reader.read_u16()?;
+        if format_version > REPLAY_FORMAT_VERSION {
+            bail!(
+                "replay format version {} is newer than this build supports ({})",
+                format_version,
+                REPLAY_FORMAT_VERSION
+            );
+        }
+
+        let game_version = reader.read_string()?;
+        let input_layout = reader.read_u16()?;
+        let start_scene = reader.read_string()?;
+        let seed = reader.read_u64()?;
+        let frame_count = reader.read_varint()?;
+        let rewind_budget = reader.read_varint()?;
+        let entry_count = reader.read_varint()?;
+        let header = &bytes[..bytes.len() - reader.remaining().len()];
+        let expected_checksum = reader.read_u64()?;
+
+        let actual_checksum = checksum(&[header, reader.remaining()].concat());
+        if actual_checksum != expected_checksum {
+            bail!(
+                "replay is corrupt: checksum is {:016x}, but should be {:016x}",
+                actual_checksum,
+                expected_checksum
+            );
+        }
+
+        let mut entries = Vec::new();
+        let mut frame = 0;
+        for _ in 0..entry_count {
+            frame = next_frame(frame, reader.read_varint()?)?;
+            let snapshot = reader.read_varint()?;
+            entries.push(ReplayEntry { frame, snapshot });
+        }
+
+        let mut state_hashes = Vec::new();
+        let count = reader.read_varint()?;
+        let mut frame = 0;
+        for _ in 0..count {
+            frame = next_frame(frame, reader.read_varint()?)?;
+            let hash = reader.read_u64()?;
+            state_hashes.push(StateHash { frame, hash });
+        }
+
+        Ok(Replay {
+            header: ReplayHeader {
+                format_version,
+                game_version,
+                input_layout,
+                start_scene,
+                seed,
+                frame_count,
+                rewind_budget,
+            },
+            entries,
+            state_hashes,
+        })
+    }
+
+    fn decode_legacy(text: &str, default_start_scene: &str) -> Result<Replay> {
+        let mut entries = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let comma = line.find(',').context("missing comma")?;
+            let (frame, snapshot) = line.split_at(comma);
+            let frame = frame.parse()?;
+            let snapshot = snapshot[1..].parse()?;
+            entries.push(ReplayEntry { frame, snapshot });
+        }
+
+        let frame_count = entries.last().map(|entry| entry.frame + 1).unwrap_or(0);
+        Ok(Replay {
+            header: ReplayHeader {
+                format_version: LEGACY_FORMAT_VERSION,
+                game_version: "unknown".to_owned(),
+                // Text files were only ever written with the original layout.
+                input_layout: 1,
+                start_scene: default_start_scene.to_owned(),
+                // Text files never stored a seed.
+                seed: 0,
+                frame_count,
+                rewind_budget: REWIND_BUDGET as u64,
+            },
+            entries,
+            state_hashes: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_replay() -> Replay {
+        Replay {
+            header: ReplayHeader {
+                format_version: REPLAY_FORMAT_VERSION,
+                game_version: "1.2.3".to_owned(),
+                input_layout: 1,
+                start_scene: "level:assets/levels/test.tmx".to_owned(),
+                seed: 0xDEAD_BEEF_0000_0001,
+                frame_count: 500,
+                rewind_budget: 64 * 1024,
+            },
+            entries: vec![
+                ReplayEntry {
+                    frame: 0,
+                    snapshot: 0,
+                },
+                ReplayEntry {
+                    frame: 10,
+                    snapshot: 1 << 3,
+                },
+                ReplayEntry {
+                    frame: 400,
+                    snapshot: (150 << 48) | (200 << 32) | 1,
+                },
+            ],
+            state_hashes: vec![
+                StateHash {
+                    frame: 0,
+                    hash: 0x0123_4567_89AB_CDEF,
+                },
+                StateHash {
+                    frame: 60,
+                    hash: u64::MAX,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let replay = test_replay();
+        let decoded = Replay::decode(&replay.encode(), "menu:start.tmx").unwrap();
+        assert_eq!(decoded, replay);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut bytes = test_replay().encode();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        assert!(Replay::decode(&bytes, "menu:start.tmx").is_err());
+    }
+
+    #[test]
+    fn detects_header_corruption() {
+        let replay = test_replay();
+        let mut bytes = replay.encode();
+        let seed = replay.header.seed.to_le_bytes();
+        let seed_start = bytes.windows(8).position(|w| w == seed).unwrap();
+        bytes[seed_start] ^= 0x01;
+        assert!(Replay::decode(&bytes, "menu:start.tmx").is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_frames() {
+        let mut body = BinaryWriter::new();
+        body.write_varint(u64::MAX);
+        body.write_varint(0);
+        body.write_varint(1);
+        body.write_varint(0);
+        body.write_varint(0);
+        let bytes = encode_with_body(&test_replay().header, 2, &body.into_bytes());
+        assert!(Replay::decode(&bytes, "menu:start.tmx").is_err());
+    }
+
+    #[test]
+    fn reads_legacy_text() {
+        let text = "0,0\n10,8\n";
+        let replay = Replay::decode(text.as_bytes(), "menu:start.tmx").unwrap();
+        assert_eq!(replay.header.format_version, LEGACY_FORMAT_VERSION);
+        assert_eq!(replay.header.seed, 0);
+        assert_eq!(replay.header.frame_count, 11);
+        assert_eq!(replay.header.rewind_budget, REWIND_BUDGET as u64);
+        assert_eq!(replay.header.start_scene, "menu:start.tmx");
+        assert_eq!(
+            replay.entries,
+            vec![
+                ReplayEntry {
+                    frame: 0,
+                    snapshot: 0
+                },
+                ReplayEntry {
+                    frame: 10,
+                    snapshot: 8
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+
+use crate::binary::{BinaryReader, BinaryWriter};
+
+/*
+ * A ring buffer of recent level states, for rewinding.
+ *
+ * Most of a level doesn't change from one frame to the next, so only the newest state is
+ * kept in full. Each older state is kept as a patch with just the bytes that differ from
+ * the state after it. Instead of a fixed number of frames, it holds as many states as fit
+ * in a budget of bytes, dropping the oldest ones to make room for new ones.
+ */
+pub struct RewindBuffer {
+    latest: Option<Vec<u8>>,
+    // Oldest first. Each one turns the state after it back into the one before.
+    patches: VecDeque<Vec<u8>>,
+    size: usize,
+    budget: usize,
+}
+
+/*
+ * Returns the changes that turn from into to.
+ *
+ * The patch is the length of to, followed by each run of bytes that differ, as how many
+ * bytes to skip since the last run, how many bytes are in this one, and the bytes.
+ */
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let same = |i: usize| from.get(i) == Some(&to[i]);
+    let mut writer = BinaryWriter::new();
+    writer.write_varint(to.len() as u64);
+    let mut end = 0;
+    let mut i = 0;
+    while i < to.len() {
+        if same(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < to.len() && !same(i) {
+            i += 1;
+        }
+        writer.write_varint((start - end) as u64);
+        writer.write_varint((i - start) as u64);
+        writer.write_bytes(&to[start..i]);
+        end = i;
+    }
+    writer.into_bytes()
+}
+
+fn patch(from: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut reader = BinaryReader::new(patch);
+    let len = reader.read_varint().expect("patches should have a length") as usize;
+    let mut result = from[..from.len().min(len)].to_vec();
+    result.resize(len, 0);
+    let mut position = 0;
+    while !reader.remaining().is_empty() {
+        let skip = reader.read_varint().expect("patches should be valid");
+        let count = reader.read_varint().expect("patches should be valid") as usize;
+        let bytes = reader.read_bytes(count).expect("patches should be valid");
+        position += skip as usize;
+        result[position..position + count].copy_from_slice(bytes);
+        position += count;
+    }
+    result
+}
+
+impl RewindBuffer {
+    pub fn new(budget: usize) -> RewindBuffer {
+        RewindBuffer {
+            latest: None,
+            patches: VecDeque::new(),
+            size: 0,
+            budget,
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.trim();
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let patch = diff(&state, &previous);
+            self.size -= previous.len();
+            self.size += patch.len();
+            self.patches.push_back(patch);
+        }
+        self.size += state.len();
+        self.latest = Some(state);
+        self.trim();
+    }
+
+    // Removes and returns the most recent state.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let state = self.latest.take()?;
+        self.size -= state.len();
+        if let Some(patch) = self.patches.pop_back() {
+            let previous = self::patch(&state, &patch);
+            self.size -= patch.len();
+            self.size += previous.len();
+            self.latest = Some(previous);
+        }
+        Some(state)
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.patches.clear();
+        self.size = 0;
+    }
+
+    fn trim(&mut self) {
+        while self.size > self.budget {
+            if let Some(patch) = self.patches.pop_front() {
+                self.size -= patch.len();
+            } else if let Some(state) = self.latest.take() {
+                self.size -= state.len();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::{FRAME_RATE, REWIND_BUDGET};
+
+    #[test]
+    fn pops_newest_first() {
+        let mut buffer = RewindBuffer::new(100);
+        buffer.push(vec![1]);
+        buffer.push(vec![2]);
+        assert_eq!(buffer.pop(), Some(vec![2]));
+        assert_eq!(buffer.pop(), Some(vec![1]));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn drops_oldest_over_budget() {
+        // Each patch here is 5 bytes: the length, the skip, the count, and the 2 bytes.
+        let mut buffer = RewindBuffer::new(12);
+        buffer.push(vec![1, 1]);
+        buffer.push(vec![2, 2]);
+        buffer.push(vec![3, 3]);
+        buffer.push(vec![4, 4]);
+        assert_eq!(buffer.pop(), Some(vec![4, 4]));
+        assert_eq!(buffer.pop(), Some(vec![3, 3]));
+        assert_eq!(buffer.pop(), Some(vec![2, 2]));
+        assert_eq!(buffer.pop(), None);
+
+        // A state bigger than the whole budget doesn't fit at all.
+        buffer.push(vec![5; 13]);
+        assert_eq!(buffer.pop(), None);
+
+        buffer.push(vec![6, 6]);
+        buffer.push(vec![7, 7]);
+        buffer.set_budget(2);
+        assert_eq!(buffer.pop(), Some(vec![7, 7]));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn states_can_change_size() {
+        let mut buffer = RewindBuffer::new(100);
+        let states = [
+            vec![1, 2, 3],
+            vec![1, 2, 3, 4, 5],
+            vec![1],
+            vec![],
+            vec![9, 2, 3, 9],
+        ];
+        for state in states.iter() {
+            buffer.push(state.clone());
+        }
+        for state in states.iter().rev() {
+            assert_eq!(buffer.pop().as_ref(), Some(state));
+        }
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn default_budget_covers_a_minute() {
+        // A big level, where the player and a few platforms and enemies move every frame.
+        let mut state = vec![0u8; 4096];
+        let mut buffer = RewindBuffer::new(REWIND_BUDGET);
+        let frames = FRAME_RATE as usize * 60;
+        for frame in 0..frames {
+            for thing in 0..8 {
+                let offset = thing * 500 + frame % 4;
+                state[offset] = state[offset].wrapping_add(1);
+                state[offset + 8] = frame as u8;
+            }
+            buffer.push(state.clone());
+        }
+        let mut count = 0;
+        while buffer.pop().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, frames);
+    }
+}
@@ -1,10 +1,18 @@
 use std::path::PathBuf;
 
+use anyhow::{bail, Result};
+
+use crate::bindings::Bindings;
+use crate::filemanager::FileManager;
 use crate::font::Font;
+use crate::ghost::Ghost;
+use crate::imagemanager::ImageLoader;
 use crate::inputmanager::InputSnapshot;
+use crate::progress::{LevelProgress, Progress};
 use crate::rendercontext::RenderContext;
 use crate::soundmanager::SoundManager;
 
+#[derive(Debug, Clone)]
 pub enum SceneResult {
     Continue,
     Pop,
@@ -13,8 +21,10 @@ pub enum SceneResult {
     PushLevelSelect { path: PathBuf },
     PushLevel { path: PathBuf },
     ReloadLevel { path: PathBuf },
+    RespawnLevel { path: PathBuf },
     PushKillScreen { path: PathBuf },
     PushPause { path: PathBuf },
+    PushControls { path: Option<PathBuf> },
     SwitchToLevel { path: PathBuf },
 }
 
@@ -27,4 +37,56 @@ pub trait Scene {
     ) -> SceneResult;
 
     fn draw(&self, context: &mut RenderContext, font: &Font, previous: Option<&dyn Scene>);
+
+    // Whether this is a level being played, as opposed to a menu or the pause screen.
+    fn is_level(&self) -> bool {
+        false
+    }
+
+    // A hash of everything in the scene that affects the simulation, used to detect when
+    // replay playback diverges from the original run. Scenes without gameplay return None.
+    fn state_hash(&self) -> Option<u64> {
+        None
+    }
+
+    // Serializes everything in the scene that changes while playing, so that it can be
+    // restored later with restore_state. Scenes without gameplay return None.
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn restore_state(&mut self, _state: &[u8]) -> Result<()> {
+        bail!("this scene doesn't support saved states")
+    }
+
+    // Sets how many bytes of recent states the scene may keep for rewinding.
+    fn set_rewind_budget(&mut self, _budget: usize) {}
+
+    // Starts a ghost from another run alongside the scene, if it's the level the ghost is for.
+    fn start_ghost(
+        &mut self,
+        _ghost: &Ghost,
+        _files: &FileManager,
+        _images: &mut dyn ImageLoader,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // For levels, the map path and what's been done in it so far.
+    fn progress(&self) -> Option<(PathBuf, LevelProgress)> {
+        None
+    }
+
+    // Gives the scene the latest saved progress, for scenes that show it.
+    fn set_progress(&mut self, _progress: &Progress) {}
+
+    // Brings the player back to life at the last checkpoint, if the scene has one.
+    fn respawn(&mut self) -> bool {
+        false
+    }
+
+    // Returns bindings the player changed in the scene, to be saved and used from now on.
+    fn take_new_bindings(&mut self) -> Option<Bindings> {
+        None
+    }
 }
@@ -32,7 +32,7 @@ impl<'a> SdlRenderer<'a> {
         }
     }
 
-    fn render_batch(&self, canvas: &mut Canvas<Window>, batch: &SpriteBatch) {
+    fn render_batch(&mut self, canvas: &mut Canvas<Window>, batch: &SpriteBatch) {
         canvas.set_draw_color(batch.clear_color);
         canvas
             .fill_rect(None)
@@ -45,11 +45,16 @@ impl<'a> SdlRenderer<'a> {
                     source,
                     destination,
                     reversed,
+                    tint,
                 } => {
                     let sprite_internal = self
                         .sprites
-                        .get(sprite.id)
+                        .get_mut(sprite.id)
                         .unwrap_or_else(|| panic!("invalid sprite: {:?}", sprite));
+                    sprite_internal
+                        .texture
+                        .set_color_mod(tint.r, tint.g, tint.b);
+                    sprite_internal.texture.set_alpha_mod(tint.a);
 
                     let source = Rect {
                         x: sprite.area.x + source.x,
@@ -82,7 +87,7 @@ impl<'a> SdlRenderer<'a> {
         }
     }
 
-    pub fn render(&self, canvas: &mut Canvas<Window>, context: &RenderContext) -> Result<()> {
+    pub fn render(&mut self, canvas: &mut Canvas<Window>, context: &RenderContext) -> Result<()> {
         let pixel_format = canvas.default_pixel_format();
 
         let texture_creator = canvas.texture_creator();
@@ -0,0 +1,324 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::{Rgba, RgbaImage};
+use log::info;
+use num_traits::Zero;
+
+use crate::filemanager::FileManager;
+use crate::geometry::{Pixels, Rect};
+use crate::rendercontext::{RenderContext, SpriteBatch, SpriteBatchEntry};
+use crate::renderer::Renderer;
+use crate::sprite::Sprite;
+use crate::utils::Color;
+
+// How dark the area outside of any spotlight gets. This matches the WGPU shader.
+const DARKNESS: f32 = 0.85;
+
+/*
+ * A renderer that rasterizes each frame into an in-memory image on the CPU.
+ *
+ * It's much slower than the other renderers, but it doesn't need a window or a GPU,
+ * so it can be used for tests and tools that want to look at the rendered frames.
+ */
+pub struct SoftwareRenderer {
+    texture_atlas: RgbaImage,
+    player_framebuffer: RgbaImage,
+    hud_framebuffer: RgbaImage,
+    frame: RgbaImage,
+}
+
+impl SoftwareRenderer {
+    pub fn new(texture_atlas_path: &Path, file_manager: &FileManager) -> Result<Self> {
+        info!("Reading texture atlas from {:?}", texture_atlas_path);
+        let bytes = file_manager.read(texture_atlas_path)?;
+        let texture_atlas = image::load_from_memory(&bytes)
+            .map_err(|e| anyhow!("unable to load image from {:?}: {}", texture_atlas_path, e))?
+            .to_rgba8();
+
+        Ok(SoftwareRenderer {
+            texture_atlas,
+            player_framebuffer: RgbaImage::new(0, 0),
+            hud_framebuffer: RgbaImage::new(0, 0),
+            frame: RgbaImage::new(0, 0),
+        })
+    }
+
+    // The most recently rendered frame.
+    pub fn frame(&self) -> &RgbaImage {
+        &self.frame
+    }
+
+    pub fn render(&mut self, context: &RenderContext) -> Result<()> {
+        let (width, height) = (context.width, context.height);
+        if self.frame.dimensions() != (width, height) {
+            self.player_framebuffer = RgbaImage::new(width, height);
+            self.hud_framebuffer = RgbaImage::new(width, height);
+            self.frame = RgbaImage::new(width, height);
+        }
+
+        render_batch(
+            &self.texture_atlas,
+            &mut self.player_framebuffer,
+            &context.player_batch,
+        );
+        render_batch(
+            &self.texture_atlas,
+            &mut self.hud_framebuffer,
+            &context.hud_batch,
+        );
+
+        let one_pixel = Pixels::new(1);
+        let lights: Vec<(f32, f32, f32)> = if context.is_dark {
+            context
+                .lights
+                .iter()
+                .map(|light| {
+                    let position = light.position.as_pixels();
+                    (
+                        (position.x / one_pixel) as f32,
+                        (position.y / one_pixel) as f32,
+                        (light.radius.as_pixels() / one_pixel) as f32,
+                    )
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        for (x, y, output) in self.frame.enumerate_pixels_mut() {
+            let player = self.player_framebuffer.get_pixel(x, y);
+            let hud = self.hud_framebuffer.get_pixel(x, y);
+
+            let spot = spotlight(&lights, x as f32 + 0.5, y as f32 + 0.5);
+            let hud_alpha = hud[3] as f32 / 255.0;
+
+            let mut color = [0, 0, 0, 255];
+            for i in 0..3 {
+                let player_channel = player[i] as f32 * (1.0 - spot);
+                let channel = hud[i] as f32 * hud_alpha + player_channel * (1.0 - hud_alpha);
+                color[i] = channel.round().clamp(0.0, 255.0) as u8;
+            }
+            *output = Rgba(color);
+        }
+
+        Ok(())
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn load_sprite(&mut self, _path: &Path) -> Result<Sprite> {
+        // Like the WGPU renderer, everything comes from the texture atlas.
+        Ok(Sprite {
+            id: 0,
+            area: Rect {
+                x: Pixels::zero(),
+                y: Pixels::zero(),
+                w: Pixels::new(self.texture_atlas.width() as i32),
+                h: Pixels::new(self.texture_atlas.height() as i32),
+            },
+        })
+    }
+}
+
+// Returns how much the darkness should cover the given point, from 0 to 1.
+fn spotlight(lights: &[(f32, f32, f32)], x: f32, y: f32) -> f32 {
+    if lights.is_empty() {
+        return 0.0;
+    }
+    let mut alpha: f32 = 1.0;
+    for &(light_x, light_y, radius) in lights {
+        let d = ((light_x - x).powi(2) + (light_y - y).powi(2)).sqrt();
+        let a = smoothstep(d / radius) * DARKNESS;
+        alpha = alpha.min(a);
+    }
+    alpha
+}
+
+fn smoothstep(x: f32) -> f32 {
+    let t = x.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn blend(dst: &mut Rgba<u8>, src: [u8; 4]) {
+    let src_alpha = src[3] as u32;
+    if src_alpha == 0 {
+        return;
+    }
+    if src_alpha == 255 {
+        *dst = Rgba(src);
+        return;
+    }
+    let dst_alpha = 255 - src_alpha;
+    for i in 0..3 {
+        dst[i] = ((src[i] as u32 * src_alpha + dst[i] as u32 * dst_alpha) / 255) as u8;
+    }
+    dst[3] = (src_alpha + dst[3] as u32 * dst_alpha / 255) as u8;
+}
+
+// Returns the pixel range of the destination that's actually on the framebuffer.
+fn clip(destination: Rect<Pixels>, framebuffer: &RgbaImage) -> (i32, i32, i32, i32) {
+    let one_pixel = Pixels::new(1);
+    let left = (destination.x / one_pixel).max(0);
+    let top = (destination.y / one_pixel).max(0);
+    let right = (destination.right() / one_pixel).min(framebuffer.width() as i32);
+    let bottom = (destination.bottom() / one_pixel).min(framebuffer.height() as i32);
+    (left, top, right, bottom)
+}
+
+fn render_batch(texture_atlas: &RgbaImage, framebuffer: &mut RgbaImage, batch: &SpriteBatch) {
+    let Color { r, g, b, a } = batch.clear_color;
+    for pixel in framebuffer.pixels_mut() {
+        *pixel = Rgba([r, g, b, a]);
+    }
+
+    let one_pixel = Pixels::new(1);
+    for entry in batch.entries.iter() {
+        match entry {
+            SpriteBatchEntry::Sprite {
+                sprite,
+                source,
+                destination,
+                reversed,
+                tint,
+            } => {
+                let tint = [tint.r, tint.g, tint.b, tint.a];
+                let sx = (sprite.area.x + source.x) / one_pixel;
+                let sy = (sprite.area.y + source.y) / one_pixel;
+                let sw = source.w / one_pixel;
+                let sh = source.h / one_pixel;
+                let dx = destination.x / one_pixel;
+                let dy = destination.y / one_pixel;
+                let dw = destination.w / one_pixel;
+                let dh = destination.h / one_pixel;
+                if sw <= 0 || sh <= 0 || dw <= 0 || dh <= 0 {
+                    continue;
+                }
+
+                let (left, top, right, bottom) = clip(*destination, framebuffer);
+                for y in top..bottom {
+                    let v = sy + (y - dy) * sh / dh;
+                    for x in left..right {
+                        let mut u = (x - dx) * sw / dw;
+                        if *reversed {
+                            u = sw - 1 - u;
+                        }
+                        let u = sx + u;
+                        if u < 0
+                            || v < 0
+                            || u >= texture_atlas.width() as i32
+                            || v >= texture_atlas.height() as i32
+                        {
+                            continue;
+                        }
+                        let mut src = texture_atlas.get_pixel(u as u32, v as u32).0;
+                        for i in 0..4 {
+                            src[i] = (src[i] as u32 * tint[i] as u32 / 255) as u8;
+                        }
+                        blend(framebuffer.get_pixel_mut(x as u32, y as u32), src);
+                    }
+                }
+            }
+            SpriteBatchEntry::FillRect { destination, color } => {
+                let (left, top, right, bottom) = clip(*destination, framebuffer);
+                let src = [color.r, color.g, color.b, color.a];
+                for y in top..bottom {
+                    for x in left..right {
+                        blend(framebuffer.get_pixel_mut(x as u32, y as u32), src);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::geometry::Point;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rect<Pixels> {
+        Rect {
+            x: Pixels::new(x),
+            y: Pixels::new(y),
+            w: Pixels::new(w),
+            h: Pixels::new(h),
+        }
+    }
+
+    #[test]
+    fn draws_rects_and_tinted_sprites() {
+        let mut texture_atlas = RgbaImage::new(2, 1);
+        texture_atlas.put_pixel(0, 0, Rgba([200, 100, 50, 255]));
+        texture_atlas.put_pixel(1, 0, Rgba([10, 20, 30, 255]));
+        let mut framebuffer = RgbaImage::new(4, 4);
+
+        let mut batch = SpriteBatch::new();
+        batch.entries.push(SpriteBatchEntry::FillRect {
+            destination: rect(0, 0, 2, 2),
+            color: Color {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+        });
+        batch.entries.push(SpriteBatchEntry::Sprite {
+            sprite: Sprite {
+                id: 0,
+                area: rect(0, 0, 2, 1),
+            },
+            source: rect(0, 0, 2, 1),
+            destination: rect(2, 2, 2, 1),
+            reversed: false,
+            tint: Color {
+                r: 128,
+                g: 255,
+                b: 255,
+                a: 255,
+            },
+        });
+        render_batch(&texture_atlas, &mut framebuffer, &batch);
+
+        assert_eq!(framebuffer.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(framebuffer.get_pixel(2, 2), &Rgba([100, 100, 50, 255]));
+        assert_eq!(framebuffer.get_pixel(3, 2), &Rgba([5, 20, 30, 255]));
+        // Anything not drawn over is left as the clear color.
+        assert_eq!(framebuffer.get_pixel(3, 3), &Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn darkness_falls_off_around_lights() {
+        assert_eq!(spotlight(&[], 5.0, 5.0), 0.0);
+        let lights = [(0.0, 0.0, 8.0), (20.0, 0.0, 4.0)];
+        assert_eq!(spotlight(&lights, 0.0, 0.0), 0.0);
+        assert_eq!(spotlight(&lights, 4.0, 0.0), 0.5 * DARKNESS);
+        assert_eq!(spotlight(&lights, 8.0, 0.0), DARKNESS);
+        assert_eq!(spotlight(&lights, 19.0, 0.0), smoothstep(0.25) * DARKNESS);
+
+        let mut renderer = SoftwareRenderer {
+            texture_atlas: RgbaImage::new(1, 1),
+            player_framebuffer: RgbaImage::new(0, 0),
+            hud_framebuffer: RgbaImage::new(0, 0),
+            frame: RgbaImage::new(0, 0),
+        };
+        let mut context = RenderContext::new(16, 1, 0, 0).unwrap();
+        context.player_batch.clear_color = Color {
+            r: 200,
+            g: 200,
+            b: 200,
+            a: 255,
+        };
+        context.is_dark = true;
+        let position = Point::new(Pixels::zero(), Pixels::zero()).into();
+        context.add_light(position, Pixels::new(8).as_subpixels());
+        renderer.render(&context).unwrap();
+
+        let brightness = |x| renderer.frame().get_pixel(x, 0)[0];
+        assert!(brightness(0) > 195);
+        assert!(brightness(4) < brightness(0));
+        assert!(brightness(6) < brightness(4));
+        assert_eq!(brightness(15), 30);
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+
+use crate::constants::FRAME_RATE;
+use crate::font::Font;
+use crate::geometry::{Point, Rect, Subpixels};
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::utils::Color;
+
+// Formats a number of frames as minutes, seconds, and hundredths, e.g. "1:02.50".
+pub fn format_time(frames: u64) -> String {
+    let rate = FRAME_RATE as u64;
+    let seconds = frames / rate;
+    let hundredths = (frames % rate) * 100 / rate;
+    format!("{}:{:02}.{:02}", seconds / 60, seconds % 60, hundredths)
+}
+
+// Formats how far ahead (-) or behind (+) a time is compared to the best.
+fn format_delta(frames: u64, best: u64) -> String {
+    if frames < best {
+        format!("-{}", format_time(best - frames))
+    } else {
+        format!("+{}", format_time(frames - best))
+    }
+}
+
+// The best times file has one level per line, like "1234 assets/levels/x.tmx".
+fn parse_best_times(text: &str) -> Result<BTreeMap<PathBuf, u64>> {
+    let mut best_times = BTreeMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (frames, path) = line
+            .split_once(' ')
+            .with_context(|| format!("invalid best time: {}", line))?;
+        best_times.insert(PathBuf::from(path), frames.parse()?);
+    }
+    Ok(best_times)
+}
+
+fn encode_best_times(best_times: &BTreeMap<PathBuf, u64>) -> String {
+    let mut text = String::new();
+    for (path, frames) in best_times.iter() {
+        text.push_str(&format!("{} {}\n", frames, path.display()));
+    }
+    text
+}
+
+struct Split {
+    frames: u64,
+    previous_best: Option<u64>,
+}
+
+/*
+ * Times each level, in frames, from when it's loaded until its door closes or the
+ * player warps out of it.
+ *
+ * Levels reached through a door or warp are splits of the same run. The best time for
+ * each level is remembered, and saved to a file if one was given.
+ */
+pub struct Speedrun {
+    best_times: BTreeMap<PathBuf, u64>,
+    best_times_path: Option<PathBuf>,
+    splits: Vec<Split>,
+    level: Option<PathBuf>,
+    frames: u64,
+    running: bool,
+}
+
+impl Speedrun {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Speedrun {
+        Speedrun {
+            best_times: BTreeMap::new(),
+            best_times_path: None,
+            splits: Vec::new(),
+            level: None,
+            frames: 0,
+            running: false,
+        }
+    }
+
+    // Reads best times from the file, if it exists, and saves new ones to it.
+    pub fn load_best_times(&mut self, path: &Path) -> Result<()> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.best_times = parse_best_times(&text)
+                    .map_err(|e| anyhow!("unable to read best times from {:?}: {}", path, e))?;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(anyhow!("unable to read {:?}: {}", path, e)),
+        }
+        self.best_times_path = Some(path.to_owned());
+        Ok(())
+    }
+
+    fn save_best_times(&self) -> Result<()> {
+        if let Some(path) = &self.best_times_path {
+            fs::write(path, encode_best_times(&self.best_times))?;
+        }
+        Ok(())
+    }
+
+    // Starts a new run, forgetting any previous splits.
+    pub fn start_run(&mut self, level: &Path) {
+        self.splits.clear();
+        self.start_level(level);
+    }
+
+    // Starts timing a level as part of the current run.
+    pub fn start_level(&mut self, level: &Path) {
+        self.level = Some(level.to_owned());
+        self.frames = 0;
+        self.running = true;
+    }
+
+    pub fn tick(&mut self) {
+        if self.running {
+            self.frames += 1;
+        }
+    }
+
+    // Stops the timer, and records the time as a split.
+    pub fn finish_level(&mut self) {
+        if !self.running {
+            return;
+        }
+        self.running = false;
+        let Some(level) = self.level.clone() else {
+            return;
+        };
+
+        let previous_best = self.best_times.get(&level).copied();
+        info!(
+            "finished {:?} in {} (best: {:?})",
+            level,
+            format_time(self.frames),
+            previous_best.map(format_time)
+        );
+        self.splits.push(Split {
+            frames: self.frames,
+            previous_best,
+        });
+
+        if previous_best.is_none_or(|best| self.frames < best) {
+            self.best_times.insert(level, self.frames);
+            if let Err(e) = self.save_best_times() {
+                warn!("unable to save best times: {}", e);
+            }
+        }
+    }
+
+    fn best(&self) -> Option<u64> {
+        self.best_times.get(self.level.as_ref()?).copied()
+    }
+
+    pub fn draw(&self, context: &mut RenderContext, font: &Font) {
+        let mut lines = vec![format!("TIME {}", format_time(self.frames))];
+        lines.push(match self.best() {
+            Some(best) => format!("BEST {}", format_time(best)),
+            None => "BEST -:--.--".to_owned(),
+        });
+        if let Some(split) = self.splits.last() {
+            lines.push(match split.previous_best {
+                Some(best) => format!("LAST {}", format_delta(split.frames, best)),
+                None => format!("LAST {}", format_time(split.frames)),
+            });
+            let total = self.splits.iter().map(|split| split.frames).sum::<u64>() + self.frames;
+            lines.push(format!("RUN  {}", format_time(total)));
+        }
+
+        let padding = Subpixels::from_pixels(2);
+        let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        let w = font.char_width * columns as i32 + padding * 2;
+        let h = font.char_height * lines.len() as i32 + padding * 2;
+        let area = context.logical_area_in_subpixels();
+        let background = Rect {
+            x: area.right() - w,
+            y: area.bottom() - h,
+            w,
+            h,
+        };
+        let color = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 127,
+        };
+        context.fill_rect(background, RenderLayer::Hud, color);
+
+        let mut pos = background.top_left() + Point::new(padding, padding);
+        for line in lines.iter() {
+            font.draw_string(context, RenderLayer::Hud, pos, line);
+            pos.y += font.char_height;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_times() {
+        assert_eq!(format_time(0), "0:00.00");
+        assert_eq!(format_time(30), "0:00.50");
+        assert_eq!(format_time(61), "0:01.01");
+        assert_eq!(format_time(60 * 75), "1:15.00");
+        assert_eq!(format_delta(90, 60), "+0:00.50");
+        assert_eq!(format_delta(60, 90), "-0:00.50");
+    }
+
+    #[test]
+    fn best_times_round_trip() {
+        let text = "120 assets/levels/a.tmx\n\n  3600 assets/levels/b c.tmx\n";
+        let best_times = parse_best_times(text).unwrap();
+        assert_eq!(best_times.len(), 2);
+        assert_eq!(best_times[Path::new("assets/levels/b c.tmx")], 3600);
+        assert_eq!(
+            encode_best_times(&best_times),
+            "120 assets/levels/a.tmx\n3600 assets/levels/b c.tmx\n"
+        );
+        assert!(parse_best_times("oops").is_err());
+    }
+
+    #[test]
+    fn splits_update_best_times() {
+        let a = Path::new("a.tmx");
+        let b = Path::new("b.tmx");
+        let mut speedrun = Speedrun::new();
+
+        speedrun.start_run(a);
+        for _ in 0..100 {
+            speedrun.tick();
+        }
+        speedrun.finish_level();
+        // Ticks after the level is finished don't count.
+        speedrun.tick();
+        speedrun.start_level(b);
+        speedrun.tick();
+        speedrun.finish_level();
+        assert_eq!(speedrun.best_times[a], 100);
+        assert_eq!(speedrun.best_times[b], 1);
+        assert_eq!(speedrun.splits.len(), 2);
+
+        // A slower run doesn't replace the best.
+        speedrun.start_run(a);
+        for _ in 0..150 {
+            speedrun.tick();
+        }
+        speedrun.finish_level();
+        assert_eq!(speedrun.best_times[a], 100);
+        assert_eq!(speedrun.splits.len(), 1);
+        assert_eq!(speedrun.splits[0].previous_best, Some(100));
+    }
+}
@@ -7,6 +7,7 @@ use anyhow::{anyhow, bail, Context, Result};
 use crate::filemanager::FileManager;
 use crate::geometry::{Pixels, Rect, Subpixels};
 use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::utils::Color;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Sprite {
@@ -70,6 +71,21 @@ impl SpriteSheet {
             context.draw(self.sprite, layer, dest, source_area);
         }
     }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn blit_tinted(
+        &self,
+        context: &mut RenderContext,
+        layer: RenderLayer,
+        dest: Rect<Subpixels>,
+        index: u32,
+        sprite_layer: u32,
+        reverse: bool,
+        tint: Color,
+    ) {
+        let source_area = self.source_area(index, sprite_layer);
+        context.draw_tinted(self.sprite, layer, dest, source_area, reverse, tint);
+    }
 }
 
 pub struct Animation {
@@ -220,6 +236,7 @@ impl AnimationStateMachineRule {
 
 pub struct AnimationStateMachine {
     rules: Vec<AnimationStateMachineRule>,
+    states: HashSet<String>,
 }
 
 impl AnimationStateMachine {
@@ -258,7 +275,12 @@ impl AnimationStateMachine {
                 rules.push(rule);
             }
         }
-        Ok(AnimationStateMachine { rules })
+        Ok(AnimationStateMachine { rules, states })
+    }
+
+    // Whether the file listed the state, so that older files can fall back to another one.
+    pub fn has_state(&self, state: &str) -> bool {
+        self.states.contains(state)
     }
 
     pub fn next_frame(&self, current_frame: u32, current_state: &str) -> Result<u32> {
@@ -1,35 +1,153 @@
-use std::{mem, path::Path};
+use std::{
+    iter, mem,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use log::{info, warn};
 
 use crate::{
+    bindings::Bindings,
+    constants::{REWIND_BUDGET, START_SCENE},
+    controls::ControlsMenu,
     filemanager::FileManager,
     font::Font,
+    ghost::Ghost,
     imagemanager::ImageLoader,
     inputmanager::InputSnapshot,
     level::Level,
     levelselect::LevelSelect,
     menu::Menu,
+    progress::Progress,
     rendercontext::RenderContext,
     scene::{Scene, SceneResult},
     soundmanager::SoundManager,
+    speedrun::Speedrun,
+    storage::Storage,
 };
 
+// Keeps what was found in a level that's being left, whether or not it was finished.
+fn record_progress(progress: &mut Progress, scene: &dyn Scene, completed: bool) {
+    if let Some((path, mut attempt)) = scene.progress() {
+        attempt.completed = completed;
+        progress.record(&path, &attempt);
+    }
+}
+
 pub struct StageManager {
     current: Box<dyn Scene>,
     stack: Vec<Box<dyn Scene>>,
+    last_transition: Option<SceneResult>,
+    // The most recent quick save, which outlives the scene it came from.
+    saved_state: Option<Vec<u8>>,
+    rewind_budget: usize,
+    // A previous run to race against, which restarts whenever its level is loaded.
+    ghost: Option<Box<Ghost>>,
+    speedrun: Speedrun,
+    progress: Progress,
+    // The bindings being used, and any new ones the player saved in the controls menu.
+    bindings: Bindings,
+    new_bindings: Option<Bindings>,
 }
 
 impl StageManager {
     pub fn new(file_manager: &FileManager, images: &mut dyn ImageLoader) -> Result<StageManager> {
-        let path = Path::new("assets/menus/start.tmx");
-        let menu = Menu::new_menu(path, file_manager, images)?;
+        Self::with_start_scene(START_SCENE, file_manager, images)
+    }
+
+    // Starts the game in a scene given like a menu action, e.g. "level:assets/levels/x.tmx".
+    pub fn with_start_scene(
+        scene: &str,
+        file_manager: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<StageManager> {
+        let mut speedrun = Speedrun::new();
+        let current: Box<dyn Scene> = if let Some(path) = scene.strip_prefix("menu:") {
+            Box::new(Menu::new_menu(Path::new(path), file_manager, images)?)
+        } else if let Some(path) = scene.strip_prefix("level:") {
+            speedrun.start_run(Path::new(path));
+            Box::new(Level::new(Path::new(path), file_manager, images)?)
+        } else {
+            bail!("invalid start scene: {}", scene);
+        };
         Ok(StageManager {
-            current: Box::new(menu),
+            current,
             stack: Vec::new(),
+            last_transition: None,
+            saved_state: None,
+            rewind_budget: REWIND_BUDGET,
+            ghost: None,
+            speedrun,
+            progress: Progress::noop(),
+            bindings: Bindings::default(),
+            new_bindings: None,
         })
     }
 
+    // Tells the controls menu which bindings are in use.
+    pub fn set_bindings(&mut self, bindings: &Bindings) {
+        self.bindings = bindings.clone();
+    }
+
+    // Returns bindings saved in the controls menu since the last call, for the input manager.
+    pub fn take_new_bindings(&mut self) -> Option<Bindings> {
+        let bindings = self.new_bindings.take()?;
+        self.bindings = bindings.clone();
+        Some(bindings)
+    }
+
+    // Reads personal best times from a file, and saves any new ones back to it.
+    pub fn load_best_times(&mut self, path: &Path) -> Result<()> {
+        self.speedrun.load_best_times(path)
+    }
+
+    // Loads saved progress, and keeps saving it there as levels are played.
+    pub fn load_progress(&mut self, storage: Box<dyn Storage>) {
+        self.progress = Progress::load(storage);
+        self.current.set_progress(&self.progress);
+    }
+
+    // Sets how much memory each level can use for rewinding, including ones already loaded.
+    pub fn set_rewind_budget(&mut self, budget: usize) {
+        self.rewind_budget = budget;
+        self.current.set_rewind_budget(budget);
+        for scene in self.stack.iter_mut() {
+            scene.set_rewind_budget(budget);
+        }
+    }
+
+    // Loads a recording that started in a level, to show as a ghost whenever that level is played.
+    pub fn load_ghost(
+        &mut self,
+        path: &Path,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<()> {
+        let ghost = Ghost::load(path, files, images)?;
+        self.current.start_ghost(&ghost, files, images)?;
+        self.ghost = Some(Box::new(ghost));
+        Ok(())
+    }
+
+    // Whether a level is being played, as opposed to a menu or the pause screen.
+    fn in_level(&self) -> bool {
+        self.current.is_level()
+    }
+
+    fn new_level(
+        &mut self,
+        path: &Path,
+        files: &FileManager,
+        images: &mut dyn ImageLoader,
+    ) -> Result<Box<dyn Scene>> {
+        let mut level = Level::new(path, files, images)?;
+        level.set_rewind_budget(self.rewind_budget);
+        if let Some(ghost) = &self.ghost {
+            level.start_ghost(ghost, files, images)?;
+        }
+        Ok(Box::new(level))
+    }
+
     pub fn update(
         &mut self,
         context: &RenderContext,
@@ -38,8 +156,40 @@ impl StageManager {
         images: &mut dyn ImageLoader,
         sounds: &mut SoundManager,
     ) -> Result<bool> {
+        if inputs.save_state_clicked {
+            if let Some(state) = self.save_state() {
+                info!("saved state ({} bytes)", state.len());
+                self.saved_state = Some(state);
+            }
+        }
+        if inputs.load_state_clicked {
+            if let Some(state) = self.saved_state.take() {
+                match self.restore_state(&state) {
+                    Ok(()) => info!("loaded saved state"),
+                    Err(e) => warn!("unable to load saved state: {}", e),
+                }
+                self.saved_state = Some(state);
+            }
+        }
+
+        // The timer only runs while a level is being played, so it pauses along with the game.
+        if self.in_level() {
+            self.speedrun.tick();
+        }
+
         let result = self.current.update(context, inputs, sounds);
-        Ok(match result {
+        let transitioned = !matches!(result, SceneResult::Continue);
+        if transitioned {
+            self.last_transition = Some(result.clone());
+            // Going through a door or a warp finishes a level.
+            if matches!(result, SceneResult::SwitchToLevel { .. }) {
+                record_progress(&mut self.progress, self.current.as_ref(), true);
+            }
+            if let Some(bindings) = self.current.take_new_bindings() {
+                self.new_bindings = Some(bindings);
+            }
+        }
+        let running = match result {
             SceneResult::Continue => true,
             SceneResult::Pop => {
                 if let Some(next) = self.stack.pop() {
@@ -50,7 +200,9 @@ impl StageManager {
                 }
             }
             SceneResult::PopTwo => {
-                self.stack.pop();
+                if let Some(abandoned) = self.stack.pop() {
+                    record_progress(&mut self.progress, abandoned.as_ref(), false);
+                }
                 if let Some(next) = self.stack.pop() {
                     self.current = next;
                     true
@@ -59,19 +211,39 @@ impl StageManager {
                 }
             }
             SceneResult::PushLevel { path } => {
-                let level = Level::new(&path, files, images)?;
-                let level = Box::new(level);
+                self.speedrun.start_run(&path);
+                let level = self.new_level(&path, files, images)?;
                 let previous = mem::replace(&mut self.current, level);
                 self.stack.push(previous);
                 true
             }
             SceneResult::SwitchToLevel { path } => {
-                self.current = Box::new(Level::new(&path, files, images)?);
+                // Going through a door or a warp finishes a level, and starts the next split.
+                self.speedrun.finish_level();
+                self.speedrun.start_level(&path);
+                self.current = self.new_level(&path, files, images)?;
                 true
             }
             SceneResult::ReloadLevel { path } => {
-                self.stack.pop();
-                self.current = Box::new(Level::new(&path, files, images)?);
+                if let Some(abandoned) = self.stack.pop() {
+                    record_progress(&mut self.progress, abandoned.as_ref(), false);
+                }
+                self.speedrun.start_level(&path);
+                self.current = self.new_level(&path, files, images)?;
+                true
+            }
+            SceneResult::RespawnLevel { path } => {
+                // Levels with a checkpoint pick up from there, without restarting the timer.
+                let respawned = self.stack.last_mut().is_some_and(|level| level.respawn());
+                if respawned {
+                    self.current = self.stack.pop().expect("level was just respawned");
+                } else {
+                    if let Some(abandoned) = self.stack.pop() {
+                        record_progress(&mut self.progress, abandoned.as_ref(), false);
+                    }
+                    self.speedrun.start_level(&path);
+                    self.current = self.new_level(&path, files, images)?;
+                }
                 true
             }
             SceneResult::PushMenu { path } => {
@@ -81,8 +253,15 @@ impl StageManager {
                 self.stack.push(previous);
                 true
             }
+            SceneResult::PushControls { path } => {
+                let controls = ControlsMenu::new(path.as_deref(), &self.bindings, files, images)?;
+                let controls = Box::new(controls);
+                let previous = mem::replace(&mut self.current, controls);
+                self.stack.push(previous);
+                true
+            }
             SceneResult::PushLevelSelect { path } => {
-                let level_select = LevelSelect::new(&path, files)?;
+                let level_select = LevelSelect::new(&path, files, images)?;
                 let level_select = Box::new(level_select);
                 let previous = mem::replace(&mut self.current, level_select);
                 self.stack.push(previous);
@@ -102,11 +281,124 @@ impl StageManager {
                 self.stack.push(previous);
                 true
             }
-        })
+        };
+
+        if transitioned {
+            self.current.set_progress(&self.progress);
+        }
+        Ok(running)
+    }
+
+    // The level being played, even if it's paused or the player just died in it.
+    pub fn current_level(&self) -> Option<PathBuf> {
+        iter::once(&self.current)
+            .chain(self.stack.iter().rev())
+            .find_map(|scene| scene.progress())
+            .map(|(path, _)| path)
+    }
+
+    pub fn state_hash(&self) -> Option<u64> {
+        self.current.state_hash()
+    }
+
+    // Returns a snapshot of the current scene, if it's one that can be saved.
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        self.current.save_state()
+    }
+
+    // Restores a snapshot from save_state. It must be for the same level as the current scene.
+    pub fn restore_state(&mut self, state: &[u8]) -> Result<()> {
+        self.current.restore_state(state)
+    }
+
+    // Returns the scene transition from the most recent update, if there was one.
+    pub fn take_transition(&mut self) -> Option<SceneResult> {
+        self.last_transition.take()
     }
 
     pub fn draw(&mut self, context: &mut RenderContext, font: &Font) {
         self.current
             .draw(context, font, self.stack.last().map(Box::as_ref));
+        if self.in_level() {
+            self.speedrun.draw(context, font);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::{RENDER_HEIGHT, RENDER_WIDTH};
+    use crate::testing::{test_archive, test_map, TestImages, TEST_MAP_PATH};
+
+    const PAUSE_BUTTONS: &str = r#"
+<object id="1" x="8" y="8" width="64" height="16">
+ <properties>
+  <property name="uibutton" type="bool" value="true"/>
+  <property name="action" value="pop"/>
+ </properties>
+</object>
+<object id="2" x="8" y="32" width="64" height="16">
+ <properties>
+  <property name="uibutton" type="bool" value="true"/>
+  <property name="action" value="pop2"/>
+ </properties>
+</object>
+"#;
+
+    const SPAWN: &str = r#"
+<object id="1" x="0" y="16">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+"#;
+
+    #[test]
+    fn records_progress_when_leaving_a_level() {
+        let level = test_map(&["........", "........", "........", "########"], SPAWN);
+        let pause = test_map(&["........"], PAUSE_BUTTONS);
+        let files = test_archive(&[
+            (TEST_MAP_PATH, level.as_bytes()),
+            ("assets/menus/pause.tmx", pause.as_bytes()),
+        ]);
+        let start = format!("level:{}", TEST_MAP_PATH);
+        let mut stage = StageManager::with_start_scene(&start, &files, &mut TestImages).unwrap();
+        let context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, 0, 0).unwrap();
+        let mut sounds = SoundManager::noop_manager();
+        let mut update = |stage: &mut StageManager, inputs: InputSnapshot| {
+            stage
+                .update(&context, &inputs, &files, &mut TestImages, &mut sounds)
+                .unwrap()
+        };
+        let pause = InputSnapshot {
+            cancel_clicked: true,
+            ..Default::default()
+        };
+        let ok = InputSnapshot {
+            ok_down: true,
+            ..Default::default()
+        };
+        let recorded =
+            |stage: &StageManager| stage.progress.level(Path::new(TEST_MAP_PATH)).is_some();
+
+        // Pausing and resuming is still the same attempt.
+        update(&mut stage, pause);
+        update(&mut stage, ok);
+        update(&mut stage, InputSnapshot::default());
+        assert!(stage.in_level());
+        assert!(!recorded(&stage));
+
+        // Quitting from the pause screen gives up on it.
+        update(&mut stage, pause);
+        update(
+            &mut stage,
+            InputSnapshot {
+                menu_down_clicked: true,
+                ..Default::default()
+            },
+        );
+        update(&mut stage, ok);
+        assert!(!update(&mut stage, InputSnapshot::default()));
+        assert!(recorded(&stage));
     }
 }
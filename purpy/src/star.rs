@@ -1,7 +1,7 @@
 use std::rc::Rc;
 
 use anyhow::{Context, Result};
-use rand::random;
+use rand::Rng;
 
 use crate::geometry::{Pixels, Point, Rect, Subpixels};
 use crate::rendercontext::{RenderContext, RenderLayer};
@@ -9,13 +9,14 @@ use crate::tilemap::TileIndex;
 use crate::tilemap::{MapObject, TileMap};
 
 pub struct Star {
+    pub id: i32,
     area: Rect<Subpixels>,
     tilemap: Rc<TileMap>,
     tile_gid: TileIndex,
 }
 
-fn star_rand() -> i32 {
-    ((((random::<i32>() % 41) - 20) as f32) / 20.0).trunc() as i32
+fn star_rand(rng: &mut impl Rng) -> i32 {
+    ((((rng.gen::<i32>() % 41) - 20) as f32) / 20.0).trunc() as i32
 }
 
 impl Star {
@@ -24,6 +25,7 @@ impl Star {
         let tile_gid = gid as TileIndex;
         let area = obj.position.into();
         Ok(Star {
+            id: obj.id,
             area,
             tile_gid,
             tilemap,
@@ -35,7 +37,10 @@ impl Star {
     }
 
     pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, offset: Point<Subpixels>) {
-        let rand = Point::new(Pixels::new(star_rand()), Pixels::new(star_rand()));
+        let rand = Point::new(
+            Pixels::new(star_rand(&mut context.rng)),
+            Pixels::new(star_rand(&mut context.rng)),
+        );
         let pos = self.area.top_left() + offset + rand.into();
         let dest = Rect {
             x: pos.x,
@@ -0,0 +1,51 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+// Somewhere to keep a bit of text between runs of the game, like saved progress.
+pub trait Storage {
+    // Returns the saved text, or None if nothing has been saved yet.
+    fn load(&self) -> Result<Option<String>>;
+    fn save(&mut self, data: &str) -> Result<()>;
+}
+
+// Forgets everything when the game exits.
+pub struct NoopStorage {}
+
+impl Storage for NoopStorage {
+    fn load(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn save(&mut self, _data: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: &Path) -> FileStorage {
+        FileStorage {
+            path: path.to_owned(),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self) -> Result<Option<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => Ok(Some(text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow!("unable to read {:?}: {}", self.path, e)),
+        }
+    }
+
+    fn save(&mut self, data: &str) -> Result<()> {
+        fs::write(&self.path, data).map_err(|e| anyhow!("unable to write {:?}: {}", self.path, e))
+    }
+}
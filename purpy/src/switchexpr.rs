@@ -0,0 +1,268 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+/*
+ * A boolean expression over switches, like "red && !(blue || green)".
+ *
+ * Names are on if the switch is on. ! binds tightest, then &&, then ||, and parentheses
+ * group. Conditions are parsed when the map is loaded, so mistakes are caught right away.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Switch(String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+// Something a button or switch tile does to a switch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    TurnOn(String),
+    TurnOff(String),
+    Toggle(String),
+}
+
+/*
+ * A comma-separated list of commands, like "red, !blue, ~green".
+ *
+ * A plain name turns a switch on, ! turns it off, and ~ toggles it.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commands(Vec<Command>);
+
+impl Commands {
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.0.iter()
+    }
+}
+
+// Anything that isn't whitespace or an operator can be part of a switch name.
+fn is_name_char(c: char) -> bool {
+    !c.is_whitespace() && !"!&|()~,".contains(c)
+}
+
+fn parse_name(s: &str) -> Result<String> {
+    if s.is_empty() {
+        bail!("missing switch name");
+    }
+    if let Some(c) = s.chars().find(|c| !is_name_char(*c)) {
+        bail!("unexpected {:?} in switch name {:?}", c, s);
+    }
+    Ok(s.to_owned())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "{:?}", name),
+            Token::Not => write!(f, "!"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '!' => tokens.push(Token::Not),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '&' | '|' => {
+                if chars.next() != Some(c) {
+                    bail!("expected {}{}", c, c);
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            c if is_name_char(c) => {
+                let mut name = c.to_string();
+                while let Some(c) = chars.next_if(|c| is_name_char(*c)) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            _ => bail!("unexpected {:?}", c),
+        }
+    }
+    Ok(tokens)
+}
+
+// A recursive descent parser, with one function for each level of precedence.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn next_if(&mut self, token: Token) -> bool {
+        if self.tokens.get(self.pos) == Some(&token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Condition> {
+        let mut left = self.parse_and()?;
+        while self.next_if(Token::Or) {
+            let right = self.parse_and()?;
+            left = Condition::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Condition> {
+        let mut left = self.parse_not()?;
+        while self.next_if(Token::And) {
+            let right = self.parse_not()?;
+            left = Condition::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Condition> {
+        Ok(match self.next() {
+            Some(Token::Name(name)) => Condition::Switch(name),
+            Some(Token::Not) => Condition::Not(Box::new(self.parse_not()?)),
+            Some(Token::Open) => {
+                let condition = self.parse_or()?;
+                if !self.next_if(Token::Close) {
+                    bail!("missing )");
+                }
+                condition
+            }
+            Some(token) => bail!("expected a switch name, but got {}", token),
+            None => bail!("expected a switch name, but got the end"),
+        })
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse = || {
+            let mut parser = Parser {
+                tokens: tokenize(s)?,
+                pos: 0,
+            };
+            let condition = parser.parse_or()?;
+            if let Some(token) = parser.next() {
+                bail!("unexpected {}", token);
+            }
+            Ok(condition)
+        };
+        parse().with_context(|| format!("invalid condition: {:?}", s))
+    }
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(if let Some(name) = s.strip_prefix('~') {
+            Command::Toggle(parse_name(name)?)
+        } else if let Some(name) = s.strip_prefix('!') {
+            Command::TurnOff(parse_name(name)?)
+        } else {
+            Command::TurnOn(parse_name(s)?)
+        })
+    }
+}
+
+impl FromStr for Commands {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let commands: Result<Vec<Command>> = s.split(',').map(|s| s.trim().parse()).collect();
+        let commands = commands.with_context(|| format!("invalid commands: {:?}", s))?;
+        Ok(Commands(commands))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn switch(name: &str) -> Box<Condition> {
+        Box::new(Condition::Switch(name.to_owned()))
+    }
+
+    #[test]
+    fn conditions_follow_precedence() {
+        assert_eq!("red".parse::<Condition>().unwrap(), *switch("red"));
+        assert_eq!(
+            "!red".parse::<Condition>().unwrap(),
+            Condition::Not(switch("red"))
+        );
+        assert_eq!(
+            "a || b && !c".parse::<Condition>().unwrap(),
+            Condition::Or(
+                switch("a"),
+                Box::new(Condition::And(
+                    switch("b"),
+                    Box::new(Condition::Not(switch("c")))
+                ))
+            )
+        );
+        assert_eq!(
+            "!(a||b)&&c".parse::<Condition>().unwrap(),
+            Condition::And(
+                Box::new(Condition::Not(Box::new(Condition::Or(
+                    switch("a"),
+                    switch("b")
+                )))),
+                switch("c")
+            )
+        );
+    }
+
+    #[test]
+    fn bad_conditions_are_rejected() {
+        for s in ["", "a &&", "a & b", "(a || b", "a b", "a)", "~a", "a, b"] {
+            assert!(s.parse::<Condition>().is_err(), "{:?}", s);
+        }
+        let error = format!("{:#}", "red && ".parse::<Condition>().unwrap_err());
+        assert!(error.contains("\"red && \""), "{}", error);
+    }
+
+    #[test]
+    fn commands_are_comma_separated() {
+        assert_eq!(
+            "red, !blue,~green".parse::<Commands>().unwrap(),
+            Commands(vec![
+                Command::TurnOn("red".to_owned()),
+                Command::TurnOff("blue".to_owned()),
+                Command::Toggle("green".to_owned()),
+            ])
+        );
+        for s in ["", "red,", "red blue", "!!red", "~"] {
+            assert!(s.parse::<Commands>().is_err(), "{:?}", s);
+        }
+    }
+}
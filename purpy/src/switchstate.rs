@@ -1,7 +1,12 @@
 use std::collections::HashSet;
 
+use anyhow::Result;
 use log::info;
 
+use crate::binary::{BinaryReader, BinaryWriter, Fnv1a};
+use crate::switchexpr::{Command, Commands, Condition};
+
+#[derive(Clone)]
 pub struct SwitchState {
     on: HashSet<String>,
 }
@@ -36,20 +41,67 @@ impl SwitchState {
         self.on.contains(s)
     }
 
-    pub fn apply_command(&mut self, s: &str) {
-        if let Some(toggled) = s.strip_prefix('~') {
-            self.toggle(toggled);
-        } else if let Some(negated) = s.strip_prefix('!') {
-            self.turn_off(negated);
-        } else {
-            self.turn_on(s);
+    pub fn apply_commands(&mut self, commands: &Commands) {
+        for command in commands.iter() {
+            match command {
+                Command::TurnOn(s) => self.turn_on(s),
+                Command::TurnOff(s) => self.turn_off(s),
+                Command::Toggle(s) => self.toggle(s),
+            }
+        }
+    }
+
+    // Whether applying the commands would leave the switches as they are. Toggles never do.
+    pub fn are_commands_applied(&self, commands: &Commands) -> bool {
+        commands.iter().all(|command| match command {
+            Command::TurnOn(s) => self.is_on(s),
+            Command::TurnOff(s) => !self.is_on(s),
+            Command::Toggle(_) => false,
+        })
+    }
+
+    pub fn is_condition_true(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Switch(s) => self.is_on(s),
+            Condition::Not(condition) => !self.is_condition_true(condition),
+            Condition::And(left, right) => {
+                self.is_condition_true(left) && self.is_condition_true(right)
+            }
+            Condition::Or(left, right) => {
+                self.is_condition_true(left) || self.is_condition_true(right)
+            }
+        }
+    }
+
+    fn sorted(&self) -> Vec<&String> {
+        let mut on: Vec<&String> = self.on.iter().collect();
+        on.sort();
+        on
+    }
+
+    pub fn state_hash(&self, hasher: &mut Fnv1a) {
+        // Sets have no stable order, so hash the switches in sorted order.
+        let on = self.sorted();
+        hasher.write_u32(on.len() as u32);
+        for s in on {
+            hasher.write_str(s);
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        let on = self.sorted();
+        writer.write_varint(on.len() as u64);
+        for s in on {
+            writer.write_string(s);
         }
     }
 
-    pub fn is_condition_true(&self, s: &str) -> bool {
-        match s.strip_prefix('!') {
-            Some(negated) => !self.is_on(negated),
-            None => self.is_on(s),
+    pub fn restore_state(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        self.on.clear();
+        let count = reader.read_varint()?;
+        for _ in 0..count {
+            self.on.insert(reader.read_string()?);
         }
+        Ok(())
     }
 }
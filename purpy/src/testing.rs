@@ -0,0 +1,202 @@
+// Tiny levels built in memory, so tests can run the game without the real assets.
+
+use std::path::Path;
+
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::constants::REWIND_BUDGET;
+use crate::filemanager::FileManager;
+use crate::geometry::{Pixels, Rect};
+use crate::imagemanager::ImageLoader;
+use crate::inputmanager::INPUT_SNAPSHOT_LAYOUT;
+use crate::replay::{Replay, ReplayEntry, ReplayHeader, REPLAY_FORMAT_VERSION};
+use crate::sprite::{Animation, Sprite, SpriteSheet};
+
+pub use crate::inputmanager::InputSnapshot;
+
+pub const TEST_MAP_PATH: &str = "assets/levels/test.tmx";
+
+// Every state the player can be in just stays on the first frame.
+const PLAYER_STATES: &str = "
+[STATES]
+STANDING
+[TRANSITIONS]
+*, *: 1
+";
+
+// Tile 1 is solid, tile 2 is a ladder, tile 3 is for objects like buttons, tile 4 is ice,
+// tile 5 is sticky, and tile 6 is spikes.
+const TILESET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.10" name="test" tilewidth="8" tileheight="8" tilecount="6" columns="6">
+ <image source="test.png" width="48" height="8"/>
+ <tile id="1">
+  <properties>
+   <property name="solid" type="bool" value="false"/>
+   <property name="climbable" type="bool" value="true"/>
+  </properties>
+ </tile>
+ <tile id="3">
+  <properties>
+   <property name="acceleration" type="int" value="25"/>
+   <property name="friction" type="int" value="10"/>
+  </properties>
+ </tile>
+ <tile id="4">
+  <properties>
+   <property name="friction" type="int" value="300"/>
+  </properties>
+ </tile>
+ <tile id="5">
+  <properties>
+   <property name="deadly" type="bool" value="true"/>
+  </properties>
+ </tile>
+</tileset>
+"#;
+
+// Loads every image as an empty sprite, since nothing is drawn.
+pub struct TestImages;
+
+impl TestImages {
+    fn sprite(height: Pixels) -> Sprite {
+        Sprite {
+            id: 0,
+            area: Rect {
+                x: Pixels::new(0),
+                y: Pixels::new(0),
+                w: Pixels::new(256),
+                h: height,
+            },
+        }
+    }
+}
+
+impl ImageLoader for TestImages {
+    fn load_sprite(&mut self, _path: &Path) -> Result<Sprite> {
+        Ok(Self::sprite(Pixels::new(256)))
+    }
+
+    fn load_spritesheet(
+        &mut self,
+        _path: &Path,
+        sprite_width: Pixels,
+        sprite_height: Pixels,
+    ) -> Result<SpriteSheet> {
+        SpriteSheet::new(Self::sprite(Pixels::new(256)), sprite_width, sprite_height)
+    }
+
+    fn load_animation(
+        &mut self,
+        _path: &Path,
+        sprite_width: Pixels,
+        sprite_height: Pixels,
+    ) -> Result<Animation> {
+        Animation::new(Self::sprite(sprite_height), sprite_width, sprite_height)
+    }
+}
+
+/*
+ * A map of 8x8 tiles, drawn as rows of text, with # for solid tiles, H for ladders, I for
+ * ice, S for sticky tiles, ^ for spikes, and anything else for nothing. The objects are
+ * Tiled's XML for whatever else the map needs, like where the player spawns.
+ */
+pub fn test_map(rows: &[&str], objects: &str) -> String {
+    let width = rows[0].len();
+    let data = rows
+        .iter()
+        .map(|row| {
+            assert_eq!(row.len(), width, "every row should be the same width");
+            row.chars()
+                .map(|c| match c {
+                    '#' => "1",
+                    'H' => "2",
+                    'I' => "4",
+                    'S' => "5",
+                    '^' => "6",
+                    _ => "0",
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" width="{width}" height="{height}" tilewidth="8" tileheight="8">
+ <tileset firstgid="1" source="../levels/test.tsx"/>
+ <layer id="1" name="tiles" width="{width}" height="{height}">
+  <data encoding="csv">
+{data}
+</data>
+ </layer>
+ <objectgroup id="2" name="objects">
+{objects}
+ </objectgroup>
+</map>
+"#,
+        height = rows.len(),
+    )
+}
+
+// The files for a single map at TEST_MAP_PATH, as described for test_map.
+pub fn test_files(rows: &[&str], objects: &str) -> FileManager {
+    let map = test_map(rows, objects);
+    test_archive(&[(TEST_MAP_PATH, map.as_bytes())])
+}
+
+// The given files, along with everything test maps need, like the tileset.
+pub fn test_archive(files: &[(&str, &[u8])]) -> FileManager {
+    let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+    let shared: [(&str, &[u8]); 2] = [
+        (
+            "assets/sprites/skelly2_states.txt",
+            PLAYER_STATES.as_bytes(),
+        ),
+        ("assets/levels/test.tsx", TILESET.as_bytes()),
+    ];
+    for (path, data) in shared.iter().chain(files) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        archive
+            .append_data(&mut header, path, *data)
+            .expect("writing to memory should work");
+    }
+    let bytes = archive
+        .into_inner()
+        .and_then(|gz| gz.finish())
+        .expect("writing to memory should work");
+    FileManager::from_archive_bytes(&bytes).expect("test files should be a valid archive")
+}
+
+// A recording that starts in the given scene, with the inputs for every frame.
+pub fn test_recording(start_scene: &str, frames: &[InputSnapshot]) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut previous = 0;
+    for (frame, inputs) in frames.iter().enumerate() {
+        let snapshot = inputs.encode();
+        if snapshot != previous {
+            entries.push(ReplayEntry {
+                frame: frame as u64,
+                snapshot,
+            });
+            previous = snapshot;
+        }
+    }
+    let replay = Replay {
+        header: ReplayHeader {
+            format_version: REPLAY_FORMAT_VERSION,
+            game_version: env!("CARGO_PKG_VERSION").to_owned(),
+            input_layout: INPUT_SNAPSHOT_LAYOUT,
+            start_scene: start_scene.to_owned(),
+            seed: 0,
+            frame_count: frames.len() as u64,
+            rewind_budget: REWIND_BUDGET as u64,
+        },
+        entries,
+        state_hashes: Vec::new(),
+    };
+    replay.encode()
+}
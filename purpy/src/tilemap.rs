@@ -4,15 +4,17 @@ use std::ops::{Index, IndexMut};
 use std::path::Path;
 use std::str::FromStr;
 
-use crate::constants::MAX_GRAVITY;
+use crate::constants::WATER_COLOR;
 use crate::filemanager::FileManager;
 use crate::geometry::{Pixels, Point, Rect, Subpixels};
 use crate::imagemanager::ImageLoader;
+use crate::physics::PhysicsConfig;
 use crate::properties::{PropertiesXml, PropertyMap};
 use crate::rendercontext::{RenderContext, RenderLayer};
 use crate::slope::Slope;
 use crate::smallintset::SmallIntSet;
 use crate::sprite::{Animation, Sprite};
+use crate::switchexpr::{Commands, Condition};
 use crate::switchstate::SwitchState;
 use crate::tileset::{LocalTileIndex, TileProperties, TileSet};
 use crate::utils::{cmp_in_direction, try_move_to_bounds, Color, Direction};
@@ -90,6 +92,13 @@ struct ObjectXml {
     gid: Option<u32>,
 
     properties: Option<PropertiesXml>,
+    polyline: Option<PolylineXml>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolylineXml {
+    #[serde(rename = "@points")]
+    points: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -274,6 +283,23 @@ impl FromStr for Overflow {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnemyType {
+    Walker,
+    Flier,
+}
+
+impl FromStr for EnemyType {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "walker" => Ok(EnemyType::Walker),
+            "flier" => Ok(EnemyType::Flier),
+            _ => Err(anyhow!("invalid enemy type: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum ConveyorDirection {
     Left,
@@ -322,21 +348,28 @@ pub struct MapObjectProperties {
     pub door: bool,
     pub star: bool,
     pub spawn: bool,
+    pub checkpoint: bool,
+    pub enemy: Option<EnemyType>,
+    pub turret: bool,
+    pub key: bool,
+    pub lock: bool,
     // Tiles
     pub solid: bool,
     // Map Areas
     pub preferred_x: Option<Pixels>,
     pub preferred_y: Option<Pixels>,
+    pub water: bool,
     // Platforms
     pub distance: i32,
     pub speed: Option<Pixels>,
-    pub condition: Option<String>,
+    pub condition: Option<Condition>,
     pub overflow: Overflow,
     pub direction: Direction,
     pub convey: Option<ConveyorDirection>,
-    // Buttons
+    // Buttons, keys, locks, and doors that need a key
     pub button_type: ButtonType,
     pub color: Option<String>,
+    pub commands: Option<Commands>,
     // Doors
     pub sprite: Option<String>,
     pub destination: Option<String>,
@@ -347,6 +380,10 @@ pub struct MapObjectProperties {
     pub dy: Pixels,
     // Warp zones
     pub warp: Option<String>,
+    // Enemies, which also use sprite, speed, facing_left, and overflow
+    pub path: Option<i32>,
+    // Turrets, which also use direction, speed, and condition
+    pub interval: Option<i32>,
     // UI elements
     pub uibutton: bool,
     pub action: Option<String>,
@@ -365,12 +402,24 @@ impl TryFrom<PropertyMap> for MapObjectProperties {
             door: properties.get_bool("door")?.unwrap_or(false),
             star: properties.get_bool("star")?.unwrap_or(false),
             spawn: properties.get_bool("spawn")?.unwrap_or(false),
+            checkpoint: properties.get_bool("checkpoint")?.unwrap_or(false),
+            enemy: properties
+                .get_string("enemy")?
+                .map(|s| s.parse())
+                .transpose()?,
+            turret: properties.get_bool("turret")?.unwrap_or(false),
+            key: properties.get_bool("key")?.unwrap_or(false),
+            lock: properties.get_bool("lock")?.unwrap_or(false),
             solid: properties.get_bool("solid")?.unwrap_or(false),
             preferred_x: properties.get_int("preferred_x")?.map(Pixels::new),
             preferred_y: properties.get_int("preferred_y")?.map(Pixels::new),
+            water: properties.get_bool("water")?.unwrap_or(false),
             distance: properties.get_int("distance")?.unwrap_or(0),
             speed: properties.get_int("speed")?.map(Pixels::new),
-            condition: properties.get_string("condition")?.map(str::to_string),
+            condition: properties
+                .get_string("condition")?
+                .map(|s| s.parse())
+                .transpose()?,
             overflow: properties
                 .get_string("overflow")?
                 .unwrap_or("oscillate")
@@ -385,6 +434,10 @@ impl TryFrom<PropertyMap> for MapObjectProperties {
                 .unwrap_or("toggle")
                 .parse()?,
             color: properties.get_string("color")?.map(str::to_string),
+            commands: properties
+                .get_string("commands")?
+                .map(|s| s.parse())
+                .transpose()?,
             sprite: properties.get_string("sprite")?.map(str::to_string),
             destination: properties.get_string("destination")?.map(str::to_string),
             stars_needed: properties.get_int("stars_needed")?.unwrap_or(0),
@@ -392,6 +445,8 @@ impl TryFrom<PropertyMap> for MapObjectProperties {
             dy: Pixels::new(properties.get_int("dy")?.unwrap_or(0)),
             facing_left: properties.get_bool("facing_left")?.unwrap_or(false),
             warp: properties.get_string("warp")?.map(str::to_string),
+            path: properties.get_int("path")?,
+            interval: properties.get_int("interval")?,
             uibutton: properties.get_bool("uibutton")?.unwrap_or(false),
             label: properties.get_string("label")?.unwrap_or("").to_string(),
             action: properties.get_string("action")?.map(str::to_string),
@@ -404,9 +459,32 @@ pub struct MapObject {
     pub id: i32,
     pub gid: Option<TileIndex>,
     pub position: Rect<Pixels>,
+    // For polylines, the points relative to the position.
+    pub polyline: Vec<Point<Pixels>>,
     pub properties: MapObjectProperties,
 }
 
+// Parses polyline points, e.g. "0,0 32,0 32,-16".
+fn parse_points(s: &str) -> Result<Vec<Point<Pixels>>> {
+    s.split_whitespace()
+        .map(|point| {
+            let (x, y) = point
+                .split_once(',')
+                .with_context(|| format!("invalid point: {}", point))?;
+            let x: f32 = x
+                .parse()
+                .with_context(|| format!("invalid point: {}", point))?;
+            let y: f32 = y
+                .parse()
+                .with_context(|| format!("invalid point: {}", point))?;
+            Ok(Point::new(
+                Pixels::new(x.round() as i32),
+                Pixels::new(y.round() as i32),
+            ))
+        })
+        .collect()
+}
+
 impl MapObject {
     fn new(xml: ObjectXml, tilesets: &TileSetList) -> Result<MapObject> {
         let id = xml.id;
@@ -420,6 +498,10 @@ impl MapObject {
             .transpose()?
             .unwrap_or_default();
         let gid = xml.gid.map(|index| (index as usize).into());
+        let polyline = match &xml.polyline {
+            Some(polyline) => parse_points(&polyline.points)?,
+            None => Vec::new(),
+        };
 
         if let Some(gid) = gid {
             let (tileset, tile_id) = tilesets.lookup(gid);
@@ -437,12 +519,15 @@ impl MapObject {
         let h = Pixels::new(height);
         let position = Rect { x, y, w, h };
 
-        let properties = properties.try_into()?;
+        let properties = properties
+            .try_into()
+            .with_context(|| format!("invalid properties for object {}", id))?;
 
         Ok(MapObject {
             id,
             gid,
             position,
+            polyline,
             properties,
         })
     }
@@ -476,8 +561,10 @@ impl TileSetList {
 
 pub struct TileMapProperties {
     pub dark: bool,
-    pub gravity: Option<Subpixels>,
     pub cancel_action: String,
+    // A file of physics parameters to share between levels, and this map's own changes.
+    pub physics_profile: Option<String>,
+    pub physics: Vec<(&'static str, i32)>,
 }
 
 impl TryFrom<PropertyMap> for TileMapProperties {
@@ -485,11 +572,14 @@ impl TryFrom<PropertyMap> for TileMapProperties {
     fn try_from(properties: PropertyMap) -> Result<Self> {
         Ok(TileMapProperties {
             dark: properties.get_bool("is_dark")?.unwrap_or(false),
-            gravity: properties.get_int("gravity")?.map(Subpixels::new),
             cancel_action: properties
                 .get_string("cancel_action")?
                 .unwrap_or("pop")
                 .to_string(),
+            physics_profile: properties
+                .get_string("physics_profile")?
+                .map(str::to_string),
+            physics: PhysicsConfig::overrides(&properties)?,
         })
     }
 }
@@ -954,8 +1044,101 @@ impl TileMap {
         result
     }
 
-    pub fn get_gravity(&self) -> Subpixels {
-        self.properties.gravity.unwrap_or(MAX_GRAVITY)
+    // Returns whether the tile at row, col is a ladder or something else climbable.
+    fn is_climbable_tile(&self, row: i32, col: i32, switches: &SwitchState) -> bool {
+        if row < 0 || col < 0 || row >= self.height || col >= self.width {
+            return false;
+        }
+        for layer in self.layers.iter() {
+            let Layer::Tile(layer) = layer else {
+                continue;
+            };
+            if !layer.player && self.player_layer.is_some() {
+                continue;
+            }
+            let mut tile_gid = layer[(row as usize, col as usize)];
+            if tile_gid.0 == 0 {
+                continue;
+            }
+            if !self.is_condition_met(tile_gid, switches) {
+                let Some(TileProperties {
+                    alternate: Some(alt),
+                    ..
+                }) = self.get_tile_properties(tile_gid)
+                else {
+                    continue;
+                };
+                let (tileset, _) = self.tilesets.lookup(tile_gid);
+                tile_gid = tileset.get_global_tile_index(*alt);
+            }
+            if let Some(TileProperties {
+                climbable: true, ..
+            }) = self.get_tile_properties(tile_gid)
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /*
+     * Returns whether any part of the rect overlaps a ladder or other climbable tile.
+     *
+     * Just touching the edge of one doesn't count, so that standing on top of a ladder
+     * isn't the same as being on it.
+     */
+    pub fn is_climbable(&self, player_rect: Rect<Subpixels>, switches: &SwitchState) -> bool {
+        let row1 = player_rect.top() / self.tileheight.as_subpixels();
+        let col1 = player_rect.left() / self.tilewidth.as_subpixels();
+        let row2 = (player_rect.bottom() - Subpixels::new(1)) / self.tileheight.as_subpixels();
+        let col2 = (player_rect.right() - Subpixels::new(1)) / self.tilewidth.as_subpixels();
+
+        for row in row1..=row2 {
+            for col in col1..=col2 {
+                if self.is_climbable_tile(row, col, switches) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /*
+     * The top of a ladder can be stood on, like a one-way platform. Returns how far a rect
+     * moving down needs to move back up to be on top of one, or zero if it isn't.
+     */
+    pub fn try_move_onto_ladder(
+        &self,
+        player_rect: Rect<Subpixels>,
+        switches: &SwitchState,
+    ) -> Subpixels {
+        let tileheight = self.tileheight.as_subpixels();
+        let tilewidth = self.tilewidth.as_subpixels();
+        let row1 = player_rect.top() / tileheight;
+        let col1 = player_rect.left() / tilewidth;
+        let row2 = player_rect.bottom() / tileheight;
+        let col2 = player_rect.right() / tilewidth;
+
+        let mut result = Subpixels::zero();
+        for row in row1..=row2 {
+            for col in col1..=col2 {
+                if !self.is_climbable_tile(row, col, switches)
+                    || self.is_climbable_tile(row - 1, col, switches)
+                {
+                    continue;
+                }
+                // Like other one-way platforms, only the top half catches the player.
+                let top = Rect {
+                    x: tilewidth * col,
+                    y: tileheight * row,
+                    w: tilewidth,
+                    h: tileheight / 2,
+                };
+                let offset = try_move_to_bounds(player_rect, top, Direction::Down);
+                result = result.min(offset);
+            }
+        }
+        result
     }
 
     pub fn get_preferred_view(
@@ -981,6 +1164,29 @@ impl TileMap {
         (preferred_x, preferred_y)
     }
 
+    // Returns whether the rect overlaps any water zone.
+    pub fn is_in_water(&self, player_rect: Rect<Subpixels>) -> bool {
+        self.objects.iter().any(|obj| {
+            obj.gid.is_none() && obj.properties.water && player_rect.intersects(obj.position.into())
+        })
+    }
+
+    // Tints the water zones, so that whatever is in them looks submerged.
+    pub fn draw_water(
+        &self,
+        context: &mut RenderContext,
+        render_layer: RenderLayer,
+        offset: Point<Subpixels>,
+    ) {
+        for obj in self.objects.iter() {
+            if obj.gid.is_some() || !obj.properties.water {
+                continue;
+            }
+            let area: Rect<Subpixels> = obj.position.into();
+            context.fill_rect(area + offset, render_layer, WATER_COLOR);
+        }
+    }
+
     pub fn draw_tile(
         &self,
         context: &mut RenderContext,
@@ -2,7 +2,7 @@ use std::num::ParseIntError;
 use std::path::Path;
 use std::str::FromStr;
 
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use log::info;
 use serde::Deserialize;
 
@@ -13,11 +13,15 @@ use crate::properties::{PropertiesXml, PropertyMap};
 use crate::slope::Slope;
 use crate::smallintmap::SmallIntMap;
 use crate::sprite::{Animation, Sprite};
+use crate::switchexpr::{Commands, Condition};
 use crate::tilemap::TileIndex;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct LocalTileIndex(usize);
 
+// How far a tile can scale the player's acceleration or friction, as a percentage.
+const MAX_SURFACE_PERCENT: i32 = 1000;
+
 impl From<LocalTileIndex> for usize {
     fn from(value: LocalTileIndex) -> Self {
         value.0
@@ -99,8 +103,8 @@ pub struct TileProperties {
     pub solid: bool,
     pub animation: Option<String>,
     // switches
-    pub switch: Option<String>,
-    pub condition: Option<String>,
+    pub switch: Option<Commands>,
+    pub condition: Option<Condition>,
     pub alternate: Option<LocalTileIndex>,
     // oneway
     pub oneway: Option<String>,
@@ -115,10 +119,29 @@ pub struct TileProperties {
     pub hitbox_bottom: Pixels,
     // spikes
     pub deadly: bool,
+    // surfaces, as percentages of the normal walking acceleration and deceleration
+    pub acceleration: i32,
+    pub friction: i32,
+    // ladders
+    pub climbable: bool,
 
     pub raw: PropertyMap,
 }
 
+// A surface that doesn't let the player speed up or slow down at all would trap them.
+fn surface_percent(value: &PropertyMap, name: &str) -> Result<i32> {
+    let percent = value.get_int(name)?.unwrap_or(100);
+    if !(1..=MAX_SURFACE_PERCENT).contains(&percent) {
+        bail!(
+            "{} should be a percentage from 1 to {}, not {}",
+            name,
+            MAX_SURFACE_PERCENT,
+            percent
+        );
+    }
+    Ok(percent)
+}
+
 impl TryFrom<PropertyMap> for TileProperties {
     type Error = Error;
 
@@ -129,7 +152,10 @@ impl TryFrom<PropertyMap> for TileProperties {
             alternate: value
                 .get_int("alternate")?
                 .map(|x| LocalTileIndex(x as usize)),
-            condition: value.get_string("condition")?.map(str::to_string),
+            condition: value
+                .get_string("condition")?
+                .map(|s| s.parse())
+                .transpose()?,
             oneway: value.get_string("oneway")?.map(str::to_string),
             slope: value.get_bool("slope")?.unwrap_or(false),
             left_y: Pixels::new(value.get_int("left_y")?.unwrap_or(0)),
@@ -139,7 +165,10 @@ impl TryFrom<PropertyMap> for TileProperties {
             hitbox_right: Pixels::new(value.get_int("hitbox_right")?.unwrap_or(0)),
             hitbox_bottom: Pixels::new(value.get_int("hitbox_bottom")?.unwrap_or(0)),
             deadly: value.get_bool("deadly")?.unwrap_or(false),
-            switch: value.get_string("switch")?.map(str::to_string),
+            acceleration: surface_percent(&value, "acceleration")?,
+            friction: surface_percent(&value, "friction")?,
+            climbable: value.get_bool("climbable")?.unwrap_or(false),
+            switch: value.get_string("switch")?.map(|s| s.parse()).transpose()?,
             raw: value,
         })
     }
@@ -217,7 +246,9 @@ impl TileSet {
                 TileSetXmlField::Tile(tile_xml) => {
                     let id = LocalTileIndex(tile_xml.id);
                     let props: PropertyMap = tile_xml.properties.try_into()?;
-                    let props: TileProperties = props.try_into()?;
+                    let props: TileProperties = props.try_into().with_context(|| {
+                        format!("invalid properties for tile {} in {:?}", id.0, path)
+                    })?;
                     if props.slope {
                         slopes.insert(id, Slope::new(&props)?);
                     }
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use crate::bindings::Action;
+use crate::font::Font;
+use crate::geometry::{Pixels, Point, Rect, Subpixels};
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::utils::Color;
+use crate::{RENDER_HEIGHT, RENDER_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchPhase {
+    Started,
+    Moved,
+    Ended,
+}
+
+// A part of the screen that triggers an action while it's being touched.
+struct TouchButton {
+    area: Rect<Pixels>,
+    action: Action,
+    label: &'static str,
+}
+
+const BUTTON_SIZE: i32 = 24;
+
+const fn button(x: i32, y: i32, action: Action, label: &'static str) -> TouchButton {
+    TouchButton {
+        area: Rect {
+            x: Pixels::new(x),
+            y: Pixels::new(y),
+            w: Pixels::new(BUTTON_SIZE),
+            h: Pixels::new(BUTTON_SIZE),
+        },
+        action,
+        label,
+    }
+}
+
+// A d-pad in the bottom left corner, jump in the bottom right, and pause in the top right.
+const BUTTONS: [TouchButton; 6] = [
+    button(4, RENDER_HEIGHT as i32 - 52, Action::Left, "<"),
+    button(52, RENDER_HEIGHT as i32 - 52, Action::Right, ">"),
+    button(28, RENDER_HEIGHT as i32 - 76, Action::Up, "^"),
+    button(28, RENDER_HEIGHT as i32 - 28, Action::Down, "v"),
+    button(
+        RENDER_WIDTH as i32 - 28,
+        RENDER_HEIGHT as i32 - 40,
+        Action::Jump,
+        "J",
+    ),
+    button(RENDER_WIDTH as i32 - 28, 4, Action::Cancel, "II"),
+];
+
+/*
+ * Every finger that's touching the screen, in render coordinates.
+ *
+ * Touches that start on one of the on-screen buttons trigger its action until they're
+ * lifted. Any other touch acts like the mouse, so menus can be tapped. Only one of those
+ * is followed at a time.
+ */
+pub struct Touches {
+    touches: BTreeMap<u64, Point<Pixels>>,
+    // The touch that's acting like the mouse, if any.
+    pointer: Option<u64>,
+    // The buttons are only shown once the screen has been touched.
+    used: bool,
+}
+
+impl Touches {
+    pub fn new() -> Touches {
+        Touches {
+            touches: BTreeMap::new(),
+            pointer: None,
+            used: false,
+        }
+    }
+
+    fn button_at(position: Point<Pixels>) -> Option<&'static TouchButton> {
+        BUTTONS.iter().find(|button| button.area.contains(position))
+    }
+
+    // Returns whether the touch is acting like the mouse.
+    pub fn start(&mut self, id: u64, position: Point<Pixels>) -> bool {
+        self.used = true;
+        self.touches.insert(id, position);
+        if self.pointer.is_none() && Touches::button_at(position).is_none() {
+            self.pointer = Some(id);
+        }
+        self.pointer == Some(id)
+    }
+
+    // Returns whether the touch is acting like the mouse.
+    pub fn move_to(&mut self, id: u64, position: Point<Pixels>) -> bool {
+        if let Some(touch) = self.touches.get_mut(&id) {
+            *touch = position;
+        }
+        self.pointer == Some(id)
+    }
+
+    // Returns whether the touch was acting like the mouse.
+    pub fn end(&mut self, id: u64) -> bool {
+        self.touches.remove(&id);
+        if self.pointer == Some(id) {
+            self.pointer = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_button_down(&self, button: &TouchButton) -> bool {
+        self.touches
+            .iter()
+            .any(|(id, position)| Some(*id) != self.pointer && button.area.contains(*position))
+    }
+
+    pub fn is_action_down(&self, action: Action) -> bool {
+        BUTTONS
+            .iter()
+            .any(|button| button.action == action && self.is_button_down(button))
+    }
+
+    pub fn draw(&self, context: &mut RenderContext, font: &Font) {
+        if !self.used {
+            return;
+        }
+        for button in BUTTONS.iter() {
+            let alpha = if self.is_button_down(button) { 160 } else { 80 };
+            let color = Color {
+                r: 255,
+                g: 255,
+                b: 255,
+                a: alpha,
+            };
+            let area: Rect<Subpixels> = button.area.into();
+            context.fill_rect(area, RenderLayer::Hud, color);
+
+            let label_width = font.char_width * button.label.len() as i32;
+            let pos = Point::new(
+                area.x + (area.w - label_width) / 2,
+                area.y + (area.h - font.char_height) / 2,
+            );
+            font.draw_string(context, RenderLayer::Hud, pos, button.label);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn center(action: Action) -> Point<Pixels> {
+        let button = BUTTONS.iter().find(|b| b.action == action).unwrap();
+        Point::new(
+            button.area.x + button.area.w / 2,
+            button.area.y + button.area.h / 2,
+        )
+    }
+
+    #[test]
+    fn touches_press_buttons() {
+        let mut touches = Touches::new();
+        assert!(!touches.start(1, center(Action::Left)));
+        assert!(!touches.start(2, center(Action::Jump)));
+        assert!(touches.is_action_down(Action::Left));
+        assert!(touches.is_action_down(Action::Jump));
+        assert!(!touches.is_action_down(Action::Right));
+
+        // Sliding a finger from one button to another switches actions.
+        assert!(!touches.move_to(1, center(Action::Right)));
+        assert!(!touches.is_action_down(Action::Left));
+        assert!(touches.is_action_down(Action::Right));
+
+        assert!(!touches.end(2));
+        assert!(!touches.is_action_down(Action::Jump));
+    }
+
+    #[test]
+    fn other_touches_act_like_the_mouse() {
+        let mut touches = Touches::new();
+        let middle = Point::new(Pixels::new(160), Pixels::new(90));
+        assert!(touches.start(1, middle));
+        // Only one touch can be the mouse at a time.
+        assert!(!touches.start(2, middle));
+        // Dragging the mouse over a button doesn't press it.
+        assert!(touches.move_to(1, center(Action::Left)));
+        assert!(!touches.is_action_down(Action::Left));
+        assert!(touches.end(1));
+        assert!(!touches.end(2));
+    }
+}
@@ -0,0 +1,192 @@
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+use num_traits::Zero;
+
+use crate::binary::{BinaryReader, BinaryWriter, Fnv1a};
+use crate::constants::{BULLET_COLOR, BULLET_SIZE, BULLET_SPEED, TURRET_INTERVAL};
+use crate::geometry::{Point, Rect, Subpixels};
+use crate::rendercontext::{RenderContext, RenderLayer};
+use crate::switchexpr::Condition;
+use crate::switchstate::SwitchState;
+use crate::tilemap::{MapObject, TileIndex, TileMap};
+use crate::utils::Direction;
+
+// A bullet fired by a turret. It flies in a straight line until it hits something solid.
+#[derive(Debug, Clone, Copy)]
+pub struct Projectile {
+    position: Rect<Subpixels>,
+    delta: Point<Subpixels>,
+}
+
+impl Projectile {
+    pub fn state_hash(&self, hasher: &mut Fnv1a) {
+        hasher.write_rect(self.position);
+        hasher.write_point(self.delta);
+    }
+
+    fn direction(&self) -> Direction {
+        if self.delta.x > Subpixels::zero() {
+            Direction::Right
+        } else if self.delta.x < Subpixels::zero() {
+            Direction::Left
+        } else if self.delta.y < Subpixels::zero() {
+            Direction::Up
+        } else {
+            Direction::Down
+        }
+    }
+
+    // Moves the projectile, and returns false if it hit a wall or left the map.
+    pub fn update(&mut self, map: &TileMap, switches: &SwitchState) -> bool {
+        self.position += self.delta;
+        let hit = map.try_move_to(self.position, self.direction(), switches, false);
+        hit.hard_offset.is_zero()
+    }
+
+    pub fn intersects(&self, player_rect: Rect<Subpixels>) -> bool {
+        self.position.intersects(player_rect)
+    }
+
+    pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, offset: Point<Subpixels>) {
+        context.fill_rect(self.position + offset, layer, BULLET_COLOR);
+    }
+
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        writer.write_rect(self.position);
+        writer.write_point(self.delta);
+    }
+
+    pub fn read_state(reader: &mut BinaryReader) -> Result<Projectile> {
+        Ok(Projectile {
+            position: reader.read_rect()?,
+            delta: reader.read_point()?,
+        })
+    }
+}
+
+/*
+ * Something that shoots projectiles in one direction.
+ *
+ * Without a condition, a turret fires every interval frames. With one, it fires as soon as
+ * the condition becomes true, and then every interval frames for as long as it stays true.
+ * An interval of 0 means it only fires when the condition becomes true.
+ */
+pub struct Turret {
+    area: Rect<Subpixels>,
+    tilemap: Rc<TileMap>,
+    tile_gid: TileIndex,
+    direction: Direction,
+    speed: Subpixels,
+    interval: i32,
+    condition: Option<Condition>,
+
+    counter: i32,
+    was_active: bool,
+}
+
+impl Turret {
+    // Only the parts of the turret that affect the simulation are hashed.
+    pub fn state_hash(&self, hasher: &mut Fnv1a) {
+        hasher.write_i32(self.counter);
+        hasher.write_bool(self.was_active);
+    }
+
+    pub fn new(obj: &MapObject, tilemap: Rc<TileMap>) -> Result<Turret> {
+        let tile_gid = obj.gid.context("gid required for turrets")?;
+        let interval = obj.properties.interval.unwrap_or(TURRET_INTERVAL);
+        let condition = obj.properties.condition.clone();
+        if interval < 0 || (interval == 0 && condition.is_none()) {
+            bail!(
+                "turret {} needs a positive interval, or a condition",
+                obj.id
+            );
+        }
+
+        // This is 16 for historical reasons, to match how platform speeds are given.
+        let speed = obj.properties.speed.unwrap_or(BULLET_SPEED);
+        let speed = speed.as_subpixels() / 16;
+        if speed <= Subpixels::zero() {
+            bail!("turret {} needs a positive speed", obj.id);
+        }
+
+        Ok(Turret {
+            area: obj.position.into(),
+            tilemap,
+            tile_gid,
+            direction: obj.properties.direction,
+            speed,
+            interval,
+            condition,
+            counter: interval,
+            // Turrets that start out active wait a whole interval before their first shot.
+            was_active: true,
+        })
+    }
+
+    fn is_active(&self, switches: &SwitchState) -> bool {
+        self.condition
+            .as_ref()
+            .is_none_or(|condition| switches.is_condition_true(condition))
+    }
+
+    // Returns a new projectile, if the turret fired one this frame.
+    pub fn update(&mut self, switches: &SwitchState) -> Option<Projectile> {
+        let active = self.is_active(switches);
+        let triggered = active && !self.was_active;
+        self.was_active = active;
+        if !active {
+            return None;
+        }
+        if !triggered {
+            if self.interval == 0 {
+                return None;
+            }
+            self.counter -= 1;
+            if self.counter > 0 {
+                return None;
+            }
+        }
+        self.counter = self.interval;
+        Some(self.fire())
+    }
+
+    // Makes a projectile just outside the middle of the side the turret is facing.
+    fn fire(&self) -> Projectile {
+        let size = BULLET_SIZE;
+        let center_x = self.area.x + (self.area.w - size) / 2;
+        let center_y = self.area.y + (self.area.h - size) / 2;
+        let zero = Subpixels::zero();
+        let (x, y, delta) = match self.direction {
+            Direction::Up => (center_x, self.area.top() - size, (zero, self.speed * -1)),
+            Direction::Down => (center_x, self.area.bottom(), (zero, self.speed)),
+            Direction::Left => (self.area.left() - size, center_y, (self.speed * -1, zero)),
+            Direction::Right => (self.area.right(), center_y, (self.speed, zero)),
+        };
+        Projectile {
+            position: Rect {
+                x,
+                y,
+                w: size,
+                h: size,
+            },
+            delta: delta.into(),
+        }
+    }
+
+    pub fn draw(&self, context: &mut RenderContext, layer: RenderLayer, offset: Point<Subpixels>) {
+        self.tilemap
+            .draw_tile(context, self.tile_gid, layer, self.area + offset);
+    }
+
+    pub fn save_state(&self, writer: &mut BinaryWriter) {
+        writer.write_i32(self.counter);
+        writer.write_bool(self.was_active);
+    }
+
+    pub fn restore_state(&mut self, reader: &mut BinaryReader) -> Result<()> {
+        self.counter = reader.read_i32()?;
+        self.was_active = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use std::path::Path;
 
 use anyhow::Result;
-use log::info;
+use log::{error, info};
 
 use crate::font::Font;
 use crate::geometry::Pixels;
@@ -42,12 +42,28 @@ impl UiButton {
         tile_height: Pixels,
         images: &mut dyn ImageLoader,
     ) -> Result<Self> {
-        let position = object.position.as_subpixels();
+        Self::with_label(
+            object.position.as_subpixels(),
+            object.properties.label.clone(),
+            object.properties.action.clone(),
+            tile_width,
+            tile_height,
+            images,
+        )
+    }
+
+    // Creates a button that isn't from a map, e.g. for a scene that lays out its own buttons.
+    pub fn with_label(
+        position: Rect<Subpixels>,
+        label: String,
+        action: Option<String>,
+        tile_width: Pixels,
+        tile_height: Pixels,
+        images: &mut dyn ImageLoader,
+    ) -> Result<Self> {
         let sprite =
             images.load_spritesheet(Path::new("assets/uibutton.png"), tile_width, tile_height)?;
         let state = UiButtonState::Normal;
-        let label = object.properties.label.clone();
-        let action = object.properties.action.clone();
         Ok(UiButton {
             position,
             sprite,
@@ -59,6 +75,14 @@ impl UiButton {
         })
     }
 
+    pub fn action(&self) -> Option<&str> {
+        self.action.as_deref()
+    }
+
+    pub fn set_label(&mut self, label: String) {
+        self.label = label;
+    }
+
     pub fn update(
         &mut self,
         selected: bool,
@@ -155,3 +179,52 @@ impl UiButton {
         font.draw_string(context, layer, label_pos, &self.label);
     }
 }
+
+pub enum ButtonOrderDirection {
+    Vertical,
+    Horizontal,
+}
+
+// The order to move through a set of buttons with the keyboard or a gamepad.
+pub struct ButtonOrder {
+    horizontal: Vec<usize>,
+    vertical: Vec<usize>,
+}
+
+impl ButtonOrder {
+    pub fn new(buttons: &[UiButton]) -> ButtonOrder {
+        let mut button_positions: Vec<(usize, Subpixels, Subpixels)> = buttons
+            .iter()
+            .enumerate()
+            .map(|(i, button)| (i, button.position.x, button.position.y))
+            .collect();
+
+        button_positions.sort_by_key(|(_, x, y)| (*y, *x));
+        let horizontal: Vec<usize> = button_positions.iter().map(|(i, _, _)| *i).collect();
+
+        button_positions.sort_by_key(|(_, x, y)| (*x, *y));
+        let vertical: Vec<usize> = button_positions.iter().map(|(i, _, _)| *i).collect();
+
+        ButtonOrder {
+            horizontal,
+            vertical,
+        }
+    }
+
+    pub fn first(&self) -> usize {
+        self.vertical[0]
+    }
+
+    pub fn next(&self, selected: usize, delta: i32, direction: ButtonOrderDirection) -> usize {
+        let order: &[usize] = match direction {
+            ButtonOrderDirection::Horizontal => &self.horizontal,
+            ButtonOrderDirection::Vertical => &self.vertical,
+        };
+        let Some(pos) = order.iter().position(|i| *i == selected) else {
+            error!("invalid button index: {}", selected);
+            return selected;
+        };
+        let new_pos = ((pos + order.len()) as i32 + delta) as usize % order.len();
+        order[new_pos]
+    }
+}
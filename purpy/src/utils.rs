@@ -47,6 +47,16 @@ pub struct Color {
     pub a: u8,
 }
 
+impl Color {
+    // Tinting a sprite with white leaves it unchanged.
+    pub const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
+}
+
 impl FromStr for Color {
     type Err = Error;
 
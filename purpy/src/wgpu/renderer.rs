@@ -302,7 +302,7 @@ where
                 break;
             }
 
-            let (destination, source, color, reversed) = match entry {
+            let (destination, source, color, reversed, tint) = match entry {
                 SpriteBatchEntry::FillRect { destination, color } => (
                     *destination,
                     Rect {
@@ -313,12 +313,14 @@ where
                     },
                     *color,
                     false,
+                    Color::WHITE,
                 ),
                 SpriteBatchEntry::Sprite {
                     sprite,
                     source,
                     destination,
                     reversed,
+                    tint,
                 } => {
                     let source = Rect {
                         x: sprite.area.x + source.x,
@@ -332,7 +334,7 @@ where
                         b: 0,
                         a: 0,
                     };
-                    (*destination, source, color, *reversed)
+                    (*destination, source, color, *reversed, *tint)
                 }
             };
 
@@ -359,6 +361,7 @@ where
             let sr = sr / xscale;
 
             let color: [f32; 4] = color.into();
+            let tint: [f32; 4] = tint.into();
 
             let i = vertex_count;
             vertex_count += 6;
@@ -367,31 +370,37 @@ where
                 position: [dl, dt],
                 tex_coords: [sl, st],
                 color,
+                tint,
             };
             vertices[i + 1] = Vertex {
                 position: [dl, db],
                 tex_coords: [sl, sb],
                 color,
+                tint,
             };
             vertices[i + 2] = Vertex {
                 position: [dr, dt],
                 tex_coords: [sr, st],
                 color,
+                tint,
             };
             vertices[i + 3] = Vertex {
                 position: [dr, dt],
                 tex_coords: [sr, st],
                 color,
+                tint,
             };
             vertices[i + 4] = Vertex {
                 position: [dl, db],
                 tex_coords: [sl, sb],
                 color,
+                tint,
             };
             vertices[i + 5] = Vertex {
                 position: [dr, db],
                 tex_coords: [sr, sb],
                 color,
+                tint,
             };
         }
         //info!("created {} vertices", vertex_count);
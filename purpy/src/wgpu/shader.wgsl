@@ -17,12 +17,14 @@ struct RenderVertexInput {
     @location(0) position: vec2<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) color: vec4<f32>,
+    @location(3) tint: vec4<f32>,
 }
 
 struct RenderVertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) color: vec4<f32>,
+    @location(2) tint: vec4<f32>,
 }
 
 @vertex
@@ -32,6 +34,7 @@ fn vs_main(
     var out: RenderVertexOutput;
     out.tex_coords = model.tex_coords;
     out.color = model.color;
+    out.tint = model.tint;
 
     var x: f32 = model.position.x / render_vertex_uniform.logical_size.x;
     var y: f32 = model.position.y / render_vertex_uniform.logical_size.y;
@@ -58,7 +61,7 @@ fn fs_main(in: RenderVertexOutput) -> @location(0) vec4<f32> {
     if col.a > 0.0 {
         return col;
     } else {
-        return textureSample(texture_atlas, texture_atlas_sampler, in.tex_coords);
+        return textureSample(texture_atlas, texture_atlas_sampler, in.tex_coords) * in.tint;
     }
 }
 
@@ -0,0 +1,285 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+
+use purpy::{
+    FileManager, ImageLoader, ImageManager, InputManager, RecordOption, RenderContext, SceneResult,
+    SoftwareRenderer, SoundManager, StageManager, RENDER_HEIGHT, RENDER_WIDTH,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    pub playback: String,
+
+    // Defaults to the length of the recording. Text recordings don't know their length,
+    // so they need this.
+    #[arg(long)]
+    pub frames: Option<u64>,
+
+    #[arg(long)]
+    pub assets: Option<String>,
+
+    // If set, the last frame is rendered and written to this path as an image.
+    #[arg(long)]
+    pub screenshot: Option<String>,
+}
+
+// What happened while playing back a recording.
+struct Summary {
+    // Each scene transition, with the frame it happened on.
+    transitions: Vec<(u64, SceneResult)>,
+    // The frame the game exited on, if it did.
+    exited: Option<u64>,
+    frames: u64,
+    seed: u64,
+    deaths: u32,
+    first_desync: Option<u64>,
+    final_level: Option<PathBuf>,
+}
+
+// Plays back the recording, and returns what happened along with the game at the end.
+fn run(
+    args: &Args,
+    file_manager: &FileManager,
+    images: &mut dyn ImageLoader,
+) -> Result<(Summary, StageManager)> {
+    let mut input_manager = InputManager::with_options(
+        RENDER_WIDTH as i32,
+        RENDER_HEIGHT as i32,
+        false,
+        RecordOption::Playback(Path::new(&args.playback).to_owned()),
+        file_manager,
+    )?;
+    let Some(frames) = args.frames.or(input_manager.frame_count()) else {
+        bail!(
+            "{} is a text recording, which doesn't store how long it ran, so --frames is required",
+            args.playback
+        );
+    };
+
+    let mut stage_manager =
+        StageManager::with_start_scene(input_manager.start_scene(), file_manager, images)?;
+    stage_manager.set_rewind_budget(input_manager.rewind_budget());
+    let mut sound_manager = SoundManager::noop_manager();
+
+    let mut transitions = Vec::new();
+    let mut exited = None;
+    let mut deaths = 0;
+    let seed = input_manager.seed();
+    let mut frame = 0;
+    let mut context = RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, frame, seed)?;
+
+    while frame < frames {
+        context.start_frame(frame, seed);
+        let input_snapshot = input_manager.update(frame);
+
+        let running = stage_manager.update(
+            &context,
+            &input_snapshot,
+            file_manager,
+            images,
+            &mut sound_manager,
+        )?;
+        input_manager.check_state(frame, stage_manager.state_hash());
+
+        if let Some(transition) = stage_manager.take_transition() {
+            if let SceneResult::PushKillScreen { .. } = transition {
+                deaths += 1;
+            }
+            transitions.push((frame, transition));
+        }
+
+        frame += 1;
+        if !running {
+            exited = Some(frame);
+            break;
+        }
+    }
+
+    let summary = Summary {
+        transitions,
+        exited,
+        frames: frame,
+        seed,
+        deaths,
+        first_desync: input_manager.first_desync(),
+        final_level: stage_manager.current_level(),
+    };
+    Ok((summary, stage_manager))
+}
+
+fn run_with_assets(args: Args) -> Result<()> {
+    let file_manager = match &args.assets {
+        Some(path) => FileManager::from_archive_file(Path::new(path)),
+        None => FileManager::from_fs(),
+    }?;
+
+    let texture_atlas_path = Path::new("assets/textures.png");
+    let renderer = SoftwareRenderer::new(texture_atlas_path, &file_manager)?;
+    let mut image_manager = ImageManager::new(renderer)?;
+    image_manager.load_texture_atlas(
+        texture_atlas_path,
+        Path::new("assets/textures_index.txt"),
+        &file_manager,
+    )?;
+    let font = image_manager.load_font(&file_manager)?;
+
+    let (summary, mut stage_manager) = run(&args, &file_manager, &mut image_manager)?;
+
+    for (frame, transition) in summary.transitions.iter() {
+        println!("frame {}: {:?}", frame, transition);
+    }
+    if let Some(frame) = summary.exited {
+        println!("frame {}: game exited", frame);
+    }
+    println!("frames: {}", summary.frames);
+    println!("deaths: {}", summary.deaths);
+    match summary.first_desync {
+        Some(desync) => println!("first desync: frame {}", desync),
+        None => println!("first desync: none"),
+    }
+    match &summary.final_level {
+        Some(path) => println!("final level: {}", path.display()),
+        None => println!("final level: none"),
+    }
+
+    if let Some(screenshot) = &args.screenshot {
+        let mut context =
+            RenderContext::new(RENDER_WIDTH, RENDER_HEIGHT, summary.frames, summary.seed)?;
+        stage_manager.draw(&mut context, &font);
+        let renderer = image_manager.renderer_mut();
+        renderer.render(&context)?;
+        renderer
+            .frame()
+            .save(screenshot)
+            .map_err(|e| anyhow!("unable to write screenshot to {}: {}", screenshot, e))?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    match run_with_assets(args) {
+        Ok(_) => {}
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use purpy::testing::{test_archive, test_map, test_recording, InputSnapshot, TestImages};
+
+    // The player spawns between a warp to the end level on the left, and spikes on the right.
+    const START_ROWS: &[&str] = &[
+        "................",
+        "................",
+        "................",
+        "########^^######",
+    ];
+
+    const START_OBJECTS: &str = r#"
+<object id="1" x="32" y="0">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+<object id="2" x="0" y="0" width="8" height="24">
+ <properties><property name="warp" value="assets/levels/end.tmx"/></properties>
+</object>
+"#;
+
+    const END_ROWS: &[&str] = &["....", "....", "....", "####"];
+
+    const SPAWN_OBJECTS: &str = r#"
+<object id="1" x="0" y="0">
+ <properties><property name="spawn" type="bool" value="true"/></properties>
+</object>
+"#;
+
+    // The death screen just has a button to try again.
+    const DEAD_OBJECTS: &str = r#"
+<object id="1" x="8" y="8" width="32" height="16">
+ <properties>
+  <property name="uibutton" type="bool" value="true"/>
+  <property name="action" value="reload"/>
+ </properties>
+</object>
+"#;
+
+    fn args(frames: Option<u64>) -> Args {
+        Args {
+            playback: "test.rpl".to_owned(),
+            frames,
+            assets: None,
+            screenshot: None,
+        }
+    }
+
+    #[test]
+    fn plays_back_recordings() {
+        let right = InputSnapshot {
+            player_right_down: true,
+            ..Default::default()
+        };
+        let left = InputSnapshot {
+            player_left_down: true,
+            ..Default::default()
+        };
+        let ok = InputSnapshot {
+            ok_down: true,
+            ..Default::default()
+        };
+        // Walk into the spikes, try again from the death screen, and then walk into the warp.
+        let mut inputs = vec![right; 60];
+        inputs.push(ok);
+        inputs.push(InputSnapshot::default());
+        inputs.extend([left; 60]);
+        let recording = test_recording("level:assets/levels/test.tmx", &inputs);
+
+        let start = test_map(START_ROWS, START_OBJECTS);
+        let end = test_map(END_ROWS, SPAWN_OBJECTS);
+        let dead = test_map(&["......", "......", "......"], DEAD_OBJECTS);
+        let files = test_archive(&[
+            ("test.rpl", &recording),
+            ("assets/levels/test.tmx", start.as_bytes()),
+            ("assets/levels/end.tmx", end.as_bytes()),
+            ("assets/menus/dead.tmx", dead.as_bytes()),
+        ]);
+
+        let (summary, _) = run(&args(None), &files, &mut TestImages).unwrap();
+        let transitions: Vec<_> = summary
+            .transitions
+            .iter()
+            .map(|(_, transition)| transition)
+            .collect();
+        assert!(matches!(
+            transitions[..],
+            [
+                SceneResult::PushKillScreen { .. },
+                SceneResult::RespawnLevel { .. },
+                SceneResult::SwitchToLevel { .. },
+            ]
+        ));
+        assert_eq!(summary.transitions[1].0, 61);
+        assert_eq!(summary.exited, None);
+        assert_eq!(summary.frames, 122);
+        assert_eq!(summary.deaths, 1);
+        assert_eq!(summary.first_desync, None);
+        assert_eq!(
+            summary.final_level,
+            Some(Path::new("assets/levels/end.tmx").to_owned())
+        );
+    }
+
+    #[test]
+    fn text_recordings_need_a_frame_count() {
+        let files = test_archive(&[("test.rpl", b"0,0\n10,8\n")]);
+        assert!(run(&args(None), &files, &mut TestImages).is_err());
+    }
+}
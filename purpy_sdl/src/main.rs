@@ -9,7 +9,7 @@ use sdl2::render::Canvas;
 use sdl2::video::Window;
 
 use purpy::{
-    FileManager, ImageManager, InputManager, RecordOption, RenderContext, SdlRenderer,
+    FileManager, FileStorage, ImageManager, InputManager, RecordOption, RenderContext, SdlRenderer,
     SoundManager, StageManager, FRAME_RATE, RENDER_HEIGHT, RENDER_WIDTH,
 };
 
@@ -30,6 +30,35 @@ struct Args {
 
     #[arg(long)]
     pub speed_test: bool,
+
+    // How many KiB of recent states each level can keep for rewinding. Recordings
+    // remember it, so playback always uses the budget the recording was made with.
+    #[arg(long)]
+    pub rewind_budget: Option<usize>,
+
+    // Starts the game in this level instead of the menu, e.g. to record a ghost.
+    #[arg(long)]
+    pub level: Option<String>,
+
+    // A recording that started in a level, to race against as a ghost in that level.
+    #[arg(long)]
+    pub ghost: Option<String>,
+
+    // Where to keep personal best times for each level. Not used during playback.
+    #[arg(long, default_value = "best_times.txt")]
+    pub best_times: String,
+
+    // Where to keep which levels have been completed. Not used during playback.
+    #[arg(long, default_value = "progress.txt")]
+    pub progress: String,
+
+    // A file of key and gamepad bindings, read like the other assets. See the Readme.
+    #[arg(long)]
+    pub bindings: Option<String>,
+
+    // Where to keep bindings changed in the controls menu. Not used during playback.
+    #[arg(long, default_value = "controls.txt")]
+    pub controls: String,
 }
 
 impl Args {
@@ -91,8 +120,33 @@ fn sdl_main(args: Args) -> Result<()> {
         args.record_option()?,
         &file_manager,
     )?;
+    if let Some(level) = &args.level {
+        input_manager.set_start_scene(&format!("level:{}", level));
+    }
+    if let Some(bindings) = &args.bindings {
+        input_manager.load_bindings(Path::new(bindings), &file_manager)?;
+    }
+    if let Some(budget) = args.rewind_budget {
+        input_manager.set_rewind_budget(budget * 1024);
+    }
 
-    let mut stage_manager = StageManager::new(&file_manager, &mut image_manager)?;
+    let mut stage_manager = StageManager::with_start_scene(
+        input_manager.start_scene(),
+        &file_manager,
+        &mut image_manager,
+    )?;
+    stage_manager.set_rewind_budget(input_manager.rewind_budget());
+    if let Some(ghost) = &args.ghost {
+        stage_manager.load_ghost(Path::new(ghost), &file_manager, &mut image_manager)?;
+    }
+    if args.playback.is_none() {
+        stage_manager.load_best_times(Path::new(&args.best_times))?;
+        let progress = FileStorage::new(Path::new(&args.progress));
+        stage_manager.load_progress(Box::new(progress));
+        let controls = FileStorage::new(Path::new(&args.controls));
+        input_manager.load_saved_bindings(Box::new(controls));
+    }
+    stage_manager.set_bindings(input_manager.bindings());
     let mut sound_manager = SoundManager::with_sdl(&audio_subsystem)?;
     let mut event_pump = sdl_context.event_pump().unwrap();
 
@@ -115,7 +169,7 @@ fn sdl_main(args: Args) -> Result<()> {
         let input_snapshot = input_manager.update(frame);
 
         let (width, height) = canvas.logical_size();
-        let mut context = RenderContext::new(width, height, frame)?;
+        let mut context = RenderContext::new(width, height, frame, input_manager.seed())?;
 
         if !stage_manager.update(
             &context,
@@ -126,10 +180,14 @@ fn sdl_main(args: Args) -> Result<()> {
         )? {
             break 'running;
         }
+        if let Some(bindings) = stage_manager.take_new_bindings() {
+            input_manager.save_bindings(bindings);
+        }
+        input_manager.check_state(frame, stage_manager.state_hash());
 
         context.clear();
         stage_manager.draw(&mut context, &font);
-        image_manager.renderer().render(&mut canvas, &context)?;
+        image_manager.renderer_mut().render(&mut canvas, &context)?;
         canvas.present();
 
         frame += 1;
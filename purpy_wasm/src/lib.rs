@@ -1,7 +1,9 @@
+use localstorage::LocalStorage;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use websoundplayer::WebSoundPlayer;
 
+mod localstorage;
 mod websoundplayer;
 
 use std::path::Path;
@@ -43,14 +45,23 @@ impl<'window> GameState<'window> {
         )?;
         let font = images.load_font(&file_manager)?;
 
-        let inputs = InputManager::with_options(
+        let mut inputs = InputManager::with_options(
             CANVAS_WIDTH as i32,
             CANVAS_HEIGHT as i32,
             true,
             RecordOption::None,
             &file_manager,
         )?;
-        let stage_manager = StageManager::new(&file_manager, &mut images)?;
+        let mut stage_manager = StageManager::new(&file_manager, &mut images)?;
+        match LocalStorage::new("purpy_progress") {
+            Ok(progress) => stage_manager.load_progress(Box::new(progress)),
+            Err(e) => error!("progress won't be saved: {:?}", e),
+        }
+        match LocalStorage::new("purpy_controls") {
+            Ok(controls) => inputs.load_saved_bindings(Box::new(controls)),
+            Err(e) => error!("controls won't be saved: {:?}", e),
+        }
+        stage_manager.set_bindings(inputs.bindings());
         let sounds = WebSoundPlayer::new(&file_manager)?;
         let sounds = SoundManager::with_internal(Box::new(sounds));
 
@@ -70,7 +81,7 @@ impl<'window> GameState<'window> {
     fn run_one_frame(&mut self) -> Result<()> {
         let width = RENDER_WIDTH;
         let height = RENDER_HEIGHT;
-        let mut context = RenderContext::new(width, height, self.frame)?;
+        let mut context = RenderContext::new(width, height, self.frame, self.inputs.seed())?;
 
         let inputs = self.inputs.update(self.frame);
         let _ = self.stage_manager.update(
@@ -80,8 +91,12 @@ impl<'window> GameState<'window> {
             &mut self.images,
             &mut self.sounds,
         )?;
+        if let Some(bindings) = self.stage_manager.take_new_bindings() {
+            self.inputs.save_bindings(bindings);
+        }
 
         self.stage_manager.draw(&mut context, &self.font);
+        self.inputs.draw_touch_controls(&mut context, &self.font);
 
         match self.images.renderer_mut().render(&context) {
             Ok(_) => {}
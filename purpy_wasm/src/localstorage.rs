@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Result};
+use purpy::Storage;
+
+// Keeps text in the browser's localStorage under a key, since there's no filesystem.
+pub struct LocalStorage {
+    storage: web_sys::Storage,
+    key: String,
+}
+
+impl LocalStorage {
+    pub fn new(key: &str) -> Result<Self> {
+        let storage = web_sys::window()
+            .ok_or_else(|| anyhow!("no window"))?
+            .local_storage()
+            .map_err(|e| anyhow!("unable to get local storage: {:?}", e))?
+            .ok_or_else(|| anyhow!("local storage isn't available"))?;
+        let key = key.to_owned();
+        Ok(Self { storage, key })
+    }
+}
+
+impl Storage for LocalStorage {
+    fn load(&self) -> Result<Option<String>> {
+        self.storage
+            .get_item(&self.key)
+            .map_err(|e| anyhow!("unable to read {}: {:?}", self.key, e))
+    }
+
+    fn save(&mut self, data: &str) -> Result<()> {
+        self.storage
+            .set_item(&self.key, data)
+            .map_err(|e| anyhow!("unable to write {}: {:?}", self.key, e))
+    }
+}
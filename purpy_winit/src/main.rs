@@ -10,8 +10,8 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Window, WindowBuilder};
 
 use purpy::{
-    FileManager, Font, ImageManager, InputManager, RecordOption, RenderContext, SoundManager,
-    StageManager, WgpuRenderer, RENDER_HEIGHT, RENDER_WIDTH,
+    FileManager, FileStorage, Font, ImageManager, InputManager, RecordOption, RenderContext,
+    SoundManager, StageManager, WgpuRenderer, RENDER_HEIGHT, RENDER_WIDTH,
 };
 
 pub const WINDOW_WIDTH: u32 = 1600;
@@ -32,6 +32,35 @@ pub struct Args {
 
     #[arg(long)]
     pub speed_test: bool,
+
+    // How many KiB of recent states each level can keep for rewinding. Recordings
+    // remember it, so playback always uses the budget the recording was made with.
+    #[arg(long)]
+    pub rewind_budget: Option<usize>,
+
+    // Starts the game in this level instead of the menu, e.g. to record a ghost.
+    #[arg(long)]
+    pub level: Option<String>,
+
+    // A recording that started in a level, to race against as a ghost in that level.
+    #[arg(long)]
+    pub ghost: Option<String>,
+
+    // Where to keep personal best times for each level. Not used during playback.
+    #[arg(long, default_value = "best_times.txt")]
+    pub best_times: String,
+
+    // Where to keep which levels have been completed. Not used during playback.
+    #[arg(long, default_value = "progress.txt")]
+    pub progress: String,
+
+    // A file of key and gamepad bindings, read like the other assets. See the Readme.
+    #[arg(long)]
+    pub bindings: Option<String>,
+
+    // Where to keep bindings changed in the controls menu. Not used during playback.
+    #[arg(long, default_value = "controls.txt")]
+    pub controls: String,
 }
 
 impl Args {
@@ -75,15 +104,37 @@ impl<'window> GameState<'window> {
         )?;
         let font = images.load_font(&file_manager)?;
 
-        let inputs = InputManager::with_options(
+        let mut inputs = InputManager::with_options(
             WINDOW_WIDTH as i32,
             WINDOW_HEIGHT as i32,
             true,
             args.record_option()?,
             &file_manager,
         )?;
+        if let Some(level) = &args.level {
+            inputs.set_start_scene(&format!("level:{}", level));
+        }
+        if let Some(bindings) = &args.bindings {
+            inputs.load_bindings(Path::new(bindings), &file_manager)?;
+        }
+        if let Some(budget) = args.rewind_budget {
+            inputs.set_rewind_budget(budget * 1024);
+        }
 
-        let stage_manager = StageManager::new(&file_manager, &mut images)?;
+        let mut stage_manager =
+            StageManager::with_start_scene(inputs.start_scene(), &file_manager, &mut images)?;
+        stage_manager.set_rewind_budget(inputs.rewind_budget());
+        if let Some(ghost) = &args.ghost {
+            stage_manager.load_ghost(Path::new(ghost), &file_manager, &mut images)?;
+        }
+        if args.playback.is_none() {
+            stage_manager.load_best_times(Path::new(&args.best_times))?;
+            let progress = FileStorage::new(Path::new(&args.progress));
+            stage_manager.load_progress(Box::new(progress));
+            let controls = FileStorage::new(Path::new(&args.controls));
+            inputs.load_saved_bindings(Box::new(controls));
+        }
+        stage_manager.set_bindings(inputs.bindings());
         let sounds = SoundManager::noop_manager();
 
         let frame = 0;
@@ -110,7 +161,7 @@ impl<'window> GameState<'window> {
 
         let width = RENDER_WIDTH;
         let height = RENDER_HEIGHT;
-        let mut context = RenderContext::new(width, height, self.frame)?;
+        let mut context = RenderContext::new(width, height, self.frame, self.inputs.seed())?;
 
         let inputs = self.inputs.update(self.frame);
         if !self.stage_manager.update(
@@ -128,8 +179,14 @@ impl<'window> GameState<'window> {
             }
             return Ok(false);
         }
+        if let Some(bindings) = self.stage_manager.take_new_bindings() {
+            self.inputs.save_bindings(bindings);
+        }
+        self.inputs
+            .check_state(self.frame, self.stage_manager.state_hash());
 
         self.stage_manager.draw(&mut context, &self.font);
+        self.inputs.draw_touch_controls(&mut context, &self.font);
 
         match self.images.renderer_mut().render(&context) {
             Ok(_) => {}